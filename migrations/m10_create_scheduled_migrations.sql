@@ -0,0 +1,27 @@
+-- Tenant migrations scheduled to run within a maintenance window
+CREATE TABLE IF NOT EXISTS "scheduled_migrations"
+(
+    "id"                    UUID        NOT NULL PRIMARY KEY,
+    -- Only migrate tenants within this environment
+    "env"                   VARCHAR     NULL,
+    -- Only migrate this specific tenant
+    "tenant_id"             UUID        NULL,
+    "target_migration_name" VARCHAR     NULL,
+    "skip_failed"           BOOLEAN     NOT NULL DEFAULT FALSE,
+    -- Maintenance window the migration must run within
+    "window_start"          TIMESTAMPTZ NOT NULL,
+    "window_end"            TIMESTAMPTZ NOT NULL,
+    -- Status of the schedule (scheduled, running, completed, failed, missed, cancelled)
+    "status"                TEXT        NOT NULL,
+    -- Job running the migration once started
+    "job_id"                UUID        NULL REFERENCES "jobs" ("id") ON DELETE SET NULL,
+    "error"                 TEXT        NULL,
+    -- Manager instance running the schedule once claimed
+    "instance_id"           UUID        NULL,
+    -- Identity of who scheduled the migration
+    "created_by"            VARCHAR     NOT NULL,
+    "created_at"            TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "finished_at"           TIMESTAMPTZ NULL
+);
+
+CREATE INDEX IF NOT EXISTS "scheduled_migrations_due_idx" ON "scheduled_migrations" ("status", "window_start");
@@ -0,0 +1,14 @@
+-- Locks held while migrating a tenant or the fleet, a lock is held by its
+-- row. Holders extend the expiry while the lock is held so the lock is
+-- freed once a holder exits uncleanly
+CREATE TABLE IF NOT EXISTS "migration_locks"
+(
+    "key"         VARCHAR     NOT NULL PRIMARY KEY,
+    -- Identity of who holds the lock
+    "holder"      VARCHAR     NOT NULL,
+    -- Unique ID of the acquisition, holders only renew or release the
+    -- lock while it is still their acquisition
+    "lock_id"     UUID        NOT NULL,
+    "acquired_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "expires_at"  TIMESTAMPTZ NOT NULL
+);
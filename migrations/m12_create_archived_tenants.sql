@@ -0,0 +1,30 @@
+-- Tenants that have been archived, archived tenants are disabled and
+-- purged once their retention period ends unless restored
+CREATE TABLE IF NOT EXISTS "archived_tenants"
+(
+    "env"         VARCHAR     NOT NULL,
+    "tenant_id"   UUID        NOT NULL,
+    -- Name of the tenant at the time it was archived
+    "name"        VARCHAR     NOT NULL,
+    "reason"      TEXT        NULL,
+    -- Status of the archive (archived, purging, purge_failed)
+    "status"      TEXT        NOT NULL,
+    -- Tenant is purged after this time
+    "purge_after" TIMESTAMPTZ NOT NULL,
+    -- Job purging the tenant once the retention period ends
+    "job_id"      UUID        NULL REFERENCES "jobs" ("id") ON DELETE SET NULL,
+    "error"       TEXT        NULL,
+    -- Time purging the tenant last failed, failed purges are retried by
+    -- the next purge check rather than the check that failed
+    "failed_at"   TIMESTAMPTZ NULL,
+    -- Number of times purging the tenant has failed
+    "purge_attempts" INTEGER  NOT NULL DEFAULT 0,
+    -- Manager instance purging the tenant once claimed
+    "instance_id" UUID        NULL,
+    -- Identity of who archived the tenant
+    "archived_by" VARCHAR     NOT NULL,
+    "archived_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("env", "tenant_id")
+);
+
+CREATE INDEX IF NOT EXISTS "archived_tenants_purge_idx" ON "archived_tenants" ("status", "purge_after");
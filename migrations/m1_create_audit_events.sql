@@ -0,0 +1,16 @@
+-- Audit log of actions performed through the manager
+CREATE TABLE IF NOT EXISTS "audit_events"
+(
+    "id"         UUID        NOT NULL PRIMARY KEY,
+    -- Type of action that was performed
+    "action"     VARCHAR     NOT NULL,
+    -- Environment and tenant the action was performed against (if any)
+    "env"        VARCHAR     NULL,
+    "tenant_id"  UUID        NULL,
+    -- Additional details about the action
+    "details"    JSONB       NOT NULL DEFAULT '{}'::jsonb,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS "audit_events_tenant_idx" ON "audit_events" ("env", "tenant_id");
+CREATE INDEX IF NOT EXISTS "audit_events_created_at_idx" ON "audit_events" ("created_at");
@@ -0,0 +1,34 @@
+-- Running manager instances, each instance periodically updates its
+-- heartbeat while running
+CREATE TABLE IF NOT EXISTS "manager_instances"
+(
+    "id"           UUID        NOT NULL PRIMARY KEY,
+    "started_at"   TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "heartbeat_at" TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+-- Background jobs started through the manager
+CREATE TABLE IF NOT EXISTS "jobs"
+(
+    "id"          UUID        NOT NULL PRIMARY KEY,
+    -- Type of job
+    "kind"        TEXT        NOT NULL,
+    -- Current status of the job (pending, running, succeeded, failed)
+    "status"      TEXT        NOT NULL,
+    -- Environment and tenant the job is running against (if any)
+    "env"         VARCHAR     NULL,
+    "tenant_id"   UUID        NULL,
+    -- Output produced by the job on success
+    "output"      JSONB       NULL,
+    -- Error message when the job failed
+    "error"       TEXT        NULL,
+    -- Manager instance running the job, jobs owned by an instance whose
+    -- heartbeat has expired can no longer complete
+    "instance_id" UUID        NULL,
+    "created_at"  TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    "started_at"  TIMESTAMPTZ NULL,
+    "finished_at" TIMESTAMPTZ NULL
+);
+
+CREATE INDEX IF NOT EXISTS "jobs_tenant_idx" ON "jobs" ("env", "tenant_id");
+CREATE INDEX IF NOT EXISTS "jobs_created_at_idx" ON "jobs" ("created_at");
@@ -0,0 +1,23 @@
+-- Catalogue of tenant database backups
+CREATE TABLE IF NOT EXISTS "tenant_backups"
+(
+    "id"          UUID        NOT NULL PRIMARY KEY,
+    "env"         VARCHAR     NOT NULL,
+    "tenant_id"   UUID        NOT NULL,
+    -- Name of the database that was backed up
+    "db_name"     VARCHAR     NOT NULL,
+    -- Location of the backup archive
+    "bucket"      VARCHAR     NOT NULL,
+    "key"         VARCHAR     NOT NULL,
+    -- Size of the archive in bytes
+    "size"        BIGINT      NOT NULL,
+    -- SHA256 checksum of the archive
+    "sha256"      VARCHAR     NOT NULL,
+    -- Reason the backup was created (manual, pre_migration)
+    "reason"      TEXT        NOT NULL,
+    -- Job that created the backup (if any)
+    "job_id"      UUID        NULL REFERENCES "jobs" ("id") ON DELETE SET NULL,
+    "created_at"  TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS "tenant_backups_tenant_idx" ON "tenant_backups" ("env", "tenant_id");
@@ -0,0 +1,15 @@
+-- History of database credential rotations
+CREATE TABLE IF NOT EXISTS "credential_rotations"
+(
+    "id"          UUID        NOT NULL PRIMARY KEY,
+    -- Environment and tenant the credentials belong to, null for the root credentials
+    "env"         VARCHAR     NULL,
+    "tenant_id"   UUID        NULL,
+    -- Secret the credentials are stored within
+    "secret_name" VARCHAR     NOT NULL,
+    -- Database role that was updated
+    "role_name"   VARCHAR     NOT NULL,
+    "rotated_at"  TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS "credential_rotations_secret_idx" ON "credential_rotations" ("secret_name", "rotated_at");
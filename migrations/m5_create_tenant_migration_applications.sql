@@ -0,0 +1,14 @@
+-- Tenant migrations applied through the manager
+CREATE TABLE IF NOT EXISTS "tenant_migration_applications"
+(
+    "id"         UUID        NOT NULL PRIMARY KEY,
+    "env"        VARCHAR     NOT NULL,
+    "tenant_id"  UUID        NOT NULL,
+    -- Name of the migration that was applied
+    "name"       VARCHAR     NOT NULL,
+    -- Identity of who applied the migration
+    "applied_by" VARCHAR     NOT NULL,
+    "applied_at" TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS "tenant_migration_applications_tenant_idx" ON "tenant_migration_applications" ("env", "tenant_id");
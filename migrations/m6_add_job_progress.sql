@@ -0,0 +1,16 @@
+-- Progress reporting and cancellation for jobs
+ALTER TABLE "jobs" ADD COLUMN IF NOT EXISTS "progress" JSONB NULL;
+ALTER TABLE "jobs" ADD COLUMN IF NOT EXISTS "cancel_requested" BOOLEAN NOT NULL DEFAULT FALSE;
+
+-- Log messages produced by jobs
+CREATE TABLE IF NOT EXISTS "job_logs"
+(
+    "id"         BIGSERIAL   NOT NULL PRIMARY KEY,
+    "job_id"     UUID        NOT NULL REFERENCES "jobs" ("id") ON DELETE CASCADE,
+    -- Level of the message (info, warn, error)
+    "level"      TEXT        NOT NULL,
+    "message"    TEXT        NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW()
+);
+
+CREATE INDEX IF NOT EXISTS "job_logs_job_idx" ON "job_logs" ("job_id", "id");
@@ -0,0 +1,2 @@
+-- Allow running jobs to be paused and resumed
+ALTER TABLE "jobs" ADD COLUMN IF NOT EXISTS "paused" BOOLEAN NOT NULL DEFAULT FALSE;
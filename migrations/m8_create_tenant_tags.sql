@@ -0,0 +1,11 @@
+-- Tags assigned to tenants for selecting groups of tenants
+CREATE TABLE IF NOT EXISTS "tenant_tags"
+(
+    "env"        VARCHAR     NOT NULL,
+    "tenant_id"  UUID        NOT NULL,
+    "tag"        VARCHAR     NOT NULL,
+    "created_at" TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+    PRIMARY KEY ("env", "tenant_id", "tag")
+);
+
+CREATE INDEX IF NOT EXISTS "tenant_tags_tag_idx" ON "tenant_tags" ("tag");
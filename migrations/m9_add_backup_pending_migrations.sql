@@ -0,0 +1,2 @@
+-- Tenant migrations that had not been applied when the backup was taken
+ALTER TABLE "tenant_backups" ADD COLUMN IF NOT EXISTS "pending_migrations" VARCHAR[] NULL;
@@ -0,0 +1,238 @@
+//! Purges archived tenants once their retention period ends
+
+use crate::{
+    database::DatabaseProvider,
+    jobs::Jobs,
+    locks::{LockScope, MigrationLock},
+    metadata::{
+        MetadataDatabase,
+        archives::ArchivedTenant,
+        audit::{AuditAction, AuditEvent, CreateAuditEvent},
+        jobs::{CreateJob, JobKind, JobLogLevel},
+        tags::set_tenant_tags,
+    },
+    teardown::{ResourceStatus, TenantDeletionReport, delete_tenant},
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use docbox_search::SearchIndexFactory;
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+/// Interval between checks for archived tenants past their retention period
+const PURGE_POLL_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Identity recorded as the holder of locks taken while purging
+const PURGE_ACTOR: &str = "archive-purge";
+
+/// Services required to purge tenants
+pub struct Purger {
+    pub db_provider: Arc<DatabaseProvider>,
+    pub storage_factory: Arc<StorageLayerFactory>,
+    pub search_factory: Arc<SearchIndexFactory>,
+    pub secrets: Arc<SecretManager>,
+    pub metadata: Arc<MetadataDatabase>,
+    pub jobs: Arc<Jobs>,
+}
+
+/// Spawn the background task purging expired archived tenants
+pub fn spawn_purger(purger: Purger) {
+    let purger = Arc::new(purger);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(PURGE_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(error) = purge_expired(&purger).await {
+                tracing::error!(?error, "failed to purge archived tenants");
+            }
+        }
+    });
+}
+
+async fn purge_expired(purger: &Arc<Purger>) -> anyhow::Result<()> {
+    // Tenants that fail to start purging are retried by the next check,
+    // taken from the database clock as failures are recorded using it
+    let started_at: DateTime<Utc> = sqlx::query_scalar("SELECT NOW()")
+        .fetch_one(&purger.metadata.pool)
+        .await?;
+
+    while let Some(archive) =
+        ArchivedTenant::claim_expired(&purger.metadata.pool, purger.jobs.instance_id(), started_at)
+            .await?
+    {
+        let (env, tenant_id) = (archive.env.clone(), archive.tenant_id);
+
+        let (name, archived_by) = (archive.name.clone(), archive.archived_by.clone());
+
+        if let Err(error) = start_purge(purger, archive).await {
+            tracing::error!(?error, %env, %tenant_id, "failed to start tenant purge");
+            record_purge_failure(
+                purger,
+                &env,
+                tenant_id,
+                &name,
+                &archived_by,
+                &format!("{error:#}"),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Start the job purging a claimed archived tenant
+async fn start_purge(purger: &Arc<Purger>, archive: ArchivedTenant) -> anyhow::Result<()> {
+    tracing::info!(?archive, "purging archived tenant");
+
+    let tenant = docbox_management::tenant::get_tenant::get_tenant(
+        purger.db_provider.as_ref(),
+        &archive.env,
+        archive.tenant_id,
+    )
+    .await
+    .map_err(anyhow::Error::new)?;
+
+    let Some(tenant) = tenant else {
+        // Tenant was removed outside of the manager, nothing left to purge
+        ArchivedTenant::delete(&purger.metadata.pool, &archive.env, archive.tenant_id).await?;
+        return Ok(());
+    };
+
+    // Held until the job completes
+    let lock = MigrationLock::acquire(
+        &purger.metadata.pool,
+        LockScope::tenant(&tenant.env, tenant.id),
+        PURGE_ACTOR,
+    )
+    .await?;
+
+    let (env, tenant_id) = (tenant.env.clone(), tenant.id);
+    let task_purger = purger.clone();
+
+    let job = purger
+        .jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::TenantPurge,
+                env: Some(env.clone()),
+                tenant_id: Some(tenant_id),
+            },
+            move |ctx| async move {
+                let purger = task_purger;
+                let report = delete_tenant(
+                    &purger.db_provider,
+                    &purger.storage_factory,
+                    &purger.search_factory,
+                    &purger.secrets,
+                    &tenant,
+                    &[],
+                )
+                .await;
+
+                lock.release().await;
+
+                let result = finish_purge(&purger, &archive, &report).await;
+                if let Err(error) = &result {
+                    ctx.log(JobLogLevel::Error, format!("{error:#}")).await;
+                }
+
+                result.map(|_| report)
+            },
+        )
+        .await?;
+
+    ArchivedTenant::set_job(&purger.metadata.pool, &env, tenant_id, job.id).await?;
+
+    Ok(())
+}
+
+/// Record the outcome of purging an archived tenant
+async fn finish_purge(
+    purger: &Purger,
+    archive: &ArchivedTenant,
+    report: &TenantDeletionReport,
+) -> anyhow::Result<()> {
+    let db = &purger.metadata.pool;
+
+    if let ResourceStatus::Failed { error } = &report.record {
+        record_purge_failure(
+            purger,
+            &archive.env,
+            archive.tenant_id,
+            &archive.name,
+            &archive.archived_by,
+            error,
+        )
+        .await?;
+        anyhow::bail!("failed to purge tenant: {error}");
+    }
+
+    set_tenant_tags(db, &archive.env, archive.tenant_id, &[])
+        .await
+        .context("failed to remove tenant tags")?;
+    ArchivedTenant::delete(db, &archive.env, archive.tenant_id).await?;
+
+    AuditEvent::create(
+        db,
+        CreateAuditEvent {
+            action: AuditAction::TenantPurge,
+            env: Some(archive.env.clone()),
+            tenant_id: Some(archive.tenant_id),
+            details: json!({
+                "actor": PURGE_ACTOR,
+                "name": archive.name,
+                "archived_by": archive.archived_by,
+                "archived_at": archive.archived_at,
+                "report": report,
+            }),
+        },
+    )
+    .await
+    .context("failed to record tenant purge in audit log")?;
+
+    Ok(())
+}
+
+/// Record a failed attempt at purging an archived tenant, each failure is
+/// recorded in the audit log along with the number of failed attempts so
+/// tenants that repeatedly fail to purge are visible
+async fn record_purge_failure(
+    purger: &Purger,
+    env: &str,
+    tenant_id: Uuid,
+    name: &str,
+    archived_by: &str,
+    error: &str,
+) -> anyhow::Result<()> {
+    let db = &purger.metadata.pool;
+
+    let attempts = ArchivedTenant::set_purge_failed(db, env, tenant_id, error).await?;
+    tracing::warn!(%env, %tenant_id, attempts, "purging archived tenant failed");
+
+    AuditEvent::create(
+        db,
+        CreateAuditEvent {
+            action: AuditAction::TenantPurgeFailed,
+            env: Some(env.to_string()),
+            tenant_id: Some(tenant_id),
+            details: json!({
+                "actor": PURGE_ACTOR,
+                "name": name,
+                "archived_by": archived_by,
+                "attempts": attempts,
+                "error": error,
+            }),
+        },
+    )
+    .await
+    .context("failed to record tenant purge failure in audit log")?;
+
+    Ok(())
+}
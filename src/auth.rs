@@ -1,3 +1,4 @@
+use crate::config::TrustedProxy;
 use axum::{
     extract::{FromRequestParts, Request},
     http::{self, StatusCode},
@@ -5,30 +6,57 @@ use axum::{
     response::Response,
 };
 use http::request::Parts;
-use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
 use tower_sessions::Session;
 
 const AUTH_KEY: &str = "counter";
 
-#[derive(Default, Deserialize, Serialize)]
-pub struct Authenticated;
+/// Header set by an authenticating proxy containing the user identity
+const FORWARDED_USER_HEADER: &str = "x-forwarded-user";
 
-impl<S> FromRequestParts<S> for Authenticated
+/// Identity of the caller performing an action, used to attribute
+/// actions in the audit log and history.
+///
+/// Uses the user from the `X-Forwarded-User` header when the manager is
+/// configured to trust an authenticating proxy (See [TrustedProxy]),
+/// otherwise a hash of the session ID
+/// (The session ID itself is a credential so it must not be stored)
+#[derive(Debug, Clone)]
+pub struct Actor(pub String);
+
+impl<S> FromRequestParts<S> for Actor
 where
     S: Send + Sync,
 {
     type Rejection = (http::StatusCode, &'static str);
 
     async fn from_request_parts(req: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
-        let session = Session::from_request_parts(req, state).await?;
-        if !is_session_authenticated(&session).await.map_err(|error| {
-            tracing::error!(?error, "failed to get session");
-            (StatusCode::INTERNAL_SERVER_ERROR, "failed to get session")
-        })? {
-            return Err((StatusCode::UNAUTHORIZED, "Not authenticated"));
+        let trusted_proxy = req
+            .extensions
+            .get::<Arc<TrustedProxy>>()
+            .is_some_and(|trusted_proxy| trusted_proxy.0);
+
+        let forwarded_user = req
+            .headers
+            .get(FORWARDED_USER_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .filter(|value| trusted_proxy && !value.is_empty());
+
+        if let Some(user) = forwarded_user {
+            return Ok(Actor(user.to_string()));
         }
 
-        Ok(Authenticated)
+        let session = Session::from_request_parts(req, state).await?;
+        let actor = match session.id() {
+            Some(id) => {
+                let hash = format!("{:x}", Sha256::digest(id.to_string().as_bytes()));
+                format!("session:{}", &hash[..12])
+            }
+            None => "unknown".to_string(),
+        };
+
+        Ok(Actor(actor))
     }
 }
 
@@ -0,0 +1,393 @@
+//! Backup archive format
+//!
+//! Backups are gzip compressed tar archives containing:
+//! - `manifest.json` describing the backup and its contents
+//! - `schema/pre-data.sql` statements to run before loading data
+//! - `schema/post-data.sql` statements to run after loading data
+//! - `data/{index}.copy` table data in the `COPY` text format, where
+//!   `index` is the position of the table within the manifest
+//!
+//! Archives are written to and unpacked into temporary files so the size
+//! of an archive is not limited by memory
+
+use super::schema::SchemaTable;
+use anyhow::Context;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use flate2::{Compression, read::GzDecoder, write::GzEncoder};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+};
+use tempfile::TempDir;
+use tokio::{sync::mpsc, task::JoinHandle};
+use uuid::Uuid;
+
+/// Current version of the archive format
+pub const FORMAT_VERSION: u32 = 1;
+
+pub const MANIFEST_PATH: &str = "manifest.json";
+pub const PRE_DATA_PATH: &str = "schema/pre-data.sql";
+pub const POST_DATA_PATH: &str = "schema/post-data.sql";
+
+/// Number of chunks buffered for a [SpoolSender] before sending waits for
+/// the chunks to be written
+const SPOOL_CHANNEL_CAPACITY: usize = 16;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub format_version: u32,
+    pub backup_id: Uuid,
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub tenant_name: String,
+    pub db_name: String,
+    pub created_at: DateTime<Utc>,
+    /// Version of the Postgres server the backup was taken from
+    pub server_version: String,
+    /// Tenant migrations that had not been applied at the time of the backup
+    pub pending_migrations: Vec<String>,
+    /// SHA256 checksum of the pre-data schema file
+    pub pre_data_sha256: String,
+    /// SHA256 checksum of the post-data schema file
+    pub post_data_sha256: String,
+    pub tables: Vec<BackupTable>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BackupTable {
+    #[serde(flatten)]
+    pub table: SchemaTable,
+    /// Path to the table data within the archive
+    pub file: String,
+    /// Number of rows in the table
+    pub rows: u64,
+    /// SHA256 checksum of the table data
+    pub sha256: String,
+}
+
+/// Path within the archive to store the data for the table at `index`
+/// within the manifest
+pub fn table_data_path(index: usize) -> String {
+    format!("data/{index}.copy")
+}
+
+/// Create a hex encoded SHA256 checksum of `data`
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Data written to a temporary file along with its size and checksum
+pub struct SpooledFile {
+    pub file: File,
+    pub size: u64,
+    pub sha256: String,
+}
+
+/// Writer spooling data to a temporary file, computing the size and
+/// checksum of the data as it is written
+pub struct SpoolWriter {
+    file: File,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl SpoolWriter {
+    pub fn new() -> io::Result<SpoolWriter> {
+        Ok(SpoolWriter {
+            file: tempfile::tempfile()?,
+            hasher: Sha256::new(),
+            size: 0,
+        })
+    }
+
+    /// Finish writing, the returned file is positioned at the start
+    pub fn finish(mut self) -> io::Result<SpooledFile> {
+        self.file.flush()?;
+        self.file.seek(SeekFrom::Start(0))?;
+
+        Ok(SpooledFile {
+            file: self.file,
+            size: self.size,
+            sha256: format!("{:x}", self.hasher.finalize()),
+        })
+    }
+}
+
+impl Write for SpoolWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Spools chunks sent from async code into a temporary file, the file is
+/// written on a blocking thread so file IO does not block the runtime
+pub struct SpoolSender {
+    sender: mpsc::Sender<Bytes>,
+    task: JoinHandle<io::Result<SpooledFile>>,
+}
+
+impl SpoolSender {
+    pub fn spawn() -> SpoolSender {
+        let (sender, mut receiver) = mpsc::channel::<Bytes>(SPOOL_CHANNEL_CAPACITY);
+        let task = tokio::task::spawn_blocking(move || {
+            let mut writer = SpoolWriter::new()?;
+            while let Some(chunk) = receiver.blocking_recv() {
+                writer.write_all(&chunk)?;
+            }
+            writer.finish()
+        });
+
+        SpoolSender { sender, task }
+    }
+
+    /// Send a chunk to be written, fails with the write error if writing
+    /// an earlier chunk failed
+    pub async fn send(&mut self, chunk: Bytes) -> anyhow::Result<()> {
+        if self.sender.send(chunk).await.is_ok() {
+            return Ok(());
+        }
+
+        // The writer only stops receiving once a write fails
+        match (&mut self.task).await? {
+            Ok(_) => anyhow::bail!("spool writer stopped unexpectedly"),
+            Err(error) => Err(anyhow::Error::new(error).context("failed to spool data")),
+        }
+    }
+
+    /// Wait for every chunk to be written and get the spooled file
+    pub async fn finish(self) -> anyhow::Result<SpooledFile> {
+        drop(self.sender);
+        let spooled = self.task.await?.context("failed to spool data")?;
+        Ok(spooled)
+    }
+}
+
+/// Writer for creating an archive in a temporary file
+pub struct ArchiveWriter {
+    builder: tar::Builder<GzEncoder<SpoolWriter>>,
+}
+
+impl ArchiveWriter {
+    pub fn new() -> io::Result<ArchiveWriter> {
+        let encoder = GzEncoder::new(SpoolWriter::new()?, Compression::default());
+        Ok(ArchiveWriter {
+            builder: tar::Builder::new(encoder),
+        })
+    }
+
+    pub fn add_file(&mut self, path: &str, data: &[u8]) -> io::Result<()> {
+        let mut header = file_header(data.len() as u64);
+        self.builder.append_data(&mut header, path, data)
+    }
+
+    /// Add the contents of a spooled file without reading it into memory
+    pub fn add_spooled(&mut self, path: &str, spooled: SpooledFile) -> io::Result<()> {
+        let mut header = file_header(spooled.size);
+        self.builder.append_data(&mut header, path, spooled.file)
+    }
+
+    /// Finish writing the archive and get the compressed archive file
+    pub fn finish(self) -> io::Result<SpooledFile> {
+        let encoder = self.builder.into_inner()?;
+        encoder.finish()?.finish()
+    }
+}
+
+fn file_header(size: u64) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    header.set_mtime(Utc::now().timestamp() as u64);
+    header.set_cksum();
+    header
+}
+
+/// Files unpacked from an archive into a temporary directory, removed
+/// once dropped
+pub struct ArchiveContents {
+    _dir: TempDir,
+    files: HashMap<String, PathBuf>,
+}
+
+impl ArchiveContents {
+    /// Unpack all the files from the compressed archive read from `reader`
+    pub fn unpack(reader: impl Read) -> io::Result<ArchiveContents> {
+        let dir = tempfile::tempdir()?;
+        let mut archive = tar::Archive::new(GzDecoder::new(reader));
+        let mut files = HashMap::new();
+
+        for (index, entry) in archive.entries()?.enumerate() {
+            let mut entry = entry?;
+            let path = entry.path()?.to_string_lossy().to_string();
+
+            // Files are stored by their position so paths from the archive
+            // are never used on the file system
+            let file_path = dir.path().join(index.to_string());
+            io::copy(&mut entry, &mut File::create(&file_path)?)?;
+            files.insert(path, file_path);
+        }
+
+        Ok(ArchiveContents { _dir: dir, files })
+    }
+
+    /// Take the file at `path` out of the archive, the file is read into
+    /// memory so this is only suitable for small files
+    pub fn take(&mut self, path: &str) -> anyhow::Result<Vec<u8>> {
+        let mut file = self.open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    /// Take the file at `path` out of the archive ensuring it matches
+    /// the expected `sha256` checksum
+    pub fn take_verified(&mut self, path: &str, sha256: &str) -> anyhow::Result<Vec<u8>> {
+        let data = self.take(path)?;
+        if sha256_hex(&data) != sha256 {
+            anyhow::bail!("checksum mismatch for {path} in archive");
+        }
+        Ok(data)
+    }
+
+    /// Ensure the file at `path` matches the expected `sha256` checksum
+    /// without reading it into memory or removing it from the archive
+    pub fn verify(&self, path: &str, sha256: &str) -> anyhow::Result<()> {
+        let file_path = self
+            .files
+            .get(path)
+            .with_context(|| format!("archive is missing {path}"))?;
+        let mut hasher = Sha256::new();
+        io::copy(&mut File::open(file_path)?, &mut hasher)?;
+
+        if format!("{:x}", hasher.finalize()) != sha256 {
+            anyhow::bail!("checksum mismatch for {path} in archive");
+        }
+
+        Ok(())
+    }
+
+    /// Open the file at `path` ensuring it matches the expected `sha256`
+    /// checksum without reading it into memory
+    pub fn open_verified(&mut self, path: &str, sha256: &str) -> anyhow::Result<File> {
+        self.verify(path, sha256)?;
+        self.open(path)
+    }
+
+    /// Open the file at `path` removing it from the archive, the file is
+    /// deleted from disk once the returned handle is closed
+    pub fn open(&mut self, path: &str) -> anyhow::Result<File> {
+        let file_path = self
+            .files
+            .remove(path)
+            .with_context(|| format!("archive is missing {path}"))?;
+        let file = File::open(&file_path)?;
+        std::fs::remove_file(&file_path)?;
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_archive(files: &[(&str, &[u8])]) -> SpooledFile {
+        let mut writer = ArchiveWriter::new().unwrap();
+        for (path, data) in files {
+            writer.add_file(path, data).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_archive_round_trip() {
+        let mut spooled = SpoolWriter::new().unwrap();
+        spooled.write_all(b"1\tfirst\n2\tsecond\n").unwrap();
+        let spooled = spooled.finish().unwrap();
+        assert_eq!(spooled.size, 17);
+        assert_eq!(spooled.sha256, sha256_hex(b"1\tfirst\n2\tsecond\n"));
+
+        let mut writer = ArchiveWriter::new().unwrap();
+        writer.add_file(MANIFEST_PATH, b"{}").unwrap();
+        writer.add_spooled(&table_data_path(0), spooled).unwrap();
+        let archive = writer.finish().unwrap();
+
+        let mut contents = ArchiveContents::unpack(archive.file).unwrap();
+        assert_eq!(contents.take(MANIFEST_PATH).unwrap(), b"{}");
+
+        let mut data = String::new();
+        contents
+            .open_verified("data/0.copy", &sha256_hex(b"1\tfirst\n2\tsecond\n"))
+            .unwrap()
+            .read_to_string(&mut data)
+            .unwrap();
+        assert_eq!(data, "1\tfirst\n2\tsecond\n");
+    }
+
+    #[tokio::test]
+    async fn test_spool_sender() {
+        let mut sender = SpoolSender::spawn();
+        sender
+            .send(Bytes::from_static(b"1\tfirst\n"))
+            .await
+            .unwrap();
+        sender
+            .send(Bytes::from_static(b"2\tsecond\n"))
+            .await
+            .unwrap();
+
+        let mut spooled = sender.finish().await.unwrap();
+        assert_eq!(spooled.size, 17);
+        assert_eq!(spooled.sha256, sha256_hex(b"1\tfirst\n2\tsecond\n"));
+
+        let mut data = String::new();
+        spooled.file.read_to_string(&mut data).unwrap();
+        assert_eq!(data, "1\tfirst\n2\tsecond\n");
+    }
+
+    #[test]
+    fn test_archive_checksum() {
+        let archive = write_archive(&[("a", b"hello"), ("b", b"world")]);
+
+        let mut file = archive.file;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).unwrap();
+        assert_eq!(archive.size, data.len() as u64);
+        assert_eq!(archive.sha256, sha256_hex(&data));
+    }
+
+    #[test]
+    fn test_archive_checksum_mismatch() {
+        let archive = write_archive(&[("a", b"hello"), ("b", b"world")]);
+        let mut contents = ArchiveContents::unpack(archive.file).unwrap();
+
+        assert!(contents.take_verified("a", &sha256_hex(b"other")).is_err());
+        assert!(contents.verify("b", &sha256_hex(b"other")).is_err());
+        assert!(contents.open_verified("b", &sha256_hex(b"other")).is_err());
+    }
+
+    #[test]
+    fn test_archive_missing_file() {
+        let archive = write_archive(&[("a", b"hello")]);
+        let mut contents = ArchiveContents::unpack(archive.file).unwrap();
+
+        assert_eq!(
+            contents.take_verified("a", &sha256_hex(b"hello")).unwrap(),
+            b"hello"
+        );
+        // Files can only be taken once
+        assert!(contents.take("a").is_err());
+        assert!(contents.take("missing").is_err());
+    }
+}
@@ -0,0 +1,193 @@
+//! Tenant database backups
+//!
+//! Exports the schema and data of a tenant database into an archive
+//! (See [archive]) stored in the configured backup bucket
+
+use crate::{
+    config::BackupConfig,
+    database::DatabaseProvider,
+    metadata::backups::{BackupReason, CreateTenantBackup, TenantBackup},
+    storage::{bucket_storage_layer, upload_spooled},
+};
+use anyhow::Context;
+use archive::{
+    ArchiveWriter, BackupManifest, BackupTable, FORMAT_VERSION, MANIFEST_PATH, POST_DATA_PATH,
+    PRE_DATA_PATH, SpoolSender, SpooledFile, sha256_hex, table_data_path,
+};
+use chrono::Utc;
+use docbox_database::models::tenant::Tenant;
+use docbox_management::database::DatabaseProvider as _;
+use docbox_storage::StorageLayerFactory;
+use futures::TryStreamExt;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+pub mod archive;
+pub mod restore;
+pub mod schema;
+
+/// Content type of backup archives
+const ARCHIVE_CONTENT_TYPE: &str = "application/gzip";
+
+/// Key within the backup bucket to store a backup
+fn backup_key(env: &str, tenant_id: Uuid, backup_id: Uuid) -> String {
+    format!("backups/{env}/{tenant_id}/{backup_id}.tar.gz")
+}
+
+/// Backup the database of `tenant` into the backup bucket and record
+/// the backup in the catalogue
+pub async fn backup_tenant(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    metadata: &PgPool,
+    config: &BackupConfig,
+    tenant: &Tenant,
+    reason: BackupReason,
+    job_id: Option<Uuid>,
+) -> anyhow::Result<TenantBackup> {
+    let bucket = config.bucket()?;
+    let backup_id = Uuid::new_v4();
+
+    tracing::info!(?tenant, %backup_id, ?reason, "starting tenant backup");
+
+    let pending_migrations =
+        docbox_management::tenant::get_pending_tenant_migrations::get_pending_tenant_migrations(
+            db_provider,
+            tenant,
+        )
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let db = db_provider
+        .connect(&tenant.db_name)
+        .await
+        .context("failed to connect to tenant database")?;
+
+    let archive = export_database(&db, tenant, backup_id, pending_migrations.clone()).await?;
+    let size = archive.size as i64;
+    let sha256 = archive.sha256.clone();
+    let key = backup_key(&tenant.env, tenant.id, backup_id);
+
+    upload_spooled(
+        &bucket_storage_layer(storage_factory, bucket),
+        &key,
+        ARCHIVE_CONTENT_TYPE,
+        archive,
+    )
+    .await
+    .context("failed to upload backup archive")?;
+
+    let backup = TenantBackup::create(
+        metadata,
+        CreateTenantBackup {
+            id: backup_id,
+            env: tenant.env.clone(),
+            tenant_id: tenant.id,
+            db_name: tenant.db_name.clone(),
+            bucket: bucket.to_string(),
+            key,
+            size,
+            sha256,
+            reason,
+            job_id,
+            pending_migrations,
+        },
+    )
+    .await
+    .context("failed to store backup in catalogue")?;
+
+    tracing::info!(?backup, "tenant backup complete");
+
+    Ok(backup)
+}
+
+/// Export the schema and data of the database into an archive, table
+/// data is streamed into the archive file rather than held in memory
+pub async fn export_database(
+    db: &PgPool,
+    tenant: &Tenant,
+    backup_id: Uuid,
+    pending_migrations: Vec<String>,
+) -> anyhow::Result<SpooledFile> {
+    let mut t = db.begin().await?;
+
+    // Export from a single snapshot so the data is consistent across tables
+    sqlx::query("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+        .execute(&mut *t)
+        .await?;
+
+    let server_version: String = sqlx::query_scalar("SHOW server_version")
+        .fetch_one(&mut *t)
+        .await?;
+
+    let schema = schema::dump_schema(&mut t)
+        .await
+        .context("failed to export database schema")?;
+
+    let mut tables = Vec::with_capacity(schema.tables.len());
+    let mut table_data = Vec::with_capacity(schema.tables.len());
+
+    for (index, table) in schema.tables.iter().enumerate() {
+        let mut data = SpoolSender::spawn();
+        let mut rows = 0;
+        let mut stream = t
+            .copy_out_raw(&format!("COPY {} TO STDOUT", table.qualified_name()))
+            .await?;
+
+        while let Some(chunk) = stream.try_next().await? {
+            rows += chunk.iter().filter(|byte| **byte == b'\n').count() as u64;
+            data.send(chunk).await?;
+        }
+
+        drop(stream);
+
+        let data = data.finish().await?;
+        tables.push(BackupTable {
+            table: table.clone(),
+            file: table_data_path(index),
+            rows,
+            sha256: data.sha256.clone(),
+        });
+        table_data.push(data);
+    }
+
+    t.rollback().await?;
+
+    let pre_data = schema.pre_data_sql();
+    let post_data = schema.post_data_sql();
+
+    let manifest = BackupManifest {
+        format_version: FORMAT_VERSION,
+        backup_id,
+        env: tenant.env.clone(),
+        tenant_id: tenant.id,
+        tenant_name: tenant.name.clone(),
+        db_name: tenant.db_name.clone(),
+        created_at: Utc::now(),
+        server_version,
+        pending_migrations,
+        pre_data_sha256: sha256_hex(pre_data.as_bytes()),
+        post_data_sha256: sha256_hex(post_data.as_bytes()),
+        tables,
+    };
+
+    // Compressing the archive is blocking work
+    let archive = tokio::task::spawn_blocking(move || {
+        let mut writer = ArchiveWriter::new()?;
+
+        for (table, data) in manifest.tables.iter().zip(table_data) {
+            writer.add_spooled(&table.file, data)?;
+        }
+
+        writer.add_file(PRE_DATA_PATH, pre_data.as_bytes())?;
+        writer.add_file(POST_DATA_PATH, post_data.as_bytes())?;
+
+        let manifest = serde_json::to_vec_pretty(&manifest)?;
+        writer.add_file(MANIFEST_PATH, &manifest)?;
+
+        anyhow::Ok(writer.finish()?)
+    })
+    .await??;
+
+    Ok(archive)
+}
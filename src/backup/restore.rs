@@ -0,0 +1,342 @@
+//! Restoring tenant database backups into a new database
+
+use super::{
+    archive::{
+        ArchiveContents, BackupManifest, FORMAT_VERSION, MANIFEST_PATH, POST_DATA_PATH,
+        PRE_DATA_PATH,
+    },
+    schema::SchemaTable,
+};
+use crate::{
+    database::{
+        DatabaseProvider, MAINTENANCE_DATABASE_NAME, create_database, database_exists,
+        drop_database, quote_ident,
+    },
+    metadata::backups::TenantBackup,
+    root::set_tenant_db_name,
+    secrets::get_database_secret,
+    storage::{bucket_storage_layer, download_spooled},
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use docbox_database::models::tenant::Tenant;
+use docbox_management::database::DatabaseProvider as _;
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use itertools::Itertools;
+use serde::Serialize;
+use sqlx::{Executor, PgConnection};
+use std::fs::File;
+use uuid::Uuid;
+
+/// Maximum length of a Postgres identifier
+const MAX_IDENTIFIER_LENGTH: usize = 63;
+
+#[derive(Debug, Serialize)]
+pub struct RestoreOutcome {
+    pub backup_id: Uuid,
+    /// Database the backup was restored into
+    pub db_name: String,
+    /// Database the tenant was using before the restore
+    pub previous_db_name: String,
+    /// Whether the tenant was switched to the restored database
+    pub swapped: bool,
+    pub tables: Vec<RestoredTable>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RestoredTable {
+    #[serde(flatten)]
+    pub table: SchemaTable,
+    pub rows: u64,
+}
+
+/// Default name for the database a backup is restored into
+pub fn default_restore_db_name(tenant: &Tenant) -> String {
+    restore_db_name(&tenant.db_name, Utc::now())
+}
+
+/// Name for the database `db_name` is restored into at `restored_at`, the
+/// name is truncated to fit within the identifier limit which Postgres
+/// measures in bytes
+fn restore_db_name(db_name: &str, restored_at: DateTime<Utc>) -> String {
+    let suffix = format!("_restore_{}", restored_at.format("%Y%m%d%H%M%S"));
+    let mut prefix_len = db_name.len().min(MAX_IDENTIFIER_LENGTH - suffix.len());
+    while !db_name.is_char_boundary(prefix_len) {
+        prefix_len -= 1;
+    }
+    format!("{}{suffix}", &db_name[..prefix_len])
+}
+
+/// Backup archive unpacked into temporary files, every file in the archive
+/// has been checked against the checksums in the manifest
+pub struct BackupArchive {
+    pub manifest: BackupManifest,
+    pub pre_data: String,
+    pub post_data: String,
+    /// Table data files in the archive
+    pub contents: ArchiveContents,
+}
+
+/// Read a backup archive and its manifest, ensuring the archive format
+/// is supported and the archive files match their checksums. The archive
+/// is unpacked on a blocking thread
+pub async fn read_backup_archive(archive: File) -> anyhow::Result<BackupArchive> {
+    tokio::task::spawn_blocking(move || {
+        let mut contents =
+            ArchiveContents::unpack(archive).context("failed to read backup archive")?;
+        let manifest: BackupManifest = serde_json::from_slice(&contents.take(MANIFEST_PATH)?)
+            .context("invalid backup manifest")?;
+
+        if manifest.format_version != FORMAT_VERSION {
+            anyhow::bail!(
+                "unsupported backup format version {}",
+                manifest.format_version
+            );
+        }
+
+        let pre_data = contents.take_verified(PRE_DATA_PATH, &manifest.pre_data_sha256)?;
+        let pre_data = String::from_utf8(pre_data).context("invalid pre-data schema")?;
+        let post_data = contents.take_verified(POST_DATA_PATH, &manifest.post_data_sha256)?;
+        let post_data = String::from_utf8(post_data).context("invalid post-data schema")?;
+
+        for table in &manifest.tables {
+            contents.verify(&table.file, &table.sha256)?;
+        }
+
+        Ok(BackupArchive {
+            manifest,
+            pre_data,
+            post_data,
+            contents,
+        })
+    })
+    .await?
+}
+
+/// Restore `backup` into a new database named `db_name`, when `swap` is
+/// set the tenant is switched to the restored database once validated
+pub async fn restore_tenant(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    secrets: &SecretManager,
+    tenant: &Tenant,
+    backup: &TenantBackup,
+    db_name: String,
+    swap: bool,
+) -> anyhow::Result<RestoreOutcome> {
+    if db_name.len() > MAX_IDENTIFIER_LENGTH {
+        anyhow::bail!("database name must be at most {MAX_IDENTIFIER_LENGTH} bytes");
+    }
+
+    if db_name == tenant.db_name {
+        anyhow::bail!("cannot restore over the tenant's current database");
+    }
+
+    tracing::info!(?tenant, backup_id = %backup.id, %db_name, "restoring tenant backup");
+
+    let archive = download_spooled(
+        &bucket_storage_layer(storage_factory, &backup.bucket),
+        &backup.key,
+    )
+    .await
+    .context("failed to download backup archive")?;
+
+    if archive.sha256 != backup.sha256 {
+        anyhow::bail!("backup archive checksum does not match the catalogue");
+    }
+
+    let mut archive = read_backup_archive(archive.file).await?;
+
+    if archive.manifest.env != tenant.env || archive.manifest.tenant_id != tenant.id {
+        anyhow::bail!("backup does not belong to this tenant");
+    }
+
+    let tenant_secret = get_database_secret(secrets, &tenant.db_secret_name).await?;
+
+    let tables =
+        restore_archive_database(db_provider, &mut archive, &db_name, &tenant_secret.username)
+            .await?;
+
+    if swap {
+        set_tenant_db_name(db_provider, &tenant.env, tenant.id, &db_name).await?;
+        tracing::info!(?tenant, %db_name, "tenant switched to restored database");
+    }
+
+    Ok(RestoreOutcome {
+        backup_id: backup.id,
+        db_name,
+        previous_db_name: tenant.db_name.clone(),
+        swapped: swap,
+        tables,
+    })
+}
+
+/// Restore the contents of a backup archive into a new database named
+/// `db_name` granting `role` access to it. The database is removed if the
+/// restore fails
+pub async fn restore_archive_database(
+    db_provider: &DatabaseProvider,
+    archive: &mut BackupArchive,
+    db_name: &str,
+    role: &str,
+) -> anyhow::Result<Vec<RestoredTable>> {
+    let maintenance = db_provider
+        .connect(MAINTENANCE_DATABASE_NAME)
+        .await
+        .context("failed to connect to maintenance database")?;
+
+    if database_exists(&maintenance, db_name).await? {
+        anyhow::bail!("database {db_name} already exists");
+    }
+
+    create_database(&maintenance, db_name)
+        .await
+        .context("failed to create restore database")?;
+
+    let result = async {
+        let db = db_provider
+            .connect(db_name)
+            .await
+            .context("failed to connect to restore database")?;
+        let mut conn = db.acquire().await?;
+
+        let tables = load_backup(&mut conn, archive).await?;
+        grant_tenant_role(&mut conn, db_name, role, &archive.manifest).await?;
+
+        anyhow::Ok(tables)
+    }
+    .await;
+
+    match result {
+        Ok(tables) => Ok(tables),
+        Err(error) => {
+            // Remove the partially restored database
+            db_provider.close_pool(db_name).await;
+            if let Err(error) = drop_database(&maintenance, db_name).await {
+                tracing::error!(?error, %db_name, "failed to drop partially restored database");
+            }
+
+            Err(error)
+        }
+    }
+}
+
+/// Load the schema and data from the backup into the database
+async fn load_backup(
+    conn: &mut PgConnection,
+    archive: &mut BackupArchive,
+) -> anyhow::Result<Vec<RestoredTable>> {
+    conn.execute(sqlx::raw_sql(&archive.pre_data))
+        .await
+        .context("failed to restore schema")?;
+
+    // The pre-data schema disables function body checks for the session
+    conn.execute("RESET check_function_bodies").await?;
+
+    let mut tables = Vec::with_capacity(archive.manifest.tables.len());
+
+    for table in &archive.manifest.tables {
+        // Checksums are verified when reading the archive
+        let data = archive.contents.open(&table.file)?;
+        let qualified_name = table.table.qualified_name();
+
+        let mut copy = conn
+            .copy_in_raw(&format!("COPY {qualified_name} FROM STDIN"))
+            .await?;
+        copy.read_from(tokio::fs::File::from_std(data)).await?;
+        let rows = copy
+            .finish()
+            .await
+            .with_context(|| format!("failed to restore data for {qualified_name}"))?;
+
+        // Validate the restored data matches the backup
+        let count: i64 = sqlx::query_scalar(&format!("SELECT COUNT(*) FROM {qualified_name}"))
+            .fetch_one(&mut *conn)
+            .await?;
+
+        if rows != table.rows || count as u64 != table.rows {
+            anyhow::bail!(
+                "row count mismatch for {qualified_name}: expected {} restored {count}",
+                table.rows
+            );
+        }
+
+        tables.push(RestoredTable {
+            table: table.table.clone(),
+            rows,
+        });
+    }
+
+    conn.execute(sqlx::raw_sql(&archive.post_data))
+        .await
+        .context("failed to restore constraints and indexes")?;
+
+    Ok(tables)
+}
+
+/// Grant the tenant database role access to the restored objects
+async fn grant_tenant_role(
+    conn: &mut PgConnection,
+    db_name: &str,
+    role: &str,
+    manifest: &BackupManifest,
+) -> anyhow::Result<()> {
+    let role = quote_ident(role);
+    let mut statements = vec![format!(
+        "GRANT ALL PRIVILEGES ON DATABASE {} TO {role}",
+        quote_ident(db_name)
+    )];
+
+    let schemas = std::iter::once("public")
+        .chain(manifest.tables.iter().map(|table| table.table.schema.as_str()))
+        .unique();
+
+    for schema in schemas {
+        let schema = quote_ident(schema);
+        statements.extend([
+            format!("GRANT USAGE, CREATE ON SCHEMA {schema} TO {role}"),
+            format!("GRANT ALL PRIVILEGES ON ALL TABLES IN SCHEMA {schema} TO {role}"),
+            format!("GRANT ALL PRIVILEGES ON ALL SEQUENCES IN SCHEMA {schema} TO {role}"),
+            format!("GRANT EXECUTE ON ALL FUNCTIONS IN SCHEMA {schema} TO {role}"),
+        ]);
+    }
+
+    conn.execute(sqlx::raw_sql(&statements.join(";\n")))
+        .await
+        .context("failed to grant tenant role access")?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_restore_db_name() {
+        let restored_at = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        assert_eq!(
+            restore_db_name("docbox_dev_tenant", restored_at),
+            "docbox_dev_tenant_restore_20260102030405"
+        );
+    }
+
+    #[test]
+    fn test_restore_db_name_truncated() {
+        let restored_at = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        let name = restore_db_name(&"a".repeat(80), restored_at);
+        assert_eq!(name.len(), MAX_IDENTIFIER_LENGTH);
+        assert!(name.ends_with("_restore_20260102030405"));
+    }
+
+    #[test]
+    fn test_restore_db_name_truncated_multibyte() {
+        let restored_at = Utc.with_ymd_and_hms(2026, 1, 2, 3, 4, 5).unwrap();
+        // The byte limit falls within the second byte of a character
+        let name = restore_db_name(&format!("a{}", "é".repeat(40)), restored_at);
+        assert!(name.len() <= MAX_IDENTIFIER_LENGTH);
+        assert_eq!(name, format!("a{}_restore_20260102030405", "é".repeat(19)));
+    }
+}
@@ -0,0 +1,842 @@
+//! Schema export for tenant databases
+//!
+//! Produces the DDL statements required to recreate the user defined
+//! objects of a database from the system catalogs. Statements are split
+//! into those that must run before the table data is loaded and those
+//! that should run afterwards (constraints, indexes, triggers) so that
+//! data can be loaded in any order.
+
+use crate::database::{quote_ident, quote_literal};
+use anyhow::Context;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sqlx::PgConnection;
+
+/// Filter restricting catalog queries to user schemas, expects the
+/// namespace to be aliased as `n`
+pub const USER_SCHEMA_FILTER: &str =
+    "n.nspname NOT IN ('pg_catalog', 'information_schema') AND n.nspname NOT LIKE 'pg\\_%'";
+
+/// Filter excluding relations owned by extensions, expects the
+/// relation to be aliased as `c`
+pub const NOT_EXTENSION_RELATION: &str = "NOT EXISTS (
+    SELECT 1 FROM pg_depend dep
+    WHERE dep.classid = 'pg_class'::regclass AND dep.objid = c.oid AND dep.deptype = 'e'
+)";
+
+/// Filter excluding types owned by extensions, expects the type to be
+/// aliased as `t`
+const NOT_EXTENSION_TYPE: &str = "NOT EXISTS (
+    SELECT 1 FROM pg_depend dep
+    WHERE dep.classid = 'pg_type'::regclass AND dep.objid = t.oid AND dep.deptype = 'e'
+)";
+
+/// Smallest object ID assigned to objects created after the database
+/// system was initialized, objects below it are built in
+const FIRST_NORMAL_OBJECT_ID: i64 = 16384;
+
+/// Table within the exported schema
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SchemaTable {
+    pub schema: String,
+    pub name: String,
+}
+
+impl SchemaTable {
+    /// Quoted schema qualified name of the table
+    pub fn qualified_name(&self) -> String {
+        format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.name))
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SchemaDump {
+    /// Statements to run before loading table data
+    pub pre_data: Vec<String>,
+    /// Statements to run after loading table data
+    pub post_data: Vec<String>,
+    /// Tables that contain data
+    pub tables: Vec<SchemaTable>,
+}
+
+impl SchemaDump {
+    pub fn pre_data_sql(&self) -> String {
+        join_statements(&self.pre_data)
+    }
+
+    pub fn post_data_sql(&self) -> String {
+        join_statements(&self.post_data)
+    }
+}
+
+fn join_statements(statements: &[String]) -> String {
+    statements
+        .iter()
+        .map(|statement| format!("{};\n", statement.trim_end().trim_end_matches(';')))
+        .join("\n")
+}
+
+/// Export the schema of the database `db` is connected to
+pub async fn dump_schema(db: &mut PgConnection) -> anyhow::Result<SchemaDump> {
+    check_supported_tables(db).await?;
+
+    let mut dump = SchemaDump::default();
+
+    dump_schemas(db, &mut dump).await?;
+    dump_extensions(db, &mut dump).await?;
+    dump_types(db, &mut dump).await?;
+    dump_sequences(db, &mut dump).await?;
+    dump_functions(db, &mut dump).await?;
+    dump_tables(db, &mut dump).await?;
+    dump_sequence_owners(db, &mut dump).await?;
+    dump_views(db, &mut dump).await?;
+    dump_constraints(db, &mut dump).await?;
+    dump_indexes(db, &mut dump).await?;
+    dump_triggers(db, &mut dump).await?;
+    dump_row_security(db, &mut dump).await?;
+    dump_sequence_values(db, &mut dump).await?;
+    dump_comments(db, &mut dump).await?;
+
+    Ok(dump)
+}
+
+/// Partitioned and inherited tables are not recreated by the export, fail
+/// rather than silently producing an export that is missing their data
+async fn check_supported_tables(db: &mut PgConnection) -> anyhow::Result<()> {
+    let unsupported: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT format('%I.%I', n.nspname, c.relname)
+        FROM pg_class c
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE (c.relkind = 'p' OR c.relispartition OR EXISTS (
+            SELECT 1 FROM pg_inherits inh WHERE inh.inhrelid = c.oid
+        ))
+            AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+        ORDER BY n.nspname, c.relname"
+    ))
+    .fetch_all(&mut *db)
+    .await
+    .context("failed to check for partitioned tables")?;
+
+    if !unsupported.is_empty() {
+        anyhow::bail!(
+            "partitioned and inherited tables are not supported: {}",
+            unsupported.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+async fn dump_schemas(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let schemas: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT n.nspname::text FROM pg_namespace n
+        WHERE {USER_SCHEMA_FILTER} AND n.nspname <> 'public'
+        AND NOT EXISTS (
+            SELECT 1 FROM pg_depend dep
+            WHERE dep.classid = 'pg_namespace'::regclass AND dep.objid = n.oid AND dep.deptype = 'e'
+        )
+        ORDER BY n.nspname"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.pre_data.extend(
+        schemas
+            .iter()
+            .map(|schema| format!("CREATE SCHEMA IF NOT EXISTS {}", quote_ident(schema))),
+    );
+
+    Ok(())
+}
+
+async fn dump_extensions(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let extensions: Vec<String> = sqlx::query_scalar(
+        "SELECT extname::text FROM pg_extension WHERE extname <> 'plpgsql' ORDER BY extname",
+    )
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.pre_data.extend(
+        extensions
+            .iter()
+            .map(|name| format!("CREATE EXTENSION IF NOT EXISTS {}", quote_ident(name))),
+    );
+
+    Ok(())
+}
+
+/// Create the enum, domain and composite types, types are created in the
+/// order they were defined as they may depend on each other
+async fn dump_types(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let enums: Vec<(i64, String, String, Vec<String>)> = sqlx::query_as(&format!(
+        "SELECT t.oid::int8, n.nspname::text, t.typname::text,
+            array_agg(e.enumlabel::text ORDER BY e.enumsortorder)
+        FROM pg_type t
+        JOIN pg_enum e ON e.enumtypid = t.oid
+        JOIN pg_namespace n ON n.oid = t.typnamespace
+        WHERE {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_TYPE}
+        GROUP BY t.oid, n.nspname, t.typname"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    let domains: Vec<DomainRow> = sqlx::query_as(&format!(
+        "SELECT t.oid::int8, n.nspname::text, t.typname::text,
+            format_type(t.typbasetype, t.typtypmod),
+            CASE WHEN t.typcollation <> base.typcollation
+                THEN format('%I.%I', cn.nspname, co.collname) END,
+            t.typdefault, t.typnotnull,
+            ARRAY(
+                SELECT format('CONSTRAINT %I %s', con.conname, pg_get_constraintdef(con.oid))
+                FROM pg_constraint con
+                WHERE con.contypid = t.oid AND con.contype = 'c'
+                ORDER BY con.conname
+            )
+        FROM pg_type t
+        JOIN pg_type base ON base.oid = t.typbasetype
+        JOIN pg_namespace n ON n.oid = t.typnamespace
+        LEFT JOIN pg_collation co ON co.oid = t.typcollation
+        LEFT JOIN pg_namespace cn ON cn.oid = co.collnamespace
+        WHERE t.typtype = 'd' AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_TYPE}"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    // Composite types created with CREATE TYPE, rather than the row types
+    // of tables and views
+    let composites: Vec<(i64, String, String, Vec<String>)> = sqlx::query_as(&format!(
+        "SELECT t.oid::int8, n.nspname::text, t.typname::text,
+            ARRAY(
+                SELECT format('%I %s', a.attname, format_type(a.atttypid, a.atttypmod))
+                FROM pg_attribute a
+                WHERE a.attrelid = c.oid AND a.attnum > 0 AND NOT a.attisdropped
+                ORDER BY a.attnum
+            )
+        FROM pg_type t
+        JOIN pg_class c ON c.oid = t.typrelid
+        JOIN pg_namespace n ON n.oid = t.typnamespace
+        WHERE t.typtype = 'c' AND c.relkind = 'c'
+            AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_TYPE}"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    let enums = enums.into_iter().map(|(oid, schema, name, labels)| {
+        let statement = format!(
+            "CREATE TYPE {}.{} AS ENUM ({})",
+            quote_ident(&schema),
+            quote_ident(&name),
+            labels.iter().map(|label| quote_literal(label)).join(", ")
+        );
+        (oid, statement)
+    });
+
+    let domains = domains.into_iter().map(
+        |(oid, schema, name, base_type, collation, default, not_null, constraints)| {
+            let mut statement = format!(
+                "CREATE DOMAIN {}.{} AS {base_type}",
+                quote_ident(&schema),
+                quote_ident(&name)
+            );
+
+            if let Some(collation) = collation {
+                statement.push_str(&format!(" COLLATE {collation}"));
+            }
+
+            if let Some(default) = default {
+                statement.push_str(&format!(" DEFAULT {default}"));
+            }
+
+            if not_null {
+                statement.push_str(" NOT NULL");
+            }
+
+            for constraint in constraints {
+                statement.push_str(&format!(" {constraint}"));
+            }
+
+            (oid, statement)
+        },
+    );
+
+    let composites = composites
+        .into_iter()
+        .map(|(oid, schema, name, attributes)| {
+            let statement = format!(
+                "CREATE TYPE {}.{} AS ({})",
+                quote_ident(&schema),
+                quote_ident(&name),
+                attributes.join(", ")
+            );
+            (oid, statement)
+        });
+
+    dump.pre_data.extend(
+        enums
+            .chain(domains)
+            .chain(composites)
+            .sorted_by_key(|(oid, _)| *oid)
+            .map(|(_, statement)| statement),
+    );
+
+    Ok(())
+}
+
+/// Object ID, schema, name, base type, collation, default, not null and
+/// check constraints of a domain
+type DomainRow = (
+    i64,
+    String,
+    String,
+    String,
+    Option<String>,
+    Option<String>,
+    bool,
+    Vec<String>,
+);
+
+/// Schema, name, data type, start, min, max, increment and cycle of a sequence
+type SequenceRow = (String, String, String, i64, i64, i64, i64, bool);
+
+async fn dump_sequences(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    // Identity sequences are created along with their table
+    let sequences: Vec<SequenceRow> =
+        sqlx::query_as(&format!(
+            "SELECT n.nspname::text, c.relname::text, format_type(s.seqtypid, NULL),
+                s.seqstart, s.seqmin, s.seqmax, s.seqincrement, s.seqcycle
+            FROM pg_sequence s
+            JOIN pg_class c ON c.oid = s.seqrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            WHERE {USER_SCHEMA_FILTER} AND NOT EXISTS (
+                SELECT 1 FROM pg_depend dep
+                WHERE dep.classid = 'pg_class'::regclass AND dep.objid = c.oid AND dep.deptype IN ('i', 'e')
+            )
+            ORDER BY n.nspname, c.relname"
+        ))
+        .fetch_all(&mut *db)
+        .await?;
+
+    dump.pre_data.extend(sequences.iter().map(
+        |(schema, name, data_type, start, min, max, increment, cycle)| {
+            format!(
+                "CREATE SEQUENCE {}.{} AS {data_type} INCREMENT BY {increment} MINVALUE {min} MAXVALUE {max} START WITH {start}{}",
+                quote_ident(schema),
+                quote_ident(name),
+                if *cycle { " CYCLE" } else { "" }
+            )
+        },
+    ));
+
+    Ok(())
+}
+
+async fn dump_functions(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let functions: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT pg_get_functiondef(p.oid)
+        FROM pg_proc p
+        JOIN pg_namespace n ON n.oid = p.pronamespace
+        WHERE {USER_SCHEMA_FILTER} AND p.prokind IN ('f', 'p') AND NOT EXISTS (
+            SELECT 1 FROM pg_depend dep
+            WHERE dep.classid = 'pg_proc'::regclass AND dep.objid = p.oid AND dep.deptype = 'e'
+        )
+        ORDER BY p.oid"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    // Functions are created before the tables they may reference so their
+    // bodies can only be checked once the whole schema exists
+    if !functions.is_empty() {
+        dump.pre_data.push("SET check_function_bodies = false".to_string());
+    }
+
+    dump.pre_data.extend(functions);
+    Ok(())
+}
+
+/// Schema, table, name, data type, not null, default, identity and generated
+/// kind of a table column
+type ColumnRow = (
+    String,
+    String,
+    String,
+    String,
+    bool,
+    Option<String>,
+    String,
+    String,
+);
+
+async fn dump_tables(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let columns: Vec<ColumnRow> = sqlx::query_as(&format!(
+        "SELECT n.nspname::text, c.relname::text, a.attname::text,
+                format_type(a.atttypid, a.atttypmod), a.attnotnull,
+                pg_get_expr(d.adbin, d.adrelid), a.attidentity::text, a.attgenerated::text
+            FROM pg_attribute a
+            JOIN pg_class c ON c.oid = a.attrelid
+            JOIN pg_namespace n ON n.oid = c.relnamespace
+            LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+            WHERE c.relkind = 'r' AND a.attnum > 0 AND NOT a.attisdropped
+                AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+            ORDER BY n.nspname, c.relname, a.attnum"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    for ((schema, name), columns) in &columns
+        .iter()
+        .chunk_by(|column| (column.0.clone(), column.1.clone()))
+    {
+        let columns = columns
+            .map(
+                |(_, _, column, data_type, not_null, default, identity, generated)| {
+                    let mut definition = format!("{} {data_type}", quote_ident(column));
+
+                    match (identity.as_str(), generated.as_str(), default) {
+                        ("a", _, _) => definition.push_str(" GENERATED ALWAYS AS IDENTITY"),
+                        ("d", _, _) => definition.push_str(" GENERATED BY DEFAULT AS IDENTITY"),
+                        (_, "s", Some(expression)) => definition
+                            .push_str(&format!(" GENERATED ALWAYS AS ({expression}) STORED")),
+                        (_, _, Some(expression)) => {
+                            definition.push_str(&format!(" DEFAULT {expression}"))
+                        }
+                        _ => {}
+                    }
+
+                    if *not_null {
+                        definition.push_str(" NOT NULL");
+                    }
+
+                    definition
+                },
+            )
+            .join(",\n    ");
+
+        let table = SchemaTable { schema, name };
+        dump.pre_data.push(format!(
+            "CREATE TABLE {} (\n    {columns}\n)",
+            table.qualified_name()
+        ));
+        dump.tables.push(table);
+    }
+
+    Ok(())
+}
+
+/// Link sequences created for serial columns to their column, so the
+/// sequence is dropped along with the column
+async fn dump_sequence_owners(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let owners: Vec<(String, String, String, String, String)> = sqlx::query_as(&format!(
+        "SELECT n.nspname::text, s.relname::text, tn.nspname::text, t.relname::text, a.attname::text
+        FROM pg_depend dep
+        JOIN pg_class s ON s.oid = dep.objid
+        JOIN pg_namespace n ON n.oid = s.relnamespace
+        JOIN pg_class t ON t.oid = dep.refobjid
+        JOIN pg_namespace tn ON tn.oid = t.relnamespace
+        JOIN pg_attribute a ON a.attrelid = t.oid AND a.attnum = dep.refobjsubid
+        WHERE dep.classid = 'pg_class'::regclass AND dep.refclassid = 'pg_class'::regclass
+            AND dep.deptype = 'a' AND s.relkind = 'S' AND {USER_SCHEMA_FILTER}
+        ORDER BY n.nspname, s.relname"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.pre_data.extend(
+        owners
+            .iter()
+            .map(|(schema, name, table_schema, table, column)| {
+                format!(
+                    "ALTER SEQUENCE {}.{} OWNED BY {}.{}.{}",
+                    quote_ident(schema),
+                    quote_ident(name),
+                    quote_ident(table_schema),
+                    quote_ident(table),
+                    quote_ident(column)
+                )
+            }),
+    );
+
+    Ok(())
+}
+
+async fn dump_views(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let views: Vec<(String, String, String)> = sqlx::query_as(&format!(
+        "SELECT n.nspname::text, c.relname::text, pg_get_viewdef(c.oid)
+        FROM pg_class c
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind = 'v' AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+        ORDER BY c.oid"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.pre_data
+        .extend(views.iter().map(|(schema, name, definition)| {
+            format!(
+                "CREATE VIEW {}.{} AS {}",
+                quote_ident(schema),
+                quote_ident(name),
+                definition.trim().trim_end_matches(';')
+            )
+        }));
+
+    Ok(())
+}
+
+async fn dump_constraints(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let constraints: Vec<(String, String, String, String)> = sqlx::query_as(&format!(
+        "SELECT n.nspname::text, c.relname::text, con.conname::text, pg_get_constraintdef(con.oid)
+        FROM pg_constraint con
+        JOIN pg_class c ON c.oid = con.conrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE con.contype IN ('p', 'u', 'x', 'c', 'f') AND con.conislocal
+            AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+        ORDER BY
+            CASE con.contype WHEN 'p' THEN 0 WHEN 'u' THEN 1 WHEN 'x' THEN 1 WHEN 'c' THEN 2 ELSE 3 END,
+            n.nspname, c.relname, con.conname"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.post_data
+        .extend(constraints.iter().map(|(schema, table, name, definition)| {
+            format!(
+                "ALTER TABLE {}.{} ADD CONSTRAINT {} {definition}",
+                quote_ident(schema),
+                quote_ident(table),
+                quote_ident(name)
+            )
+        }));
+
+    Ok(())
+}
+
+async fn dump_indexes(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    // Indexes backing constraints are created by the constraint itself
+    let indexes: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT pg_get_indexdef(i.indexrelid)
+        FROM pg_index i
+        JOIN pg_class c ON c.oid = i.indrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION} AND NOT EXISTS (
+            SELECT 1 FROM pg_constraint con
+            WHERE con.conindid = i.indexrelid AND con.contype IN ('p', 'u', 'x')
+        )
+        ORDER BY n.nspname, c.relname, i.indexrelid"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.post_data.extend(indexes);
+    Ok(())
+}
+
+async fn dump_triggers(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let triggers: Vec<String> = sqlx::query_scalar(&format!(
+        "SELECT pg_get_triggerdef(t.oid)
+        FROM pg_trigger t
+        JOIN pg_class c ON c.oid = t.tgrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE NOT t.tgisinternal AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+        ORDER BY n.nspname, c.relname, t.tgname"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.post_data.extend(triggers);
+    Ok(())
+}
+
+/// Schema, table, name, permissive, command, roles, using expression and
+/// check expression of a row security policy
+type PolicyRow = (
+    String,
+    String,
+    String,
+    bool,
+    String,
+    Vec<String>,
+    Option<String>,
+    Option<String>,
+);
+
+/// Enable row level security and create the policies, row security is
+/// enabled after loading data so loading is not subject to the policies
+async fn dump_row_security(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let tables: Vec<(String, String, bool)> = sqlx::query_as(&format!(
+        "SELECT n.nspname::text, c.relname::text, c.relforcerowsecurity
+        FROM pg_class c
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind = 'r' AND c.relrowsecurity
+            AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+        ORDER BY n.nspname, c.relname"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    for (schema, name, force) in &tables {
+        let table = format!("{}.{}", quote_ident(schema), quote_ident(name));
+        dump.post_data
+            .push(format!("ALTER TABLE {table} ENABLE ROW LEVEL SECURITY"));
+        if *force {
+            dump.post_data
+                .push(format!("ALTER TABLE {table} FORCE ROW LEVEL SECURITY"));
+        }
+    }
+
+    let policies: Vec<PolicyRow> = sqlx::query_as(&format!(
+        "SELECT n.nspname::text, c.relname::text, pol.polname::text, pol.polpermissive,
+            pol.polcmd::text,
+            ARRAY(
+                SELECT CASE WHEN role.oid = 0 THEN 'PUBLIC' ELSE quote_ident(pg_get_userbyid(role.oid)) END
+                FROM unnest(pol.polroles) AS role(oid)
+                ORDER BY 1
+            ),
+            pg_get_expr(pol.polqual, pol.polrelid), pg_get_expr(pol.polwithcheck, pol.polrelid)
+        FROM pg_policy pol
+        JOIN pg_class c ON c.oid = pol.polrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+        ORDER BY n.nspname, c.relname, pol.polname"
+    ))
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.post_data.extend(policies.iter().map(
+        |(schema, table, name, permissive, command, roles, using, check)| {
+            let command = match command.as_str() {
+                "r" => "SELECT",
+                "a" => "INSERT",
+                "w" => "UPDATE",
+                "d" => "DELETE",
+                _ => "ALL",
+            };
+
+            let mut statement = format!(
+                "CREATE POLICY {} ON {}.{} AS {} FOR {command} TO {}",
+                quote_ident(name),
+                quote_ident(schema),
+                quote_ident(table),
+                if *permissive {
+                    "PERMISSIVE"
+                } else {
+                    "RESTRICTIVE"
+                },
+                roles.join(", ")
+            );
+
+            if let Some(using) = using {
+                statement.push_str(&format!(" USING ({using})"));
+            }
+
+            if let Some(check) = check {
+                statement.push_str(&format!(" WITH CHECK ({check})"));
+            }
+
+            statement
+        },
+    ));
+
+    Ok(())
+}
+
+async fn dump_sequence_values(db: &mut PgConnection, dump: &mut SchemaDump) -> sqlx::Result<()> {
+    let values: Vec<(String, String, i64)> = sqlx::query_as(
+        "SELECT schemaname::text, sequencename::text, last_value
+        FROM pg_sequences
+        WHERE last_value IS NOT NULL AND schemaname NOT IN ('pg_catalog', 'information_schema')
+        ORDER BY schemaname, sequencename",
+    )
+    .fetch_all(&mut *db)
+    .await?;
+
+    dump.post_data
+        .extend(values.iter().map(|(schema, name, value)| {
+            let sequence = format!("{}.{}", quote_ident(schema), quote_ident(name));
+            format!("SELECT setval({}, {value}, true)", quote_literal(&sequence))
+        }));
+
+    Ok(())
+}
+
+/// Recreate the comments on user objects, fails if a comment is on a kind
+/// of object the export does not support commenting on
+async fn dump_comments(db: &mut PgConnection, dump: &mut SchemaDump) -> anyhow::Result<()> {
+    let comments: Vec<(String, String, String)> = sqlx::query_as(
+        "SELECT o.type, o.identity, d.description
+        FROM pg_description d
+        CROSS JOIN LATERAL pg_identify_object(d.classoid, d.objoid, d.objsubid) o
+        WHERE d.objoid >= $1 AND d.classoid <> 'pg_extension'::regclass AND NOT EXISTS (
+            SELECT 1 FROM pg_depend dep
+            WHERE dep.classid = d.classoid AND dep.objid = d.objoid AND dep.deptype = 'e'
+        )
+        ORDER BY o.type, o.identity",
+    )
+    .bind(FIRST_NORMAL_OBJECT_ID)
+    .fetch_all(&mut *db)
+    .await
+    .context("failed to export comments")?;
+
+    let mut unsupported = Vec::new();
+
+    for (kind, identity, comment) in &comments {
+        // Identities of constraints, triggers and policies are in the
+        // "{name} on {table}" form expected by COMMENT ON
+        let object = match kind.as_str() {
+            "schema" => "SCHEMA",
+            "table" => "TABLE",
+            "view" => "VIEW",
+            "sequence" => "SEQUENCE",
+            "index" => "INDEX",
+            "table column" | "view column" | "composite type column" => "COLUMN",
+            "function" => "FUNCTION",
+            "procedure" => "PROCEDURE",
+            "type" | "composite type" => "TYPE",
+            "domain" => "DOMAIN",
+            "table constraint" => "CONSTRAINT",
+            "trigger" => "TRIGGER",
+            "policy" => "POLICY",
+            _ => {
+                unsupported.push(format!("{kind} {identity}"));
+                continue;
+            }
+        };
+
+        dump.post_data.push(format!(
+            "COMMENT ON {object} {identity} IS {}",
+            quote_literal(comment)
+        ));
+    }
+
+    if !unsupported.is_empty() {
+        anyhow::bail!(
+            "comments on these objects are not supported: {}",
+            unsupported.join(", ")
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::{Executor, PgPool};
+
+    #[test]
+    fn test_join_statements() {
+        let statements = [
+            "CREATE TABLE \"a\" (\"id\" int)".to_string(),
+            "CREATE INDEX \"a_id\" ON \"a\" (\"id\");".to_string(),
+            "SET check_function_bodies = false;  \n".to_string(),
+        ];
+
+        assert_eq!(
+            join_statements(&statements),
+            "CREATE TABLE \"a\" (\"id\" int);\n\n\
+             CREATE INDEX \"a_id\" ON \"a\" (\"id\");\n\n\
+             SET check_function_bodies = false;\n"
+        );
+    }
+
+    #[test]
+    fn test_join_statements_empty() {
+        assert_eq!(join_statements(&[]), "");
+    }
+
+    /// Dump the schema, recreate it from the dump in an empty public schema
+    /// and check dumping it again produces the same statements
+    #[sqlx::test(migrations = false)]
+    async fn test_dump_schema_round_trip(pool: PgPool) {
+        let mut db = pool.acquire().await.unwrap();
+
+        db.execute(sqlx::raw_sql(
+            r#"
+            CREATE TYPE "status" AS ENUM ('active', 'disabled');
+            CREATE DOMAIN "email" AS TEXT NOT NULL CHECK (VALUE LIKE '%@%');
+            CREATE TYPE "address" AS ("street" TEXT, "contact" "email");
+            CREATE TABLE "users" (
+                "id" SERIAL PRIMARY KEY,
+                "status" "status" NOT NULL,
+                "email" "email",
+                "address" "address",
+                "owner" TEXT NOT NULL DEFAULT current_user
+            );
+            COMMENT ON TABLE "users" IS 'Users of the tenant';
+            COMMENT ON COLUMN "users"."email" IS 'It''s the login';
+            COMMENT ON CONSTRAINT "users_pkey" ON "users" IS 'Primary key';
+            ALTER TABLE "users" ENABLE ROW LEVEL SECURITY;
+            CREATE POLICY "users_owner" ON "users" AS RESTRICTIVE FOR UPDATE TO PUBLIC
+                USING ("owner" = current_user) WITH CHECK ("status" = 'active');
+            "#,
+        ))
+        .await
+        .unwrap();
+
+        let dump = dump_schema(&mut db).await.unwrap();
+
+        assert!(dump.pre_data.contains(
+            &r#"ALTER SEQUENCE "public"."users_id_seq" OWNED BY "public"."users"."id""#.to_string()
+        ));
+
+        for statement in [
+            r#"ALTER TABLE "public"."users" ENABLE ROW LEVEL SECURITY"#,
+            "COMMENT ON COLUMN public.users.email IS 'It''s the login'",
+            "COMMENT ON CONSTRAINT users_pkey on public.users IS 'Primary key'",
+        ] {
+            assert!(
+                dump.post_data.contains(&statement.to_string()),
+                "{statement}"
+            );
+        }
+        assert!(
+            dump.post_data
+                .iter()
+                .any(|statement| statement.starts_with(r#"CREATE POLICY "users_owner""#))
+        );
+
+        db.execute("DROP SCHEMA public CASCADE; CREATE SCHEMA public")
+            .await
+            .unwrap();
+        db.execute(sqlx::raw_sql(&dump.pre_data_sql()))
+            .await
+            .unwrap();
+        db.execute(sqlx::raw_sql(&dump.post_data_sql()))
+            .await
+            .unwrap();
+
+        let restored = dump_schema(&mut db).await.unwrap();
+        assert_eq!(restored.pre_data, dump.pre_data);
+        assert_eq!(restored.post_data, dump.post_data);
+        assert_eq!(restored.tables, dump.tables);
+
+        // The sequence is dropped along with its column
+        db.execute(r#"ALTER TABLE "users" DROP COLUMN "id""#)
+            .await
+            .unwrap();
+        let sequence: Option<String> =
+            sqlx::query_scalar("SELECT to_regclass('users_id_seq')::text")
+                .fetch_one(&mut *db)
+                .await
+                .unwrap();
+        assert_eq!(sequence, None);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_dump_schema_unsupported_comment(pool: PgPool) {
+        let mut db = pool.acquire().await.unwrap();
+
+        db.execute(sqlx::raw_sql(
+            r#"
+            CREATE TABLE "a" ("id" INT);
+            CREATE RULE "a_rule" AS ON INSERT TO "a" DO ALSO NOTIFY "a";
+            COMMENT ON RULE "a_rule" ON "a" IS 'Rule';
+            "#,
+        ))
+        .await
+        .unwrap();
+
+        let error = dump_schema(&mut db).await.unwrap_err();
+        assert!(format!("{error:#}").contains("comments on these objects are not supported"));
+    }
+}
@@ -0,0 +1,448 @@
+//! Cloning a tenant into another environment
+//!
+//! The clone is created as a new tenant, the source database is exported
+//! into a backup archive (See [crate::backup::archive]) and restored into
+//! a database that replaces the clone's empty database. Storage objects
+//! are then copied between the buckets and the search index rebuilt
+
+use crate::{
+    backup::{
+        export_database,
+        restore::{default_restore_db_name, read_backup_archive, restore_archive_database},
+    },
+    database::{DatabaseProvider, MAINTENANCE_DATABASE_NAME, drop_database},
+    jobs::JobContext,
+    metadata::jobs::JobLogLevel,
+    root::switch_tenant_database,
+    secrets::get_database_secret,
+    storage::{download_spooled, upload_spooled},
+    teardown::{ResourceStatus, delete_tenant},
+};
+use anyhow::Context;
+use docbox_core::tenant::rebuild_tenant_index::{
+    apply_rebuilt_tenant_index, recreate_search_index_data,
+};
+use docbox_database::models::tenant::Tenant;
+use docbox_management::{
+    database::DatabaseProvider as _, tenant::create_tenant::CreateTenantConfig,
+};
+use docbox_search::SearchIndexFactory;
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::{fs::File, sync::OnceLock};
+use tokio_util::task::LocalPoolHandle;
+use uuid::Uuid;
+
+/// Number of threads available for rebuilding search indexes
+const SEARCH_REBUILD_THREADS: usize = 2;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloneStage {
+    CreateTenant,
+    CopyDatabase,
+    CopyStorage,
+    RebuildSearch,
+    Complete,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CloneProgress {
+    pub stage: CloneStage,
+    /// Number of storage objects copied
+    pub objects_copied: usize,
+    /// Total number of storage objects to copy, known once copying starts
+    pub objects_total: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CloneOutcome {
+    pub source_env: String,
+    pub source_tenant_id: Uuid,
+    pub tenant: Tenant,
+    /// Number of rows copied from each table
+    pub tables: Vec<CopiedTable>,
+    pub objects_copied: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct CopiedTable {
+    pub table: String,
+    pub rows: u64,
+}
+
+/// Storage object referenced by the tenant database
+#[derive(Debug, FromRow)]
+pub struct StorageObject {
+    pub file_key: String,
+    pub mime: String,
+}
+
+/// Get every storage object referenced by the tenant database
+pub async fn get_storage_objects(db: &PgPool) -> sqlx::Result<Vec<StorageObject>> {
+    sqlx::query_as(
+        r#"
+        SELECT "file_key", "mime" FROM "docbox_files"
+        UNION
+        SELECT "file_key", "mime" FROM "docbox_generated_files"
+        "#,
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Clone `source` into a new tenant created from `target`
+// The docbox services are passed individually as they are to create_tenant
+#[allow(clippy::too_many_arguments)]
+pub async fn clone_tenant(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    search_factory: &SearchIndexFactory,
+    secrets: &SecretManager,
+    source: &Tenant,
+    target: CreateTenantConfig,
+    keep_on_failure: bool,
+    job: &JobContext,
+) -> anyhow::Result<CloneOutcome> {
+    let mut progress = CloneProgress {
+        stage: CloneStage::CreateTenant,
+        objects_copied: 0,
+        objects_total: None,
+    };
+    job.set_progress(&progress).await;
+
+    job.log(
+        JobLogLevel::Info,
+        format!("creating tenant {}/{}", target.env, target.id),
+    )
+    .await;
+
+    let tenant = docbox_management::tenant::create_tenant::create_tenant(
+        db_provider,
+        search_factory,
+        storage_factory,
+        secrets,
+        target,
+    )
+    .await
+    .map_err(anyhow::Error::new)?;
+
+    // The created tenant is deleted when a later stage fails unless it is
+    // kept for inspection (See [handle_created_tenant_failure])
+    let result = async {
+        let tables =
+            copy_database(db_provider, secrets, source, &tenant, job, &mut progress).await?;
+
+        let objects_copied = copy_storage(
+            db_provider,
+            storage_factory,
+            source,
+            &tenant,
+            job,
+            &mut progress,
+        )
+        .await?;
+
+        progress.stage = CloneStage::RebuildSearch;
+        job.set_progress(&progress).await;
+        job.log(JobLogLevel::Info, "rebuilding search index").await;
+
+        let tenant = rebuild_search_index(
+            db_provider,
+            storage_factory,
+            search_factory,
+            &tenant.env,
+            tenant.id,
+        )
+        .await?;
+
+        progress.stage = CloneStage::Complete;
+        job.set_progress(&progress).await;
+
+        anyhow::Ok(CloneOutcome {
+            source_env: source.env.clone(),
+            source_tenant_id: source.id,
+            tenant,
+            tables,
+            objects_copied,
+        })
+    }
+    .await;
+
+    match result {
+        Ok(outcome) => Ok(outcome),
+        Err(error) => Err(handle_created_tenant_failure(
+            db_provider,
+            storage_factory,
+            search_factory,
+            secrets,
+            &tenant,
+            keep_on_failure,
+            job,
+            error.context("clone failed"),
+        )
+        .await),
+    }
+}
+
+/// Handle a stage failing after `tenant` was created by a clone or import.
+/// The tenant is deleted unless `keep_on_failure` is set, the returned
+/// error names the tenant and whether it still exists
+// Takes the same services as delete_tenant along with the failure details
+#[allow(clippy::too_many_arguments)]
+pub async fn handle_created_tenant_failure(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    search_factory: &SearchIndexFactory,
+    secrets: &SecretManager,
+    tenant: &Tenant,
+    keep_on_failure: bool,
+    job: &JobContext,
+    error: anyhow::Error,
+) -> anyhow::Error {
+    let (env, tenant_id) = (tenant.env.clone(), tenant.id);
+
+    if keep_on_failure {
+        job.log(
+            JobLogLevel::Error,
+            format!("{error:#}, tenant {env}/{tenant_id} was left in place for inspection"),
+        )
+        .await;
+        return error.context(format!("tenant {env}/{tenant_id} was left in place"));
+    }
+
+    job.log(
+        JobLogLevel::Warn,
+        format!("{error:#}, deleting tenant {env}/{tenant_id}"),
+    )
+    .await;
+
+    // Reloaded as the tenant database may have been replaced
+    let current =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider, &env, tenant_id).await;
+    let tenant = match current {
+        Ok(Some(tenant)) => tenant,
+        _ => tenant.clone(),
+    };
+
+    let report = delete_tenant(
+        db_provider,
+        storage_factory,
+        search_factory,
+        secrets,
+        &tenant,
+        &[],
+    )
+    .await;
+
+    if matches!(report.record, ResourceStatus::Deleted) {
+        return error.context(format!("tenant {env}/{tenant_id} was deleted"));
+    }
+
+    job.log(
+        JobLogLevel::Error,
+        format!(
+            "failed to delete tenant {env}/{tenant_id}: {}",
+            serde_json::to_string(&report).unwrap_or_default()
+        ),
+    )
+    .await;
+
+    error.context(format!(
+        "tenant {env}/{tenant_id} could not be fully deleted and was left in place"
+    ))
+}
+
+/// Copy the source database into a new database and switch the clone to
+/// it, dropping the empty database created for the clone
+async fn copy_database(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    source: &Tenant,
+    tenant: &Tenant,
+    job: &JobContext,
+    progress: &mut CloneProgress,
+) -> anyhow::Result<Vec<CopiedTable>> {
+    progress.stage = CloneStage::CopyDatabase;
+    job.set_progress(&*progress).await;
+
+    if job.is_cancelled().await {
+        anyhow::bail!("clone cancelled");
+    }
+
+    job.log(
+        JobLogLevel::Info,
+        format!(
+            "copying database {} to tenant {}",
+            source.db_name, tenant.id
+        ),
+    )
+    .await;
+
+    let pending_migrations =
+        docbox_management::tenant::get_pending_tenant_migrations::get_pending_tenant_migrations(
+            db_provider,
+            source,
+        )
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let source_db = db_provider
+        .connect(&source.db_name)
+        .await
+        .context("failed to connect to source database")?;
+
+    let archive = export_database(&source_db, source, Uuid::new_v4(), pending_migrations).await?;
+
+    replace_tenant_database(db_provider, secrets, tenant, archive.file).await
+}
+
+/// Restore the database in a backup `archive` into a new database and
+/// switch `tenant` to it, dropping the database the tenant was using.
+/// Migrations pending in the backup are recorded as pending for the tenant
+pub async fn replace_tenant_database(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    tenant: &Tenant,
+    archive: File,
+) -> anyhow::Result<Vec<CopiedTable>> {
+    let mut archive = read_backup_archive(archive).await?;
+
+    let tenant_secret = get_database_secret(secrets, &tenant.db_secret_name).await?;
+    let db_name = default_restore_db_name(tenant);
+
+    let tables =
+        restore_archive_database(db_provider, &mut archive, &db_name, &tenant_secret.username)
+            .await?;
+
+    // The restored schema matches the backup, migrations pending in the
+    // backup are pending on the tenant
+    switch_tenant_database(
+        db_provider,
+        &tenant.env,
+        tenant.id,
+        &db_name,
+        &archive.manifest.pending_migrations,
+    )
+    .await?;
+
+    db_provider.close_pool(&tenant.db_name).await;
+    let maintenance = db_provider
+        .connect(MAINTENANCE_DATABASE_NAME)
+        .await
+        .context("failed to connect to maintenance database")?;
+    drop_database(&maintenance, &tenant.db_name)
+        .await
+        .context("failed to drop replaced tenant database")?;
+
+    Ok(tables
+        .into_iter()
+        .map(|table| CopiedTable {
+            table: table.table.qualified_name(),
+            rows: table.rows,
+        })
+        .collect())
+}
+
+/// Pool of single threaded runtimes driving search index rebuilds
+fn search_rebuild_pool() -> &'static LocalPoolHandle {
+    static POOL: OnceLock<LocalPoolHandle> = OnceLock::new();
+    POOL.get_or_init(|| LocalPoolHandle::new(SEARCH_REBUILD_THREADS))
+}
+
+/// Rebuild the search index of `tenant` from its database and storage
+pub async fn rebuild_search_index(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    search_factory: &SearchIndexFactory,
+    env: &str,
+    tenant_id: Uuid,
+) -> anyhow::Result<Tenant> {
+    // Reloaded as the tenant database may have been replaced
+    let tenant = docbox_management::tenant::get_tenant::get_tenant(db_provider, env, tenant_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .context("tenant not found")?;
+
+    let db = db_provider
+        .connect(&tenant.db_name)
+        .await
+        .context("failed to connect to tenant database")?;
+
+    let search = search_factory.create_search_index(&tenant);
+    let storage = storage_factory.create_storage_layer(&tenant);
+
+    // Rebuilding the index is not Send so is driven on a pinned local task
+    // rather than within the job task
+    search_rebuild_pool()
+        .spawn_pinned(move || async move {
+            let data = recreate_search_index_data(&db, &storage).await?;
+            apply_rebuilt_tenant_index(&search, data).await?;
+            anyhow::Ok(())
+        })
+        .await
+        .context("search index rebuild task failed")?
+        .context("failed to rebuild search index")?;
+
+    Ok(tenant)
+}
+
+/// Copy every storage object referenced by the source database into the
+/// bucket of the clone, returns the number of objects copied
+async fn copy_storage(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    source: &Tenant,
+    tenant: &Tenant,
+    job: &JobContext,
+    progress: &mut CloneProgress,
+) -> anyhow::Result<usize> {
+    let source_db = db_provider
+        .connect(&source.db_name)
+        .await
+        .context("failed to connect to source database")?;
+    let objects = get_storage_objects(&source_db)
+        .await
+        .context("failed to get storage objects")?;
+
+    progress.stage = CloneStage::CopyStorage;
+    progress.objects_total = Some(objects.len());
+    job.set_progress(&*progress).await;
+
+    job.log(
+        JobLogLevel::Info,
+        format!(
+            "copying {} objects from {} to {}",
+            objects.len(),
+            source.s3_name,
+            tenant.s3_name
+        ),
+    )
+    .await;
+
+    let source_storage = storage_factory.create_storage_layer(source);
+    let target_storage = storage_factory.create_storage_layer(tenant);
+
+    for object in objects {
+        if job.is_cancelled().await {
+            anyhow::bail!("clone cancelled");
+        }
+
+        job.wait_while_paused().await;
+
+        let data = download_spooled(&source_storage, &object.file_key)
+            .await
+            .with_context(|| format!("failed to read object {}", object.file_key))?;
+
+        upload_spooled(&target_storage, &object.file_key, &object.mime, data)
+            .await
+            .with_context(|| format!("failed to upload object {}", object.file_key))?;
+
+        progress.objects_copied += 1;
+        job.set_progress(&*progress).await;
+    }
+
+    Ok(progress.objects_copied)
+}
@@ -1,5 +1,8 @@
 use anyhow::Context;
+use chrono::{DateTime, TimeDelta, Utc};
+use docbox_database::{PgConnectOptions, sqlx::postgres::PgSslMode};
 use serde::Deserialize;
+use std::{path::PathBuf, str::FromStr};
 
 pub struct ServerPassword(pub String);
 
@@ -21,6 +24,198 @@ impl DocboxServerUrl {
     }
 }
 
+/// Token required to access the metrics endpoint, metrics are
+/// disabled when not set
+pub struct MetricsToken(pub Option<String>);
+
+impl MetricsToken {
+    pub fn from_env() -> MetricsToken {
+        MetricsToken(std::env::var("DOCBOX_MANAGER_METRICS_TOKEN").ok())
+    }
+}
+
+/// Whether the manager is behind an authenticating proxy that sets the
+/// `X-Forwarded-User` header, the header is ignored otherwise as any
+/// client could set it
+pub struct TrustedProxy(pub bool);
+
+impl TrustedProxy {
+    pub fn from_env() -> anyhow::Result<TrustedProxy> {
+        let enabled = std::env::var("DOCBOX_MANAGER_TRUSTED_PROXY")
+            .ok()
+            .map(|value| value.parse::<bool>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_TRUSTED_PROXY value")?
+            .unwrap_or_default();
+
+        Ok(TrustedProxy(enabled))
+    }
+}
+
+/// Configuration for tenant database backups
+pub struct BackupConfig {
+    /// Bucket to store backups within, backups are disabled when not set
+    pub bucket: Option<String>,
+    /// Whether to automatically backup tenants before migrating them
+    pub before_migrate: bool,
+}
+
+impl BackupConfig {
+    pub fn from_env() -> anyhow::Result<BackupConfig> {
+        let bucket = std::env::var("DOCBOX_MANAGER_BACKUP_BUCKET").ok();
+        let before_migrate = std::env::var("DOCBOX_MANAGER_BACKUP_BEFORE_MIGRATE")
+            .ok()
+            .map(|value| value.parse::<bool>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_BACKUP_BEFORE_MIGRATE value")?
+            .unwrap_or_default();
+
+        if before_migrate && bucket.is_none() {
+            anyhow::bail!(
+                "DOCBOX_MANAGER_BACKUP_BEFORE_MIGRATE requires DOCBOX_MANAGER_BACKUP_BUCKET to be set"
+            );
+        }
+
+        Ok(BackupConfig {
+            bucket,
+            before_migrate,
+        })
+    }
+
+    /// Get the backup bucket, errors when backups are not configured
+    pub fn bucket(&self) -> anyhow::Result<&str> {
+        self.bucket
+            .as_deref()
+            .context("backups are not configured (missing DOCBOX_MANAGER_BACKUP_BUCKET)")
+    }
+}
+
+/// Policy for rotating database credentials
+pub struct RotationPolicy {
+    /// Maximum age of credentials before they are considered overdue
+    pub max_age_days: i64,
+}
+
+impl RotationPolicy {
+    pub fn from_env() -> anyhow::Result<RotationPolicy> {
+        let max_age_days = std::env::var("DOCBOX_MANAGER_CREDENTIAL_MAX_AGE_DAYS")
+            .ok()
+            .map(|value| value.parse::<i64>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_CREDENTIAL_MAX_AGE_DAYS value")?
+            .unwrap_or(90);
+
+        Ok(RotationPolicy { max_age_days })
+    }
+
+    /// Check if credentials last rotated at `last_rotated_at` are overdue,
+    /// credentials that have never been rotated are always overdue
+    pub fn is_overdue(&self, last_rotated_at: Option<DateTime<Utc>>) -> bool {
+        let overdue_before = Utc::now() - TimeDelta::days(self.max_age_days);
+        last_rotated_at.is_none_or(|rotated_at| rotated_at < overdue_before)
+    }
+}
+
+/// Configuration for the read-only query console
+pub struct QueryConsoleConfig {
+    /// Whether the query console is enabled
+    pub enabled: bool,
+    /// Maximum time a query may run for
+    pub statement_timeout_ms: u64,
+    /// Maximum number of rows a query may return
+    pub max_rows: u32,
+}
+
+impl QueryConsoleConfig {
+    pub fn from_env() -> anyhow::Result<QueryConsoleConfig> {
+        let enabled = std::env::var("DOCBOX_MANAGER_QUERY_CONSOLE")
+            .ok()
+            .map(|value| value.parse::<bool>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_QUERY_CONSOLE value")?
+            .unwrap_or_default();
+        let statement_timeout_ms = std::env::var("DOCBOX_MANAGER_QUERY_TIMEOUT_MS")
+            .ok()
+            .map(|value| value.parse::<u64>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_QUERY_TIMEOUT_MS value")?
+            .unwrap_or(10_000);
+        let max_rows = std::env::var("DOCBOX_MANAGER_QUERY_MAX_ROWS")
+            .ok()
+            .map(|value| value.parse::<u32>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_QUERY_MAX_ROWS value")?
+            .unwrap_or(1_000);
+
+        Ok(QueryConsoleConfig {
+            enabled,
+            statement_timeout_ms,
+            max_rows,
+        })
+    }
+}
+
+/// Configuration for archiving tenants
+pub struct ArchiveConfig {
+    /// Default number of days an archived tenant is kept before it is purged
+    pub retention_days: i64,
+}
+
+impl ArchiveConfig {
+    pub fn from_env() -> anyhow::Result<ArchiveConfig> {
+        let retention_days = std::env::var("DOCBOX_MANAGER_ARCHIVE_RETENTION_DAYS")
+            .ok()
+            .map(|value| value.parse::<i64>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_ARCHIVE_RETENTION_DAYS value")?
+            .unwrap_or(30);
+
+        Ok(ArchiveConfig { retention_days })
+    }
+}
+
+/// Default maximum size of an uploaded export archive (10GB)
+const DEFAULT_MAX_IMPORT_SIZE: u64 = 10 * 1024 * 1024 * 1024;
+
+/// Configuration for importing tenants from export archives
+pub struct ImportConfig {
+    /// Maximum size in bytes of an uploaded export archive
+    pub max_size: u64,
+}
+
+impl ImportConfig {
+    pub fn from_env() -> anyhow::Result<ImportConfig> {
+        let max_size = std::env::var("DOCBOX_MANAGER_MAX_IMPORT_SIZE")
+            .ok()
+            .map(|value| value.parse::<u64>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_MAX_IMPORT_SIZE value")?
+            .unwrap_or(DEFAULT_MAX_IMPORT_SIZE);
+
+        Ok(ImportConfig { max_size })
+    }
+}
+
+/// Configuration for destructive operations used by development stacks
+pub struct TeardownConfig {
+    /// Whether the root database may be torn down, must only be enabled
+    /// on development and CI environments
+    pub enabled: bool,
+}
+
+impl TeardownConfig {
+    pub fn from_env() -> anyhow::Result<TeardownConfig> {
+        let enabled = std::env::var("DOCBOX_MANAGER_ALLOW_TEARDOWN")
+            .ok()
+            .map(|value| value.parse::<bool>())
+            .transpose()
+            .context("invalid DOCBOX_MANAGER_ALLOW_TEARDOWN value")?
+            .unwrap_or_default();
+
+        Ok(TeardownConfig { enabled })
+    }
+}
+
 #[derive(Clone, Deserialize)]
 pub struct DatabaseConfig {
     pub host: String,
@@ -30,6 +225,28 @@ pub struct DatabaseConfig {
     pub password: String,
 
     pub root_secret_name: String,
+
+    /// Maximum number of connections for each database pool
+    pub max_connections: Option<u32>,
+
+    /// Name of the database storing the manager's own metadata
+    pub manager_database_name: String,
+
+    #[serde(skip)]
+    pub tls: DatabaseTlsConfig,
+}
+
+/// TLS options applied to every Postgres connection the manager makes
+#[derive(Clone, Default)]
+pub struct DatabaseTlsConfig {
+    /// SSL mode to connect with (disable, allow, prefer, require, verify-ca, verify-full)
+    pub ssl_mode: Option<PgSslMode>,
+    /// Path to the root CA certificate used to verify the server
+    pub ssl_root_cert: Option<PathBuf>,
+    /// Path to the client certificate for certificate authentication
+    pub ssl_client_cert: Option<PathBuf>,
+    /// Path to the private key for the client certificate
+    pub ssl_client_key: Option<PathBuf>,
 }
 
 impl DatabaseConfig {
@@ -48,12 +265,171 @@ impl DatabaseConfig {
         let root_secret_name = std::env::var("DOCBOX_DB_CREDENTIAL_NAME")
             .unwrap_or_else(|_| "postgres/docbox/config".to_string());
 
+        let max_connections = std::env::var("DOCBOX_DATABASE_MAX_CONNECTIONS")
+            .ok()
+            .map(|value| value.parse::<u32>())
+            .transpose()
+            .context("invalid DOCBOX_DATABASE_MAX_CONNECTIONS value")?;
+
+        let manager_database_name = std::env::var("DOCBOX_MANAGER_DATABASE_NAME")
+            .unwrap_or_else(|_| "docbox_manager".to_string());
+
+        let tls = DatabaseTlsConfig::from_env()?;
+
         Ok(DatabaseConfig {
             host,
             port,
             username,
             password,
             root_secret_name,
+            max_connections,
+            manager_database_name,
+            tls,
         })
     }
+
+    /// Create the connection options for connecting to `database` as
+    /// the configured user
+    pub fn connect_options(&self, database: &str) -> PgConnectOptions {
+        self.connect_options_as(database, &self.username, &self.password)
+    }
+
+    /// Create the connection options for connecting to `database` as
+    /// a specific user
+    pub fn connect_options_as(
+        &self,
+        database: &str,
+        username: &str,
+        password: &str,
+    ) -> PgConnectOptions {
+        let options = PgConnectOptions::new()
+            .host(&self.host)
+            .port(self.port)
+            .username(username)
+            .password(password)
+            .database(database);
+
+        self.tls.apply(options)
+    }
+}
+
+impl DatabaseTlsConfig {
+    pub fn from_env() -> anyhow::Result<DatabaseTlsConfig> {
+        let ssl_mode = std::env::var("DOCBOX_DATABASE_SSL_MODE")
+            .ok()
+            .map(|value| PgSslMode::from_str(&value))
+            .transpose()
+            .context("invalid DOCBOX_DATABASE_SSL_MODE value")?;
+
+        let ssl_root_cert = std::env::var("DOCBOX_DATABASE_SSL_ROOT_CERT")
+            .ok()
+            .map(PathBuf::from);
+        let ssl_client_cert = std::env::var("DOCBOX_DATABASE_SSL_CLIENT_CERT")
+            .ok()
+            .map(PathBuf::from);
+        let ssl_client_key = std::env::var("DOCBOX_DATABASE_SSL_CLIENT_KEY")
+            .ok()
+            .map(PathBuf::from);
+
+        if ssl_client_cert.is_some() != ssl_client_key.is_some() {
+            anyhow::bail!(
+                "DOCBOX_DATABASE_SSL_CLIENT_CERT and DOCBOX_DATABASE_SSL_CLIENT_KEY must be set together"
+            );
+        }
+
+        Ok(DatabaseTlsConfig {
+            ssl_mode,
+            ssl_root_cert,
+            ssl_client_cert,
+            ssl_client_key,
+        })
+    }
+
+    /// Apply the TLS options to the provided connection options
+    pub fn apply(&self, mut options: PgConnectOptions) -> PgConnectOptions {
+        if let Some(ssl_mode) = self.ssl_mode {
+            options = options.ssl_mode(ssl_mode);
+        }
+
+        if let Some(ssl_root_cert) = &self.ssl_root_cert {
+            options = options.ssl_root_cert(ssl_root_cert);
+        }
+
+        if let Some(ssl_client_cert) = &self.ssl_client_cert {
+            options = options.ssl_client_cert(ssl_client_cert);
+        }
+
+        if let Some(ssl_client_key) = &self.ssl_client_key {
+            options = options.ssl_client_key(ssl_client_key);
+        }
+
+        options
+    }
+
+    /// Export the TLS options as the standard libpq environment variables.
+    ///
+    /// Connections that are created outside of the manager (i.e the
+    /// [DatabasePoolCache](docbox_database::DatabasePoolCache) used by the
+    /// search factory) build their options from these variables.
+    ///
+    /// # Safety
+    ///
+    /// Must be called before any other threads are spawned
+    pub unsafe fn export_env(&self) {
+        let ssl_mode = self.ssl_mode.map(|ssl_mode| match ssl_mode {
+            PgSslMode::Disable => "disable",
+            PgSslMode::Allow => "allow",
+            PgSslMode::Prefer => "prefer",
+            PgSslMode::Require => "require",
+            PgSslMode::VerifyCa => "verify-ca",
+            PgSslMode::VerifyFull => "verify-full",
+        });
+
+        let vars = [
+            ("PGSSLMODE", ssl_mode),
+            (
+                "PGSSLROOTCERT",
+                self.ssl_root_cert.as_ref().and_then(|value| value.to_str()),
+            ),
+            (
+                "PGSSLCERT",
+                self.ssl_client_cert.as_ref().and_then(|value| value.to_str()),
+            ),
+            (
+                "PGSSLKEY",
+                self.ssl_client_key.as_ref().and_then(|value| value.to_str()),
+            ),
+        ];
+
+        for (key, value) in vars
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?)))
+        {
+            // Safety: Caller guarantees no other threads are running
+            unsafe { std::env::set_var(key, value) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotation_policy_is_overdue() {
+        let policy = RotationPolicy { max_age_days: 90 };
+        let now = Utc::now();
+
+        assert!(policy.is_overdue(None));
+        assert!(!policy.is_overdue(Some(now)));
+        assert!(!policy.is_overdue(Some(now - TimeDelta::days(90) + TimeDelta::minutes(1))));
+        assert!(policy.is_overdue(Some(now - TimeDelta::days(90) - TimeDelta::minutes(1))));
+        assert!(policy.is_overdue(Some(now - TimeDelta::days(365))));
+    }
+
+    #[test]
+    fn test_rotation_policy_is_overdue_zero_age() {
+        let policy = RotationPolicy { max_age_days: 0 };
+        assert!(policy.is_overdue(Some(Utc::now() - TimeDelta::minutes(1))));
+    }
 }
@@ -0,0 +1,390 @@
+//! Read-only query console for tenant databases
+
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use futures::{StreamExt, TryStreamExt};
+use itertools::Itertools;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::{
+    Column, Connection, Decode, Executor, PgConnection, Postgres, Row, TypeInfo, ValueRef,
+    error::BoxDynError,
+    postgres::{PgRow, PgTypeInfo, PgTypeKind, PgValueRef, types::Oid},
+};
+use std::{fmt::Write, time::Instant};
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct QueryResult {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<serde_json::Value>>,
+    /// Whether more rows were available than the row limit allowed
+    pub truncated: bool,
+    pub duration_ms: u64,
+}
+
+/// Run `query` against `db` inside a read only transaction, returning at
+/// most `limit` rows. The query is aborted if it runs longer than
+/// `statement_timeout_ms`.
+///
+/// `db` should be connected as the tenant role rather than the manager
+/// user so the query cannot use privileged functions
+pub async fn run_read_only_query(
+    db: &mut PgConnection,
+    query: &str,
+    limit: u32,
+    statement_timeout_ms: u64,
+) -> anyhow::Result<QueryResult> {
+    let query = query.trim().trim_end_matches(';').trim();
+    if query.is_empty() {
+        anyhow::bail!("query is empty");
+    }
+
+    let start = Instant::now();
+    let mut t = db.begin().await?;
+
+    sqlx::query("SET TRANSACTION READ ONLY")
+        .execute(&mut *t)
+        .await?;
+    sqlx::query(&format!("SET LOCAL statement_timeout = {statement_timeout_ms}"))
+        .execute(&mut *t)
+        .await?;
+
+    // Describe the query first so columns are known even when there are no rows
+    let describe = (&mut *t).describe(query).await?;
+    let columns: Vec<String> = describe
+        .columns()
+        .iter()
+        .map(|column| column.name().to_string())
+        .collect();
+
+    // The query is run as given, fetching one extra row to detect truncation
+    let rows: Vec<PgRow> = sqlx::query(query)
+        .fetch(&mut *t)
+        .take(limit as usize + 1)
+        .try_collect()
+        .await?;
+
+    t.rollback().await?;
+
+    let truncated = rows.len() > limit as usize;
+    let rows = rows
+        .iter()
+        .take(limit as usize)
+        .map(|row| {
+            (0..row.len())
+                .map(|index| column_to_json(row, index))
+                .collect()
+        })
+        .collect::<anyhow::Result<Vec<Vec<serde_json::Value>>>>()?;
+
+    Ok(QueryResult {
+        columns,
+        rows,
+        truncated,
+        duration_ms: start.elapsed().as_millis() as u64,
+    })
+}
+
+/// Convert the value of the column at `index` to JSON, columns are read by
+/// position as a query may return several columns with the same name
+fn column_to_json(row: &PgRow, index: usize) -> anyhow::Result<serde_json::Value> {
+    let value = row.try_get_raw(index)?;
+    if value.is_null() {
+        return Ok(serde_json::Value::Null);
+    }
+
+    let type_info = value.type_info().into_owned();
+    value_to_json(value, &type_info)
+        .map_err(|error| anyhow::anyhow!(error))
+        .with_context(|| format!("failed to read column {}", row.column(index).name()))
+}
+
+fn value_to_json(
+    value: PgValueRef<'_>,
+    type_info: &PgTypeInfo,
+) -> Result<serde_json::Value, BoxDynError> {
+    match type_info.kind() {
+        PgTypeKind::Domain(base) => return value_to_json(value, base),
+        PgTypeKind::Enum(_) => return Ok(json!(value.as_str()?)),
+        _ => {}
+    }
+
+    let value = match type_info.name() {
+        "BOOL" => json!(decode::<bool>(value)?),
+        "INT2" => json!(decode::<i16>(value)?),
+        "INT4" => json!(decode::<i32>(value)?),
+        "INT8" => json!(decode::<i64>(value)?),
+        "OID" => json!(decode::<Oid>(value)?.0),
+        "FLOAT4" => json!(decode::<f32>(value)?),
+        "FLOAT8" => json!(decode::<f64>(value)?),
+        // Numeric values are kept as strings so no precision is lost
+        "NUMERIC" => json!(numeric_to_string(value.as_bytes()?)?),
+        "TEXT" | "VARCHAR" | "BPCHAR" | "NAME" | "CHAR" | "CITEXT" | "UNKNOWN" => {
+            json!(value.as_str()?)
+        }
+        "UUID" => json!(decode::<Uuid>(value)?),
+        "JSON" | "JSONB" => decode::<serde_json::Value>(value)?,
+        "TIMESTAMPTZ" => json!(decode::<DateTime<Utc>>(value)?),
+        "TIMESTAMP" => json!(decode::<NaiveDateTime>(value)?),
+        "DATE" => json!(decode::<NaiveDate>(value)?),
+        "TIME" => json!(decode::<NaiveTime>(value)?),
+        "BYTEA" => {
+            let bytes = value.as_bytes()?;
+            let mut hex = String::with_capacity(2 + bytes.len() * 2);
+            hex.push_str("\\x");
+            for byte in bytes {
+                _ = write!(hex, "{byte:02x}");
+            }
+            json!(hex)
+        }
+        name => {
+            return Err(format!(
+                "unsupported column type {name}, cast the column to text using ::text"
+            )
+            .into());
+        }
+    };
+
+    Ok(value)
+}
+
+fn decode<'r, T: Decode<'r, Postgres>>(value: PgValueRef<'r>) -> Result<T, BoxDynError> {
+    T::decode(value)
+}
+
+/// Format a NUMERIC in the binary wire format as a decimal string, the
+/// value is stored as base 10000 digits with the weight of the first digit
+fn numeric_to_string(bytes: &[u8]) -> Result<String, BoxDynError> {
+    let read = |index: usize| -> Result<u16, BoxDynError> {
+        bytes
+            .get(index * 2..index * 2 + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(|| "numeric value is truncated".into())
+    };
+
+    let digit_count = read(0)? as usize;
+    let weight = read(1)? as i16 as isize;
+    let sign = read(2)?;
+    let scale = read(3)? as usize;
+
+    match sign {
+        0xC000 => return Ok("NaN".to_string()),
+        0xD000 => return Ok("Infinity".to_string()),
+        0xF000 => return Ok("-Infinity".to_string()),
+        _ => {}
+    }
+
+    let digits = (0..digit_count)
+        .map(|index| read(4 + index))
+        .collect::<Result<Vec<u16>, BoxDynError>>()?;
+    // Digit with the weight `weight - index`, zero for digits not stored
+    let digit = |index: isize| -> u16 {
+        usize::try_from(index)
+            .ok()
+            .and_then(|index| digits.get(index).copied())
+            .unwrap_or(0)
+    };
+
+    let mut value = String::new();
+    if sign == 0x4000 {
+        value.push('-');
+    }
+
+    if weight < 0 {
+        value.push('0');
+    } else {
+        _ = write!(value, "{}", digit(0));
+        for index in 1..=weight {
+            _ = write!(value, "{:04}", digit(index));
+        }
+    }
+
+    if scale > 0 {
+        let mut fraction = String::new();
+        let mut index = weight + 1;
+        while fraction.len() < scale {
+            _ = write!(fraction, "{:04}", digit(index));
+            index += 1;
+        }
+        fraction.truncate(scale);
+
+        value.push('.');
+        value.push_str(&fraction);
+    }
+
+    Ok(value)
+}
+
+/// Render the query result as CSV
+pub fn to_csv(result: &QueryResult) -> String {
+    let header = result.columns.iter().map(|column| csv_cell(column)).join(",");
+    let rows = result.rows.iter().map(|row| {
+        row.iter()
+            .map(|value| match value {
+                serde_json::Value::Null => String::new(),
+                serde_json::Value::String(value) => csv_cell(value),
+                value => csv_cell(&value.to_string()),
+            })
+            .join(",")
+    });
+
+    std::iter::once(header)
+        .chain(rows)
+        .map(|line| format!("{line}\r\n"))
+        .collect()
+}
+
+fn csv_cell(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::PgPool;
+
+    #[sqlx::test(migrations = false)]
+    async fn test_run_read_only_query(pool: PgPool) {
+        let mut db = pool.acquire().await.unwrap();
+
+        // Trailing comments and duplicate column names are kept as given
+        let result = run_read_only_query(
+            &mut db,
+            "SELECT \"value\" AS \"a\", 'x' AS \"a\", NULL::INT AS \"b\"
+            FROM generate_series(1, 5) AS \"value\" -- trailing comment",
+            3,
+            1000,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(result.columns, ["a", "a", "b"]);
+        assert_eq!(
+            result.rows,
+            [
+                [json!(1), json!("x"), serde_json::Value::Null],
+                [json!(2), json!("x"), serde_json::Value::Null],
+                [json!(3), json!("x"), serde_json::Value::Null],
+            ]
+        );
+        assert!(result.truncated);
+
+        let result = run_read_only_query(&mut db, "SELECT 1 AS \"a\" WHERE FALSE;", 3, 1000)
+            .await
+            .unwrap();
+        assert_eq!(result.columns, ["a"]);
+        assert!(result.rows.is_empty());
+        assert!(!result.truncated);
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_run_read_only_query_types(pool: PgPool) {
+        let mut db = pool.acquire().await.unwrap();
+
+        let result = run_read_only_query(
+            &mut db,
+            "SELECT TRUE, 1::INT2, 1::INT8, 1.5::FLOAT8, 'text'::VARCHAR,
+                123.4500::NUMERIC, -0.00012::NUMERIC, 10000::NUMERIC, 0::NUMERIC,
+                'NaN'::NUMERIC, '00000000-0000-0000-0000-000000000001'::UUID,
+                '{\"a\": [1]}'::JSONB, '\\x0aff'::BYTEA, '2024-01-02'::DATE,
+                '2024-01-02 03:04:05+00'::TIMESTAMPTZ",
+            1,
+            1000,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            result.rows,
+            [[
+                json!(true),
+                json!(1),
+                json!(1),
+                json!(1.5),
+                json!("text"),
+                json!("123.4500"),
+                json!("-0.00012"),
+                json!("10000"),
+                json!("0"),
+                json!("NaN"),
+                json!("00000000-0000-0000-0000-000000000001"),
+                json!({"a": [1]}),
+                json!("\\x0aff"),
+                json!("2024-01-02"),
+                json!("2024-01-02T03:04:05Z"),
+            ]]
+        );
+
+        // Unsupported types must be cast to text
+        let error = run_read_only_query(&mut db, "SELECT '1 day'::INTERVAL", 1, 1000)
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("::text"));
+    }
+
+    #[sqlx::test(migrations = false)]
+    async fn test_run_read_only_query_rejects_writes(pool: PgPool) {
+        let mut db = pool.acquire().await.unwrap();
+
+        let error = run_read_only_query(&mut db, "CREATE TABLE \"console\" ()", 1, 1000)
+            .await
+            .unwrap_err();
+        assert!(format!("{error:#}").contains("read-only transaction"));
+
+        // Statements cannot be chained to escape the read only transaction
+        let result =
+            run_read_only_query(&mut db, "COMMIT; CREATE TABLE \"console\" ()", 1, 1000).await;
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_csv_cell() {
+        assert_eq!(csv_cell("plain"), "plain");
+        assert_eq!(csv_cell(""), "");
+        assert_eq!(csv_cell("a,b"), "\"a,b\"");
+        assert_eq!(csv_cell("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_cell("line\nbreak"), "\"line\nbreak\"");
+        assert_eq!(csv_cell("line\rbreak"), "\"line\rbreak\"");
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let result = QueryResult {
+            columns: vec![
+                "id".to_string(),
+                "name, full".to_string(),
+                "data".to_string(),
+            ],
+            rows: vec![
+                vec![json!(1), json!("Jacob"), json!({"a": "b,c"})],
+                vec![json!(2), serde_json::Value::Null, json!(true)],
+            ],
+            truncated: false,
+            duration_ms: 0,
+        };
+
+        assert_eq!(
+            to_csv(&result),
+            "id,\"name, full\",data\r\n\
+             1,Jacob,\"{\"\"a\"\":\"\"b,c\"\"}\"\r\n\
+             2,,true\r\n"
+        );
+    }
+
+    #[test]
+    fn test_to_csv_no_rows() {
+        let result = QueryResult {
+            columns: vec!["id".to_string()],
+            rows: vec![],
+            truncated: false,
+            duration_ms: 0,
+        };
+
+        assert_eq!(to_csv(&result), "id\r\n");
+    }
+}
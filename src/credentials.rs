@@ -0,0 +1,261 @@
+//! Rotation of database role credentials
+
+use crate::{
+    database::{DatabaseProvider, MAINTENANCE_DATABASE_NAME, quote_ident, quote_literal},
+    metadata::credentials::{CreateCredentialRotation, CredentialRotation},
+    secrets::{DatabaseSecret, get_database_secret, set_database_secret},
+};
+use anyhow::Context;
+use docbox_database::{ROOT_DATABASE_NAME, models::tenant::Tenant};
+use docbox_management::database::DatabaseProvider as _;
+use docbox_secrets::SecretManager;
+use rand::{Rng, distributions::Alphanumeric};
+use sqlx::{Connection, PgConnection, PgPool};
+
+/// Length of generated passwords
+const PASSWORD_LENGTH: usize = 32;
+
+/// Generate a new random password
+pub fn generate_password() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(PASSWORD_LENGTH)
+        .map(char::from)
+        .collect()
+}
+
+/// Rotate the password of the role whose credentials are stored in
+/// `secret_name`, the new credentials are verified by logging into
+/// `verify_database`.
+///
+/// The previous password is restored if any step fails so the role and
+/// secret are never left out of sync. Returns the new credentials
+pub async fn rotate_secret_credentials(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    secret_name: &str,
+    verify_database: &str,
+) -> anyhow::Result<DatabaseSecret> {
+    let db = db_provider
+        .connect(MAINTENANCE_DATABASE_NAME)
+        .await
+        .context("failed to connect to maintenance database")?;
+
+    // Hold a lock for the duration of the rotation to prevent concurrent
+    // rotations of the same secret leaving the role and secret out of sync
+    let mut lock = db.begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(format!("docbox-manager:rotate:{secret_name}"))
+        .execute(&mut *lock)
+        .await
+        .context("failed to acquire rotation lock")?;
+
+    // Read while holding the lock so the previous credentials are those
+    // left by any rotation that completed while waiting for the lock
+    let previous = get_database_secret(secrets, secret_name).await?;
+    let rotated = DatabaseSecret {
+        username: previous.username.clone(),
+        password: generate_password(),
+    };
+
+    set_role_password(&db, &rotated)
+        .await
+        .context("failed to update role password")?;
+
+    let result = async {
+        set_database_secret(secrets, secret_name, &rotated).await?;
+
+        if let Err(error) = verify_login(db_provider, verify_database, &rotated).await {
+            // Put the previous secret back before reverting the role
+            if let Err(error) = set_database_secret(secrets, secret_name, &previous).await {
+                tracing::error!(?error, %secret_name, "failed to revert secret");
+            }
+
+            return Err(error.context("failed to login with rotated credentials"));
+        }
+
+        anyhow::Ok(())
+    }
+    .await;
+
+    if let Err(error) = result {
+        if let Err(error) = set_role_password(&db, &previous).await {
+            tracing::error!(?error, %secret_name, "failed to revert role password");
+        }
+
+        return Err(error);
+    }
+
+    lock.rollback().await?;
+
+    tracing::info!(%secret_name, role = %rotated.username, "rotated database credentials");
+
+    Ok(rotated)
+}
+
+/// Rotate the credentials of the root database role and record the rotation
+pub async fn rotate_root_credentials(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    metadata: &PgPool,
+    root_secret_name: &str,
+) -> anyhow::Result<CredentialRotation> {
+    let rotated =
+        rotate_secret_credentials(db_provider, secrets, root_secret_name, ROOT_DATABASE_NAME)
+            .await?;
+
+    let rotation = CredentialRotation::create(
+        metadata,
+        CreateCredentialRotation {
+            env: None,
+            tenant_id: None,
+            secret_name: root_secret_name.to_string(),
+            role_name: rotated.username,
+        },
+    )
+    .await
+    .context("failed to record credential rotation")?;
+
+    Ok(rotation)
+}
+
+/// Rotate the credentials of the tenant database role and record the rotation
+pub async fn rotate_tenant_credentials(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    metadata: &PgPool,
+    tenant: &Tenant,
+) -> anyhow::Result<CredentialRotation> {
+    let rotated =
+        rotate_secret_credentials(db_provider, secrets, &tenant.db_secret_name, &tenant.db_name)
+            .await?;
+
+    let rotation = CredentialRotation::create(
+        metadata,
+        CreateCredentialRotation {
+            env: Some(tenant.env.clone()),
+            tenant_id: Some(tenant.id),
+            secret_name: tenant.db_secret_name.clone(),
+            role_name: rotated.username,
+        },
+    )
+    .await
+    .context("failed to record credential rotation")?;
+
+    Ok(rotation)
+}
+
+pub async fn set_role_password(db: &PgPool, secret: &DatabaseSecret) -> sqlx::Result<()> {
+    // Utility statements do not support bind parameters
+    sqlx::query(&format!(
+        "ALTER ROLE {} WITH PASSWORD {}",
+        quote_ident(&secret.username),
+        quote_literal(&secret.password)
+    ))
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+async fn verify_login(
+    db_provider: &DatabaseProvider,
+    database: &str,
+    secret: &DatabaseSecret,
+) -> anyhow::Result<()> {
+    let options =
+        db_provider
+            .config
+            .connect_options_as(database, &secret.username, &secret.password);
+    let mut conn = PgConnection::connect_with(&options).await?;
+    sqlx::query("SELECT 1").execute(&mut conn).await?;
+    conn.close().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::DatabaseConfig;
+    use docbox_secrets::{Secret, memory::MemorySecretManager};
+    use sqlx::postgres::{PgConnectOptions, PgPoolOptions};
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    #[sqlx::test(migrations = false)]
+    async fn test_rotate_secret_credentials_rollback(
+        pool_options: PgPoolOptions,
+        options: PgConnectOptions,
+    ) {
+        let pool = pool_options.connect_with(options.clone()).await.unwrap();
+
+        // Roles are shared by every database on the server so they are
+        // unique to the test. Passwords set by the manager role are stored
+        // as MD5 hashes so the stored password can be checked
+        let suffix = Uuid::new_v4().simple().to_string();
+        let manager = format!("rotation_manager_{suffix}");
+        let tenant = format!("rotation_tenant_{suffix}");
+
+        sqlx::raw_sql(&format!(
+            "CREATE ROLE {manager} SUPERUSER LOGIN PASSWORD 'manager';
+            ALTER ROLE {manager} SET password_encryption = 'md5';
+            CREATE ROLE {tenant} LOGIN PASSWORD 'previous';"
+        ))
+        .execute(&pool)
+        .await
+        .unwrap();
+
+        let db_provider = DatabaseProvider::new(DatabaseConfig {
+            host: options.get_host().to_string(),
+            port: options.get_port(),
+            username: manager.clone(),
+            password: "manager".to_string(),
+            root_secret_name: "root".to_string(),
+            max_connections: None,
+            manager_database_name: "docbox_manager".to_string(),
+            tls: Default::default(),
+        });
+
+        let previous = DatabaseSecret {
+            username: tenant.clone(),
+            password: "previous".to_string(),
+        };
+        let secrets = SecretManager::Memory(MemorySecretManager::new(
+            HashMap::from([(
+                "tenant".to_string(),
+                Secret::String(serde_json::to_string(&previous).unwrap()),
+            )]),
+            None,
+        ));
+
+        // Logging into the rotated credentials fails as the database does
+        // not exist, so the rotation is rolled back
+        let result = rotate_secret_credentials(
+            &db_provider,
+            &secrets,
+            "tenant",
+            &format!("missing_{suffix}"),
+        )
+        .await;
+        assert!(result.is_err());
+
+        db_provider.close_pool(MAINTENANCE_DATABASE_NAME).await;
+
+        let secret = get_database_secret(&secrets, "tenant").await.unwrap();
+        assert_eq!(secret.username, tenant);
+        assert_eq!(secret.password, "previous");
+
+        let reverted: bool = sqlx::query_scalar(
+            "SELECT rolpassword = 'md5' || md5('previous' || rolname) FROM pg_authid WHERE rolname = $1",
+        )
+        .bind(&tenant)
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+        assert!(reverted);
+
+        sqlx::raw_sql(&format!("DROP ROLE {tenant}; DROP ROLE {manager};"))
+            .execute(&pool)
+            .await
+            .unwrap();
+    }
+}
@@ -1,23 +1,300 @@
-use docbox_database::{DbResult, PgConnectOptions, PgPool};
-
 use crate::config::DatabaseConfig;
+use docbox_database::{DbPool, DbResult};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool, postgres::PgPoolOptions};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+/// Default maximum number of connections for each database pool
+pub const DEFAULT_MAX_CONNECTIONS: u32 = 10;
+
+/// Time a connection can sit idle in a pool before it is closed
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// Time a pool without any connections can go unused before it is
+/// removed from the cache
+const POOL_EXPIRY: Duration = Duration::from_secs(15 * 60);
 
 pub struct DatabaseProvider {
     pub config: DatabaseConfig,
+
+    /// Cached pools for each database
+    pools: Mutex<HashMap<String, CachedPool>>,
+}
+
+struct CachedPool {
+    pool: DbPool,
+    counters: Arc<PoolCounters>,
+    last_used: Instant,
+}
+
+/// Counters updated by the pool hooks, so every acquire made through the
+/// pool is counted rather than only those made by the provider
+#[derive(Default)]
+struct PoolCounters {
+    acquired: AtomicU64,
+    opened: AtomicU64,
+}
+
+/// Statistics about acquiring connections from a database pool
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AcquireStats {
+    /// Total number of connections acquired from the pool
+    pub acquired: u64,
+    /// Total number of new connections opened by the pool
+    pub opened: u64,
+}
+
+/// Point in time statistics for a database pool
+#[derive(Debug, Clone, Serialize)]
+pub struct PoolStats {
+    pub database: String,
+    /// Maximum number of connections the pool may open
+    pub max_connections: u32,
+    /// Total number of open connections
+    pub size: u32,
+    /// Number of connections sitting idle in the pool
+    pub idle: u32,
+    /// Number of connections currently in use
+    pub active: u32,
+    /// Whether the pool has been closed
+    pub closed: bool,
+    pub acquire: AcquireStats,
+}
+
+/// Server side connection counts for a single database from `pg_stat_activity`
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ServerActivity {
+    pub database: String,
+    pub total: i64,
+    pub active: i64,
+    pub idle: i64,
+    pub idle_in_transaction: i64,
+    pub waiting: i64,
+}
+
+/// Get the server side connection counts for every database on the server
+pub async fn get_server_activity(db: &PgPool) -> sqlx::Result<Vec<ServerActivity>> {
+    sqlx::query_as(
+        r#"
+        SELECT
+            "datname" AS "database",
+            COUNT(*) AS "total",
+            COUNT(*) FILTER (WHERE "state" = 'active') AS "active",
+            COUNT(*) FILTER (WHERE "state" = 'idle') AS "idle",
+            COUNT(*) FILTER (WHERE "state" LIKE 'idle in transaction%') AS "idle_in_transaction",
+            COUNT(*) FILTER (WHERE "wait_event_type" = 'Lock') AS "waiting"
+        FROM "pg_stat_activity"
+        WHERE "datname" IS NOT NULL
+        GROUP BY "datname"
+        ORDER BY "datname"
+        "#,
+    )
+    .fetch_all(db)
+    .await
+}
+
+impl DatabaseProvider {
+    pub fn new(config: DatabaseConfig) -> DatabaseProvider {
+        DatabaseProvider {
+            config,
+            pools: Default::default(),
+        }
+    }
+
+    fn max_connections(&self) -> u32 {
+        self.config
+            .max_connections
+            .unwrap_or(DEFAULT_MAX_CONNECTIONS)
+    }
+
+    /// Get the cached pool for `database` or create a new one, also
+    /// returns whether the pool was created
+    fn get_pool(&self, database: &str) -> (DbPool, bool) {
+        let mut pools = self.pools.lock().expect("lock poisoned");
+        let now = Instant::now();
+
+        // Pools for databases that are no longer used (i.e deleted tenants)
+        // are removed once their connections have been closed
+        pools.retain(|_, cached| {
+            !cached.pool.is_closed()
+                && (cached.pool.size() > 0 || now.duration_since(cached.last_used) < POOL_EXPIRY)
+        });
+
+        if let Some(cached) = pools.get_mut(database) {
+            cached.last_used = now;
+            return (cached.pool.clone(), false);
+        }
+
+        let counters = Arc::new(PoolCounters::default());
+        let pool = PgPoolOptions::new()
+            .max_connections(self.max_connections())
+            .idle_timeout(POOL_IDLE_TIMEOUT)
+            .after_connect({
+                let counters = counters.clone();
+                move |_, _| {
+                    counters.opened.fetch_add(1, Ordering::Relaxed);
+                    counters.acquired.fetch_add(1, Ordering::Relaxed);
+                    Box::pin(async { Ok(()) })
+                }
+            })
+            .before_acquire({
+                let counters = counters.clone();
+                move |_, _| {
+                    counters.acquired.fetch_add(1, Ordering::Relaxed);
+                    Box::pin(async { Ok(true) })
+                }
+            })
+            .connect_lazy_with(self.config.connect_options(database));
+
+        pools.insert(
+            database.to_string(),
+            CachedPool {
+                pool: pool.clone(),
+                counters,
+                last_used: now,
+            },
+        );
+        (pool, true)
+    }
+
+    /// Close and remove the cached pool for `database`, must be called
+    /// before dropping or replacing a database
+    pub async fn close_pool(&self, database: &str) {
+        let cached = self.pools.lock().expect("lock poisoned").remove(database);
+        if let Some(cached) = cached {
+            cached.pool.close().await;
+        }
+    }
+
+    /// Get the current statistics for every pool the manager has created
+    pub fn pool_stats(&self) -> Vec<PoolStats> {
+        let pools = self.pools.lock().expect("lock poisoned");
+        let max_connections = self.max_connections();
+
+        let mut pool_stats: Vec<PoolStats> = pools
+            .iter()
+            .map(|(database, CachedPool { pool, counters, .. })| {
+                let size = pool.size();
+                let idle = pool.num_idle() as u32;
+
+                PoolStats {
+                    database: database.clone(),
+                    max_connections,
+                    size,
+                    idle,
+                    active: size.saturating_sub(idle),
+                    closed: pool.is_closed(),
+                    acquire: AcquireStats {
+                        acquired: counters.acquired.load(Ordering::Relaxed),
+                        opened: counters.opened.load(Ordering::Relaxed),
+                    },
+                }
+            })
+            .collect();
+
+        pool_stats.sort_by(|a, b| a.database.cmp(&b.database));
+        pool_stats
+    }
 }
 
 impl docbox_management::database::DatabaseProvider for DatabaseProvider {
-    fn connect(
-        &self,
-        database: &str,
-    ) -> impl Future<Output = DbResult<docbox_database::DbPool>> + Send {
-        let options = PgConnectOptions::new()
-            .host(&self.config.host)
-            .port(self.config.port)
-            .username(&self.config.username)
-            .password(&self.config.password)
-            .database(database);
-
-        PgPool::connect_with(options)
+    async fn connect(&self, database: &str) -> DbResult<docbox_database::DbPool> {
+        let (pool, created) = self.get_pool(database);
+
+        // Connect when the pool is created to surface connection errors
+        // to the caller, the connection is kept in the pool for reuse
+        if created {
+            let result = pool.acquire().await;
+            if let Err(error) = result {
+                self.close_pool(database).await;
+                return Err(error);
+            }
+        }
+
+        Ok(pool)
+    }
+}
+
+/// Database used to connect when creating or dropping databases
+pub const MAINTENANCE_DATABASE_NAME: &str = "postgres";
+
+/// Postgres error code for attempting to create a database that exists
+const DUPLICATE_DATABASE_CODE: &str = "42P04";
+
+/// Check if a database named `name` exists on the server
+pub async fn database_exists(db: &PgPool, name: &str) -> sqlx::Result<bool> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM pg_database WHERE datname = $1)")
+        .bind(name)
+        .fetch_one(db)
+        .await
+}
+
+/// Create a new database named `name`
+pub async fn create_database(db: &PgPool, name: &str) -> sqlx::Result<()> {
+    sqlx::query(&format!("CREATE DATABASE {}", quote_ident(name)))
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Drop the database named `name`, terminating any open connections
+pub async fn drop_database(db: &PgPool, name: &str) -> sqlx::Result<()> {
+    sqlx::query(&format!(
+        "DROP DATABASE IF EXISTS {} WITH (FORCE)",
+        quote_ident(name)
+    ))
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Check if `error` was caused by creating a database that already exists
+pub fn is_duplicate_database(error: &sqlx::Error) -> bool {
+    matches!(
+        error,
+        sqlx::Error::Database(error) if error.code().as_deref() == Some(DUPLICATE_DATABASE_CODE)
+    )
+}
+
+/// Quote an identifier (database, role, table names) for use in a SQL
+/// statement where bind parameters are not supported
+pub fn quote_ident(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+/// Quote a string literal for use in a SQL statement where bind
+/// parameters are not supported
+pub fn quote_literal(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_ident() {
+        assert_eq!(quote_ident("docbox_tenant"), "\"docbox_tenant\"");
+        assert_eq!(quote_ident("Mixed Case"), "\"Mixed Case\"");
+        assert_eq!(quote_ident("a\"b"), "\"a\"\"b\"");
+        assert_eq!(
+            quote_ident("\"; DROP TABLE x; --"),
+            "\"\"\"; DROP TABLE x; --\""
+        );
+    }
+
+    #[test]
+    fn test_quote_literal() {
+        assert_eq!(quote_literal("password"), "'password'");
+        assert_eq!(quote_literal("it's"), "'it''s'");
+        assert_eq!(quote_literal("''"), "''''''");
+        assert_eq!(quote_literal("a\"b"), "'a\"b'");
     }
 }
@@ -0,0 +1,530 @@
+//! Schema drift detection for tenant databases
+//!
+//! Compares the tables, columns, indexes and constraints of tenant
+//! databases against a reference schema for the same set of applied
+//! migrations. The reference is either a scratch database freshly
+//! migrated to that version or the most common schema among the tenants
+//! at that version
+
+use crate::{
+    backup::schema::{NOT_EXTENSION_RELATION, USER_SCHEMA_FILTER},
+    database::{DatabaseProvider, MAINTENANCE_DATABASE_NAME, create_database, drop_database},
+    jobs::JobContext,
+    metadata::jobs::JobLogLevel,
+    root::{get_all_applied_migrations, get_tenant_applied_migrations},
+};
+use anyhow::Context;
+use docbox_database::{migrations::TENANT_MIGRATIONS, models::tenant::Tenant};
+use docbox_management::database::DatabaseProvider as _;
+use itertools::Itertools;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use std::collections::{BTreeMap, BTreeSet};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftReference {
+    /// Scratch database freshly migrated to the tenant version
+    #[default]
+    Scratch,
+    /// Most common schema among tenants at the same version
+    MostCommon,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum SchemaObjectKind {
+    Table,
+    Column,
+    Index,
+    Constraint,
+}
+
+/// Schema objects of a database along with their definitions
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaSnapshot {
+    objects: BTreeMap<(SchemaObjectKind, String), String>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum SchemaDifference {
+    /// Object in the reference schema is missing from the tenant
+    Missing {
+        kind: SchemaObjectKind,
+        name: String,
+        expected: String,
+    },
+    /// Object in the tenant is not in the reference schema
+    Unexpected {
+        kind: SchemaObjectKind,
+        name: String,
+        actual: String,
+    },
+    /// Object definition differs from the reference schema
+    Changed {
+        kind: SchemaObjectKind,
+        name: String,
+        expected: String,
+        actual: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct TenantDriftReport {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    #[serde(flatten)]
+    pub status: DriftStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DriftStatus {
+    /// Schema matches the reference
+    Clean,
+    /// Schema differs from the reference
+    Drifted { differences: Vec<SchemaDifference> },
+    /// Drift check could not be performed
+    Failed { error: String },
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FleetDriftReport {
+    pub reference: DriftReference,
+    pub clean: usize,
+    pub drifted: usize,
+    pub failed: usize,
+    pub tenants: Vec<TenantDriftReport>,
+}
+
+impl FleetDriftReport {
+    fn push(&mut self, report: TenantDriftReport) {
+        match &report.status {
+            DriftStatus::Clean => self.clean += 1,
+            DriftStatus::Drifted { .. } => self.drifted += 1,
+            DriftStatus::Failed { .. } => self.failed += 1,
+        }
+        self.tenants.push(report);
+    }
+}
+
+/// Progress of a fleet drift check running as a job
+#[derive(Debug, Serialize)]
+pub struct DriftProgress {
+    pub total: usize,
+    pub checked: usize,
+    pub clean: usize,
+    pub drifted: usize,
+    pub failed: usize,
+}
+
+/// Capture the schema objects of a database
+pub async fn snapshot_schema(db: &PgPool) -> anyhow::Result<SchemaSnapshot> {
+    let query = format!(
+        r#"
+        SELECT 'table', n.nspname || '.' || c.relname, c.relkind::TEXT
+        FROM pg_class c
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE c.relkind IN ('r', 'p', 'v', 'm') AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+
+        UNION ALL
+
+        SELECT 'column', n.nspname || '.' || c.relname || '.' || a.attname,
+            format_type(a.atttypid, a.atttypmod)
+                || CASE WHEN a.attnotnull THEN ' NOT NULL' ELSE '' END
+                || COALESCE(' DEFAULT ' || pg_get_expr(d.adbin, d.adrelid), '')
+        FROM pg_attribute a
+        JOIN pg_class c ON c.oid = a.attrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        LEFT JOIN pg_attrdef d ON d.adrelid = a.attrelid AND d.adnum = a.attnum
+        WHERE a.attnum > 0 AND NOT a.attisdropped
+          AND c.relkind IN ('r', 'p', 'v', 'm') AND {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+
+        UNION ALL
+
+        SELECT 'index', n.nspname || '.' || c.relname, pg_get_indexdef(c.oid)
+        FROM pg_index i
+        JOIN pg_class c ON c.oid = i.indexrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+
+        UNION ALL
+
+        SELECT 'constraint', n.nspname || '.' || c.relname || '.' || con.conname,
+            pg_get_constraintdef(con.oid)
+        FROM pg_constraint con
+        JOIN pg_class c ON c.oid = con.conrelid
+        JOIN pg_namespace n ON n.oid = c.relnamespace
+        WHERE {USER_SCHEMA_FILTER} AND {NOT_EXTENSION_RELATION}
+        "#
+    );
+
+    let rows: Vec<(SchemaObjectKind, String, String)> = sqlx::query_as(&query)
+        .fetch_all(db)
+        .await
+        .context("failed to read database schema")?;
+
+    Ok(SchemaSnapshot {
+        objects: rows
+            .into_iter()
+            .map(|(kind, name, definition)| ((kind, name), definition))
+            .collect(),
+    })
+}
+
+/// Get the differences between the `reference` schema and the `actual` schema
+pub fn diff_schema(reference: &SchemaSnapshot, actual: &SchemaSnapshot) -> Vec<SchemaDifference> {
+    let keys: BTreeSet<_> = reference
+        .objects
+        .keys()
+        .chain(actual.objects.keys())
+        .collect();
+
+    keys.into_iter()
+        .filter_map(|key| {
+            let (kind, name) = (key.0, key.1.clone());
+            match (reference.objects.get(key), actual.objects.get(key)) {
+                (Some(expected), None) => Some(SchemaDifference::Missing {
+                    kind,
+                    name,
+                    expected: expected.clone(),
+                }),
+                (None, Some(actual)) => Some(SchemaDifference::Unexpected {
+                    kind,
+                    name,
+                    actual: actual.clone(),
+                }),
+                (Some(expected), Some(actual)) if expected != actual => {
+                    Some(SchemaDifference::Changed {
+                        kind,
+                        name,
+                        expected: expected.clone(),
+                        actual: actual.clone(),
+                    })
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn drift_status(reference: &SchemaSnapshot, actual: &SchemaSnapshot) -> DriftStatus {
+    let differences = diff_schema(reference, actual);
+    if differences.is_empty() {
+        DriftStatus::Clean
+    } else {
+        DriftStatus::Drifted { differences }
+    }
+}
+
+/// Create a scratch database with `applied` migrations applied in
+/// migration order and capture its schema, the scratch database is
+/// dropped afterwards
+pub async fn scratch_reference(
+    db_provider: &DatabaseProvider,
+    applied: &BTreeSet<String>,
+) -> anyhow::Result<SchemaSnapshot> {
+    let db_name = format!("docbox_manager_drift_{}", Uuid::new_v4().simple());
+
+    let maintenance = db_provider
+        .connect(MAINTENANCE_DATABASE_NAME)
+        .await
+        .context("failed to connect to maintenance database")?;
+
+    create_database(&maintenance, &db_name)
+        .await
+        .context("failed to create scratch database")?;
+
+    let result = async {
+        let db = db_provider
+            .connect(&db_name)
+            .await
+            .context("failed to connect to scratch database")?;
+
+        for (name, sql) in TENANT_MIGRATIONS
+            .iter()
+            .filter(|(name, _)| applied.contains(*name))
+        {
+            sqlx::raw_sql(sql)
+                .execute(&db)
+                .await
+                .with_context(|| format!("failed to apply migration {name} to scratch database"))?;
+        }
+
+        snapshot_schema(&db).await
+    }
+    .await;
+
+    db_provider.close_pool(&db_name).await;
+    if let Err(error) = drop_database(&maintenance, &db_name).await {
+        tracing::error!(?error, %db_name, "failed to drop scratch database");
+    }
+
+    result
+}
+
+/// Check a single tenant for drift against a scratch database migrated
+/// to the same version
+pub async fn check_tenant_drift(
+    db_provider: &DatabaseProvider,
+    tenant: &Tenant,
+) -> anyhow::Result<TenantDriftReport> {
+    let applied: BTreeSet<String> =
+        get_tenant_applied_migrations(db_provider, &tenant.env, tenant.id)
+            .await?
+            .into_iter()
+            .map(|migration| migration.name)
+            .collect();
+
+    let reference = scratch_reference(db_provider, &applied).await?;
+    let actual = snapshot_tenant(db_provider, tenant).await?;
+
+    Ok(TenantDriftReport {
+        env: tenant.env.clone(),
+        tenant_id: tenant.id,
+        name: tenant.name.clone(),
+        status: drift_status(&reference, &actual),
+    })
+}
+
+async fn snapshot_tenant(
+    db_provider: &DatabaseProvider,
+    tenant: &Tenant,
+) -> anyhow::Result<SchemaSnapshot> {
+    let db = db_provider
+        .connect(&tenant.db_name)
+        .await
+        .context("failed to connect to tenant database")?;
+    snapshot_schema(&db).await
+}
+
+/// Check every tenant in `tenants` for drift, tenants are grouped by
+/// their applied migrations and compared against the reference for
+/// their group
+pub async fn check_fleet_drift(
+    db_provider: &DatabaseProvider,
+    tenants: Vec<Tenant>,
+    reference: DriftReference,
+    job: &JobContext,
+) -> anyhow::Result<FleetDriftReport> {
+    let applied = get_all_applied_migrations(db_provider).await?;
+    let total = tenants.len();
+
+    let groups = tenants.into_iter().into_group_map_by(|tenant| {
+        applied
+            .iter()
+            .filter(|migration| migration.env == tenant.env && migration.tenant_id == tenant.id)
+            .map(|migration| migration.name.clone())
+            .collect::<BTreeSet<String>>()
+    });
+
+    let mut report = FleetDriftReport {
+        reference,
+        ..Default::default()
+    };
+
+    for (applied, tenants) in groups {
+        if job.is_cancelled().await {
+            job.log(JobLogLevel::Warn, "drift check cancelled").await;
+            break;
+        }
+
+        job.log(
+            JobLogLevel::Info,
+            format!(
+                "checking {} tenants with {} applied migrations",
+                tenants.len(),
+                applied.len()
+            ),
+        )
+        .await;
+
+        let mut snapshots = Vec::with_capacity(tenants.len());
+        for tenant in tenants {
+            let snapshot = snapshot_tenant(db_provider, &tenant).await;
+            snapshots.push((tenant, snapshot));
+        }
+
+        let reference_schema = match reference {
+            DriftReference::Scratch => scratch_reference(db_provider, &applied).await,
+            DriftReference::MostCommon => most_common_schema(&snapshots)
+                .context("no tenant schemas could be read"),
+        };
+
+        for (tenant, snapshot) in snapshots {
+            let status = match (&reference_schema, snapshot) {
+                (Ok(reference), Ok(actual)) => drift_status(reference, &actual),
+                (Err(error), _) => DriftStatus::Failed {
+                    error: format!("failed to create reference schema: {error:#}"),
+                },
+                (_, Err(error)) => DriftStatus::Failed {
+                    error: format!("{error:#}"),
+                },
+            };
+
+            if let DriftStatus::Drifted { differences } = &status {
+                job.log(
+                    JobLogLevel::Warn,
+                    format!(
+                        "{} ({}/{}): {} differences",
+                        tenant.name,
+                        tenant.env,
+                        tenant.id,
+                        differences.len()
+                    ),
+                )
+                .await;
+            }
+
+            report.push(TenantDriftReport {
+                env: tenant.env,
+                tenant_id: tenant.id,
+                name: tenant.name,
+                status,
+            });
+        }
+
+        job.set_progress(&DriftProgress {
+            total,
+            checked: report.tenants.len(),
+            clean: report.clean,
+            drifted: report.drifted,
+            failed: report.failed,
+        })
+        .await;
+    }
+
+    Ok(report)
+}
+
+/// Most common schema among the successfully read snapshots
+fn most_common_schema(
+    snapshots: &[(Tenant, anyhow::Result<SchemaSnapshot>)],
+) -> Option<SchemaSnapshot> {
+    let schemas: Vec<&SchemaSnapshot> = snapshots
+        .iter()
+        .filter_map(|(_, snapshot)| snapshot.as_ref().ok())
+        .collect();
+
+    schemas
+        .iter()
+        .max_by_key(|schema| schemas.iter().filter(|other| other == schema).count())
+        .map(|schema| (*schema).clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn snapshot(objects: &[(SchemaObjectKind, &str, &str)]) -> SchemaSnapshot {
+        SchemaSnapshot {
+            objects: objects
+                .iter()
+                .map(|(kind, name, definition)| ((*kind, name.to_string()), definition.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_diff_schema_identical() {
+        let reference = snapshot(&[
+            (SchemaObjectKind::Table, "public.docbox_files", "r"),
+            (
+                SchemaObjectKind::Column,
+                "public.docbox_files.id",
+                "uuid NOT NULL",
+            ),
+        ]);
+
+        assert!(diff_schema(&reference, &reference.clone()).is_empty());
+        assert!(matches!(
+            drift_status(&reference, &reference),
+            DriftStatus::Clean
+        ));
+    }
+
+    #[test]
+    fn test_diff_schema_differences() {
+        let reference = snapshot(&[
+            (SchemaObjectKind::Table, "public.docbox_files", "r"),
+            (
+                SchemaObjectKind::Column,
+                "public.docbox_files.id",
+                "uuid NOT NULL",
+            ),
+            (
+                SchemaObjectKind::Column,
+                "public.docbox_files.name",
+                "text NOT NULL",
+            ),
+        ]);
+        let actual = snapshot(&[
+            (SchemaObjectKind::Table, "public.docbox_files", "r"),
+            (SchemaObjectKind::Column, "public.docbox_files.id", "uuid"),
+            (
+                SchemaObjectKind::Index,
+                "public.docbox_files_extra",
+                "CREATE INDEX docbox_files_extra ON public.docbox_files USING btree (id)",
+            ),
+        ]);
+
+        let differences = diff_schema(&reference, &actual);
+        assert_eq!(
+            serde_json::to_value(&differences).unwrap(),
+            json!([
+                {
+                    "change": "changed",
+                    "kind": "column",
+                    "name": "public.docbox_files.id",
+                    "expected": "uuid NOT NULL",
+                    "actual": "uuid",
+                },
+                {
+                    "change": "missing",
+                    "kind": "column",
+                    "name": "public.docbox_files.name",
+                    "expected": "text NOT NULL",
+                },
+                {
+                    "change": "unexpected",
+                    "kind": "index",
+                    "name": "public.docbox_files_extra",
+                    "actual": "CREATE INDEX docbox_files_extra ON public.docbox_files USING btree (id)",
+                },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_diff_schema_same_name_different_kind() {
+        // Objects are keyed by both their kind and name
+        let reference = snapshot(&[(SchemaObjectKind::Index, "public.docbox_files_pkey", "a")]);
+        let actual = snapshot(&[(
+            SchemaObjectKind::Constraint,
+            "public.docbox_files_pkey",
+            "a",
+        )]);
+
+        let differences = diff_schema(&reference, &actual);
+        assert_eq!(differences.len(), 2);
+        assert!(matches!(
+            &differences[0],
+            SchemaDifference::Missing {
+                kind: SchemaObjectKind::Index,
+                ..
+            }
+        ));
+        assert!(matches!(
+            &differences[1],
+            SchemaDifference::Unexpected {
+                kind: SchemaObjectKind::Constraint,
+                ..
+            }
+        ));
+    }
+}
@@ -9,7 +9,6 @@ use std::{
     fmt::{Debug, Display},
 };
 use thiserror::Error;
-use tracing::error;
 
 /// Type alias for dynamic error handling and JSON responses
 pub type HttpResult<T> = Result<Json<T>, DynHttpError>;
@@ -118,16 +117,176 @@ pub struct HttpErrorResponse {
 }
 
 #[derive(Debug, Error)]
-pub enum HttpCommonError {
-    #[allow(unused)]
-    #[error("internal server error")]
-    ServerError,
+pub enum QueryConsoleError {
+    #[error("the query console is disabled")]
+    Disabled,
+
+    #[error("query failed: {0}")]
+    Query(String),
+}
+
+impl HttpError for QueryConsoleError {
+    fn status(&self) -> axum::http::StatusCode {
+        match self {
+            QueryConsoleError::Disabled => StatusCode::FORBIDDEN,
+            QueryConsoleError::Query(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum JobError {
+    #[error("job not found")]
+    NotFound,
+
+    #[error("job has already finished")]
+    Finished,
+}
+
+impl HttpError for JobError {
+    fn status(&self) -> axum::http::StatusCode {
+        match self {
+            JobError::NotFound => StatusCode::NOT_FOUND,
+            JobError::Finished => StatusCode::CONFLICT,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RolloutError {
+    #[error("wave size must be at least 1")]
+    WaveSize,
+
+    #[error("concurrency must be between 1 and {}", crate::rollout::MAX_CONCURRENCY)]
+    Concurrency,
+
+    #[error("max failure rate must be between 0 and 1")]
+    FailureRate,
+}
+
+impl HttpError for RolloutError {
+    fn status(&self) -> axum::http::StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum RollbackError {
+    #[error("rollback must be confirmed, data written since the backup will be lost")]
+    NotConfirmed,
+
+    #[error("unknown migration {0}")]
+    UnknownMigration(String),
+}
+
+impl HttpError for RollbackError {
+    fn status(&self) -> axum::http::StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ScheduleError {
+    #[error("schedule not found")]
+    NotFound,
+
+    #[error("schedule has already started")]
+    AlreadyStarted,
+
+    #[error("maintenance window must end after it starts")]
+    InvalidWindow,
+
+    #[error("maintenance window has already ended")]
+    WindowEnded,
+}
+
+impl HttpError for ScheduleError {
+    fn status(&self) -> axum::http::StatusCode {
+        match self {
+            ScheduleError::NotFound => StatusCode::NOT_FOUND,
+            ScheduleError::AlreadyStarted => StatusCode::CONFLICT,
+            ScheduleError::InvalidWindow | ScheduleError::WindowEnded => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum InitializeError {
+    #[error("secret {0} already exists, set overwrite_secret to replace it")]
+    SecretExists(String),
+
+    #[error("role name must only contain lowercase letters, digits and underscores")]
+    InvalidRoleName,
+}
+
+impl HttpError for InitializeError {
+    fn status(&self) -> axum::http::StatusCode {
+        match self {
+            InitializeError::SecretExists(_) => StatusCode::CONFLICT,
+            InitializeError::InvalidRoleName => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TeardownError {
+    #[error("teardown is disabled (set DOCBOX_MANAGER_ALLOW_TEARDOWN to enable it)")]
+    Disabled,
+
+    #[error("teardown must be confirmed, all tenants and the root database will be destroyed")]
+    NotConfirmed,
+}
+
+impl HttpError for TeardownError {
+    fn status(&self) -> axum::http::StatusCode {
+        match self {
+            TeardownError::Disabled => StatusCode::FORBIDDEN,
+            TeardownError::NotConfirmed => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum TenantError {
+    #[error("tenant name must not be empty")]
+    EmptyName,
+
+    #[error("no tenant attributes to update")]
+    NoChanges,
+
+    #[error("confirmation does not match the tenant name")]
+    NameMismatch,
+
+    #[error("tenant is archived")]
+    Archived,
+
+    #[error("tenant is not archived")]
+    NotArchived,
+
+    #[error("archived tenant is being purged")]
+    Purging,
+
+    #[error("retention period must not be negative")]
+    InvalidRetention,
+
+    #[error("invalid export archive: {0}")]
+    InvalidExport(String),
+
+    #[error("export archive is larger than the maximum import size of {0} bytes")]
+    ExportTooLarge(u64),
 }
 
-impl HttpError for HttpCommonError {
+impl HttpError for TenantError {
     fn status(&self) -> axum::http::StatusCode {
         match self {
-            HttpCommonError::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            TenantError::EmptyName
+            | TenantError::NoChanges
+            | TenantError::NameMismatch
+            | TenantError::InvalidRetention
+            | TenantError::InvalidExport(_) => StatusCode::BAD_REQUEST,
+            TenantError::Archived | TenantError::Purging => StatusCode::CONFLICT,
+            TenantError::NotArchived => StatusCode::NOT_FOUND,
+            TenantError::ExportTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
         }
     }
 }
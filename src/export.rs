@@ -0,0 +1,420 @@
+//! Portable tenant export archives
+//!
+//! Exports are gzip compressed tar archives containing:
+//! - `manifest.json` describing the tenant and the archive contents
+//! - `database.tar.gz` a backup archive of the tenant database (See [crate::backup::archive])
+//! - `objects/{index}` the contents of each storage object
+//!
+//! Every file is listed in the manifest with a checksum that is verified
+//! on import. Archives are built in temporary files like backup archives
+//! so neither exports nor imports hold the archive in memory
+
+use crate::{
+    backup::{
+        archive::{ArchiveContents, ArchiveWriter, SpooledFile},
+        export_database,
+    },
+    clone::{
+        CopiedTable, get_storage_objects, handle_created_tenant_failure, rebuild_search_index,
+        replace_tenant_database,
+    },
+    database::DatabaseProvider,
+    jobs::JobContext,
+    metadata::jobs::JobLogLevel,
+    storage::{bucket_storage_layer, download_spooled, upload_spooled},
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use docbox_database::models::tenant::Tenant;
+use docbox_management::{
+    database::DatabaseProvider as _, tenant::create_tenant::CreateTenantConfig,
+};
+use docbox_search::SearchIndexFactory;
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use uuid::Uuid;
+
+/// Current version of the export format
+pub const EXPORT_FORMAT_VERSION: u32 = 1;
+
+pub const EXPORT_MANIFEST_PATH: &str = "manifest.json";
+pub const EXPORT_DATABASE_PATH: &str = "database.tar.gz";
+
+/// Content type of export archives
+pub const EXPORT_CONTENT_TYPE: &str = "application/gzip";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportManifest {
+    pub format_version: u32,
+    pub export_id: Uuid,
+    pub created_at: DateTime<Utc>,
+    pub tenant: ExportedTenant,
+    pub database: ExportedFile,
+    pub objects: Vec<ExportedObject>,
+}
+
+/// Tenant the export was taken from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedTenant {
+    pub env: String,
+    pub id: Uuid,
+    pub name: String,
+    pub db_name: String,
+    pub s3_name: String,
+    pub os_index_name: String,
+    pub event_queue_url: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedFile {
+    /// Path to the file within the archive
+    pub path: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportedObject {
+    /// Key of the object within the tenant bucket
+    pub file_key: String,
+    pub mime: String,
+    #[serde(flatten)]
+    pub file: ExportedFile,
+}
+
+/// Export archive stored in a bucket
+#[derive(Debug, Serialize)]
+pub struct StoredExport {
+    pub export_id: Uuid,
+    pub bucket: String,
+    pub key: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportOutcome {
+    pub export_id: Uuid,
+    pub source: ExportedTenant,
+    pub tenant: Tenant,
+    pub tables: Vec<CopiedTable>,
+    pub objects_imported: usize,
+}
+
+/// Path within the archive to store the storage object at `index`
+fn object_path(index: usize) -> String {
+    format!("objects/{index}")
+}
+
+/// Key within the backup bucket to store an export
+fn export_key(env: &str, tenant_id: Uuid, export_id: Uuid) -> String {
+    format!("exports/{env}/{tenant_id}/{export_id}.tar.gz")
+}
+
+/// Key within the backup bucket to store an uploaded archive awaiting import
+pub fn import_key(upload_id: Uuid) -> String {
+    format!("imports/{upload_id}.tar.gz")
+}
+
+/// Unpacked export archive with every file verified against the manifest
+pub struct ExportArchive {
+    pub manifest: ExportManifest,
+    /// Backup archive of the tenant database
+    pub database: File,
+    pub contents: ArchiveContents,
+}
+
+fn exported_file(path: String, spooled: &SpooledFile) -> ExportedFile {
+    ExportedFile {
+        path,
+        size: spooled.size,
+        sha256: spooled.sha256.clone(),
+    }
+}
+
+/// Export the database and storage objects of `tenant` into an archive,
+/// storage objects are streamed into the archive file one at a time
+pub async fn export_tenant(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    tenant: &Tenant,
+    job: Option<&JobContext>,
+) -> anyhow::Result<(Uuid, SpooledFile)> {
+    let export_id = Uuid::new_v4();
+    tracing::info!(?tenant, %export_id, "exporting tenant");
+
+    let pending_migrations =
+        docbox_management::tenant::get_pending_tenant_migrations::get_pending_tenant_migrations(
+            db_provider,
+            tenant,
+        )
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let db = db_provider
+        .connect(&tenant.db_name)
+        .await
+        .context("failed to connect to tenant database")?;
+
+    let database = export_database(&db, tenant, export_id, pending_migrations).await?;
+    let database_file = exported_file(EXPORT_DATABASE_PATH.to_string(), &database);
+
+    // Compressing the archive is blocking work, the writer is moved to a
+    // blocking thread for each file added
+    let mut writer = tokio::task::spawn_blocking(move || {
+        let mut writer = ArchiveWriter::new()?;
+        writer.add_spooled(EXPORT_DATABASE_PATH, database)?;
+        anyhow::Ok(writer)
+    })
+    .await??;
+
+    let objects = get_storage_objects(&db)
+        .await
+        .context("failed to get storage objects")?;
+
+    if let Some(job) = job {
+        job.log(
+            JobLogLevel::Info,
+            format!("exporting {} storage objects", objects.len()),
+        )
+        .await;
+    }
+
+    let storage = storage_factory.create_storage_layer(tenant);
+    let mut exported = Vec::with_capacity(objects.len());
+
+    for (index, object) in objects.into_iter().enumerate() {
+        let cancelled = match job {
+            Some(job) => job.is_cancelled().await,
+            None => false,
+        };
+        if cancelled {
+            anyhow::bail!("export cancelled");
+        }
+
+        let data = download_spooled(&storage, &object.file_key)
+            .await
+            .with_context(|| format!("failed to read object {}", object.file_key))?;
+
+        let path = object_path(index);
+        let file = exported_file(path.clone(), &data);
+        writer = tokio::task::spawn_blocking(move || {
+            writer.add_spooled(&path, data)?;
+            anyhow::Ok(writer)
+        })
+        .await??;
+
+        exported.push(ExportedObject {
+            file_key: object.file_key,
+            mime: object.mime,
+            file,
+        });
+    }
+
+    let manifest = ExportManifest {
+        format_version: EXPORT_FORMAT_VERSION,
+        export_id,
+        created_at: Utc::now(),
+        tenant: ExportedTenant {
+            env: tenant.env.clone(),
+            id: tenant.id,
+            name: tenant.name.clone(),
+            db_name: tenant.db_name.clone(),
+            s3_name: tenant.s3_name.clone(),
+            os_index_name: tenant.os_index_name.clone(),
+            event_queue_url: tenant.event_queue_url.clone(),
+        },
+        database: database_file,
+        objects: exported,
+    };
+
+    let archive = tokio::task::spawn_blocking(move || {
+        let manifest = serde_json::to_vec_pretty(&manifest)?;
+        writer.add_file(EXPORT_MANIFEST_PATH, &manifest)?;
+        anyhow::Ok(writer.finish()?)
+    })
+    .await??;
+    tracing::info!(?tenant, %export_id, size = archive.size, "tenant export complete");
+
+    Ok((export_id, archive))
+}
+
+/// Export `tenant` into an archive stored in `bucket`
+pub async fn store_tenant_export(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    bucket: &str,
+    tenant: &Tenant,
+    job: &JobContext,
+) -> anyhow::Result<StoredExport> {
+    let (export_id, archive) =
+        export_tenant(db_provider, storage_factory, tenant, Some(job)).await?;
+
+    let size = archive.size;
+    let sha256 = archive.sha256.clone();
+    let key = export_key(&tenant.env, tenant.id, export_id);
+
+    job.log(
+        JobLogLevel::Info,
+        format!("uploading export to {bucket}/{key}"),
+    )
+    .await;
+
+    upload_spooled(
+        &bucket_storage_layer(storage_factory, bucket),
+        &key,
+        EXPORT_CONTENT_TYPE,
+        archive,
+    )
+    .await
+    .context("failed to upload export archive")?;
+
+    Ok(StoredExport {
+        export_id,
+        bucket: bucket.to_string(),
+        key,
+        size,
+        sha256,
+    })
+}
+
+/// Read an export archive and its manifest, ensuring the archive format
+/// is supported and verifying the checksum of every file in the archive
+pub async fn read_export_archive(archive: File) -> anyhow::Result<ExportArchive> {
+    tokio::task::spawn_blocking(move || {
+        let mut contents =
+            ArchiveContents::unpack(archive).context("failed to read export archive")?;
+        let manifest: ExportManifest =
+            serde_json::from_slice(&contents.take(EXPORT_MANIFEST_PATH)?)
+                .context("invalid export manifest")?;
+
+        if manifest.format_version != EXPORT_FORMAT_VERSION {
+            anyhow::bail!(
+                "unsupported export format version {}",
+                manifest.format_version
+            );
+        }
+
+        let database =
+            contents.open_verified(&manifest.database.path, &manifest.database.sha256)?;
+        for object in &manifest.objects {
+            contents.verify(&object.file.path, &object.file.sha256)?;
+        }
+
+        Ok(ExportArchive {
+            manifest,
+            database,
+            contents,
+        })
+    })
+    .await?
+}
+
+/// Create a new tenant from `target` with the database and storage
+/// objects from an export archive then rebuild its search index
+// The docbox services are passed individually as they are to create_tenant
+#[allow(clippy::too_many_arguments)]
+pub async fn import_tenant(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    search_factory: &SearchIndexFactory,
+    secrets: &SecretManager,
+    archive: File,
+    target: CreateTenantConfig,
+    keep_on_failure: bool,
+    job: &JobContext,
+) -> anyhow::Result<ImportOutcome> {
+    // The whole archive is verified before creating any resources
+    let ExportArchive {
+        manifest,
+        database,
+        mut contents,
+    } = read_export_archive(archive).await?;
+
+    job.log(
+        JobLogLevel::Info,
+        format!(
+            "importing export {} of tenant {}/{} as {}/{}",
+            manifest.export_id, manifest.tenant.env, manifest.tenant.id, target.env, target.id
+        ),
+    )
+    .await;
+
+    let tenant = docbox_management::tenant::create_tenant::create_tenant(
+        db_provider,
+        search_factory,
+        storage_factory,
+        secrets,
+        target,
+    )
+    .await
+    .map_err(anyhow::Error::new)?;
+
+    // The created tenant is deleted when a later stage fails unless it is
+    // kept for inspection (See [handle_created_tenant_failure])
+    let result = async {
+        job.log(JobLogLevel::Info, "restoring database").await;
+        let tables = replace_tenant_database(db_provider, secrets, &tenant, database).await?;
+
+        job.log(
+            JobLogLevel::Info,
+            format!("uploading {} storage objects", manifest.objects.len()),
+        )
+        .await;
+
+        let storage = storage_factory.create_storage_layer(&tenant);
+        let objects_imported = manifest.objects.len();
+
+        for object in &manifest.objects {
+            if job.is_cancelled().await {
+                anyhow::bail!("import cancelled");
+            }
+
+            let data = SpooledFile {
+                file: contents.open(&object.file.path)?,
+                size: object.file.size,
+                sha256: object.file.sha256.clone(),
+            };
+
+            upload_spooled(&storage, &object.file_key, &object.mime, data)
+                .await
+                .with_context(|| format!("failed to upload object {}", object.file_key))?;
+        }
+
+        job.log(JobLogLevel::Info, "rebuilding search index").await;
+        let tenant = rebuild_search_index(
+            db_provider,
+            storage_factory,
+            search_factory,
+            &tenant.env,
+            tenant.id,
+        )
+        .await?;
+
+        anyhow::Ok(ImportOutcome {
+            export_id: manifest.export_id,
+            source: manifest.tenant.clone(),
+            tenant,
+            tables,
+            objects_imported,
+        })
+    }
+    .await;
+
+    match result {
+        Ok(outcome) => Ok(outcome),
+        Err(error) => Err(handle_created_tenant_failure(
+            db_provider,
+            storage_factory,
+            search_factory,
+            secrets,
+            &tenant,
+            keep_on_failure,
+            job,
+            error.context("import failed"),
+        )
+        .await),
+    }
+}
@@ -0,0 +1,236 @@
+//! Configurable initialization of the root database
+//!
+//! Initialization is planned against the current state of the server
+//! before anything is changed, steps that are already complete are
+//! skipped so the plan can be returned as a dry-run or executed. The
+//! steps are performed by [docbox_management::root::initialize] so the
+//! root database, role and secret match what docbox expects
+
+use crate::{
+    credentials::set_role_password,
+    database::{DatabaseProvider, MAINTENANCE_DATABASE_NAME, database_exists},
+    secrets::{DatabaseSecret, secret_exists},
+};
+use anyhow::Context;
+use docbox_database::ROOT_DATABASE_NAME;
+use docbox_management::{
+    database::DatabaseProvider as _,
+    password::random_password,
+    root::{
+        initialize::{
+            initialize, initialize_root_database, initialize_root_role, initialize_root_secret,
+        },
+        migrate_root::migrate_root,
+    },
+};
+use docbox_secrets::SecretManager;
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Role created by [docbox_management::root::initialize::initialize] to
+/// access the root database
+pub const DEFAULT_ROOT_ROLE_NAME: &str = "docbox_config_api";
+
+/// Length of the generated root role password
+const ROOT_PASSWORD_LENGTH: usize = 30;
+
+/// Options for initializing the root database
+#[derive(Debug, Clone)]
+pub struct InitializeOptions {
+    pub role_name: String,
+    /// Secret the root credentials are stored in
+    pub secret_name: String,
+    /// Replace the credentials stored in an existing secret
+    pub overwrite_secret: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InitializeStepKind {
+    CreateDatabase,
+    CreateRole,
+    SetRolePassword,
+    StoreSecret,
+    MigrateRoot,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitializeStep {
+    pub kind: InitializeStepKind,
+    pub description: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct InitializePlan {
+    pub root_database_name: String,
+    pub role_name: String,
+    pub secret_name: String,
+    /// Whether the secret already holds credentials
+    pub secret_exists: bool,
+    /// Steps that will be performed in order
+    pub steps: Vec<InitializeStep>,
+    /// Steps that are skipped as they are already complete
+    pub skipped: Vec<InitializeStep>,
+}
+
+impl InitializePlan {
+    /// Whether the plan is the full default initialization performed by
+    /// [docbox_management::root::initialize::initialize]
+    fn is_default(&self) -> bool {
+        self.role_name == DEFAULT_ROOT_ROLE_NAME
+            && self
+                .steps
+                .iter()
+                .any(|step| step.kind == InitializeStepKind::CreateRole)
+    }
+}
+
+/// Whether `name` can be used as the root role name, the role name is
+/// used unquoted when the role is created
+pub fn is_valid_role_name(name: &str) -> bool {
+    name.starts_with(|c: char| c.is_ascii_lowercase() || c == '_')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        && name.len() <= 63
+}
+
+/// Check if a role named `name` exists on the server
+async fn role_exists(db: &PgPool, name: &str) -> sqlx::Result<bool> {
+    sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM pg_roles WHERE rolname = $1)")
+        .bind(name)
+        .fetch_one(db)
+        .await
+}
+
+/// Plan the steps required to initialize the root database with `options`
+/// based on the current state of the server and secret manager
+pub async fn plan_initialize(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    options: &InitializeOptions,
+) -> anyhow::Result<InitializePlan> {
+    let InitializeOptions {
+        role_name,
+        secret_name,
+        ..
+    } = options;
+
+    let has_secret = secret_exists(secrets, secret_name).await?;
+
+    let db = db_provider
+        .connect(MAINTENANCE_DATABASE_NAME)
+        .await
+        .context("failed to connect to maintenance database")?;
+
+    let has_database = database_exists(&db, ROOT_DATABASE_NAME).await?;
+    let has_role = role_exists(&db, role_name).await?;
+
+    let mut steps = Vec::new();
+    let mut skipped = Vec::new();
+
+    let create_database = InitializeStep {
+        kind: InitializeStepKind::CreateDatabase,
+        description: format!("create database {ROOT_DATABASE_NAME}"),
+    };
+    if has_database {
+        skipped.push(create_database);
+    } else {
+        steps.push(create_database);
+    }
+
+    steps.push(if has_role {
+        InitializeStep {
+            kind: InitializeStepKind::SetRolePassword,
+            description: format!("set a new password for existing role {role_name}"),
+        }
+    } else {
+        InitializeStep {
+            kind: InitializeStepKind::CreateRole,
+            description: format!("create role {role_name} with access to {ROOT_DATABASE_NAME}"),
+        }
+    });
+
+    steps.push(InitializeStep {
+        kind: InitializeStepKind::StoreSecret,
+        description: if has_secret {
+            format!("overwrite the credentials stored in secret {secret_name}")
+        } else {
+            format!("store the credentials in secret {secret_name}")
+        },
+    });
+
+    steps.push(InitializeStep {
+        kind: InitializeStepKind::MigrateRoot,
+        description: format!("apply pending root migrations to {ROOT_DATABASE_NAME}"),
+    });
+
+    Ok(InitializePlan {
+        root_database_name: ROOT_DATABASE_NAME.to_string(),
+        role_name: role_name.clone(),
+        secret_name: secret_name.clone(),
+        secret_exists: has_secret,
+        steps,
+        skipped,
+    })
+}
+
+/// Perform the steps of an initialization `plan`
+pub async fn execute_initialize(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    plan: &InitializePlan,
+) -> anyhow::Result<()> {
+    if plan.is_default() {
+        tracing::info!("initializing root");
+        initialize(db_provider, secrets, &plan.secret_name)
+            .await
+            .map_err(anyhow::Error::new)?;
+        return Ok(());
+    }
+
+    let root_db = initialize_root_database(db_provider)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let credentials = DatabaseSecret {
+        username: plan.role_name.clone(),
+        password: random_password(ROOT_PASSWORD_LENGTH),
+    };
+
+    for step in &plan.steps {
+        tracing::info!(kind = ?step.kind, description = %step.description, "initializing root");
+
+        match step.kind {
+            // Created along with the connection to the root database
+            InitializeStepKind::CreateDatabase => {}
+            InitializeStepKind::CreateRole => {
+                initialize_root_role(&root_db, &credentials.username, &credentials.password)
+                    .await
+                    .map_err(anyhow::Error::new)?;
+            }
+            InitializeStepKind::SetRolePassword => {
+                set_role_password(&root_db, &credentials)
+                    .await
+                    .context("failed to set root role password")?;
+            }
+            InitializeStepKind::StoreSecret => {
+                initialize_root_secret(
+                    secrets,
+                    &plan.secret_name,
+                    &credentials.username,
+                    &credentials.password,
+                )
+                .await
+                .map_err(anyhow::Error::new)?;
+            }
+            InitializeStepKind::MigrateRoot => {
+                migrate_root(db_provider, None)
+                    .await
+                    .map_err(anyhow::Error::new)?;
+            }
+        }
+    }
+
+    Ok(())
+}
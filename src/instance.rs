@@ -0,0 +1,63 @@
+//! Liveness of manager instances
+//!
+//! Each manager instance records a heartbeat in the manager database while
+//! it is running. Jobs, scheduled migrations and purges are owned by the
+//! instance that started them and are only failed as interrupted once the
+//! heartbeat of their instance expires, so restarting one replica does
+//! not fail work running on the others
+
+use crate::metadata::{
+    archives::ArchivedTenant, instances::ManagerInstance, jobs::Job, schedules::ScheduledMigration,
+};
+use sqlx::PgPool;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Interval between heartbeats of a running instance
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Time since the last heartbeat after which an instance is considered
+/// to have stopped
+const HEARTBEAT_EXPIRY: Duration = Duration::from_secs(60);
+
+/// Fail any work owned by instances that have stopped
+pub async fn fail_interrupted(db: &PgPool) -> anyhow::Result<()> {
+    let interrupted = Job::fail_interrupted(db, HEARTBEAT_EXPIRY).await?;
+    if interrupted > 0 {
+        tracing::warn!(%interrupted, "marked interrupted jobs as failed");
+    }
+
+    let interrupted = ScheduledMigration::fail_interrupted(db, HEARTBEAT_EXPIRY).await?;
+    if interrupted > 0 {
+        tracing::warn!(%interrupted, "marked interrupted scheduled migrations as failed");
+    }
+
+    let interrupted = ArchivedTenant::fail_interrupted(db, HEARTBEAT_EXPIRY).await?;
+    if interrupted > 0 {
+        tracing::warn!(%interrupted, "marked interrupted tenant purges as failed");
+    }
+
+    ManagerInstance::delete_expired(db, HEARTBEAT_EXPIRY).await?;
+
+    Ok(())
+}
+
+/// Spawn the background task recording the heartbeat of this instance
+/// and failing work owned by instances that have stopped
+pub fn spawn_heartbeat(db: PgPool, instance_id: Uuid) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(HEARTBEAT_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(error) = ManagerInstance::heartbeat(&db, instance_id).await {
+                tracing::error!(?error, %instance_id, "failed to record instance heartbeat");
+            }
+
+            if let Err(error) = fail_interrupted(&db).await {
+                tracing::error!(?error, "failed to fail interrupted work");
+            }
+        }
+    });
+}
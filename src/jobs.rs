@@ -0,0 +1,397 @@
+//! Background jobs tracked in the manager database
+
+use crate::metadata::jobs::{CreateJob, Job, JobLog, JobLogLevel};
+use futures::{SinkExt, channel::mpsc};
+use serde::Serialize;
+use sqlx::PgPool;
+use std::{
+    collections::HashMap,
+    future::Future,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Capacity of the per job event broadcast channel
+const EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// Interval between polls when watching a job running on another
+/// manager instance
+const WATCH_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Interval between checks for a paused job being resumed
+const PAUSE_POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Event produced while a job is running
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobEvent {
+    /// Current state of the job
+    Job { job: Job },
+    /// Message logged by the job
+    Log(JobLog),
+    /// Progress reported by the job
+    Progress { progress: serde_json::Value },
+    /// Job has finished, no further events will be produced
+    Finished { job: Job },
+}
+
+impl JobEvent {
+    /// Name of the event
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobEvent::Job { .. } => "job",
+            JobEvent::Log(_) => "log",
+            JobEvent::Progress { .. } => "progress",
+            JobEvent::Finished { .. } => "finished",
+        }
+    }
+}
+
+struct RunningJob {
+    cancelled: Arc<AtomicBool>,
+    events: broadcast::Sender<JobEvent>,
+}
+
+/// Registry of jobs running within this manager process
+pub struct Jobs {
+    db: PgPool,
+    /// Manager instance the jobs are running on
+    instance_id: Uuid,
+    running: Mutex<HashMap<Uuid, RunningJob>>,
+}
+
+/// Handle provided to a running job for reporting logs and progress
+/// and checking for cancellation
+#[derive(Clone)]
+pub struct JobContext {
+    pub id: Uuid,
+    db: PgPool,
+    cancelled: Arc<AtomicBool>,
+    events: broadcast::Sender<JobEvent>,
+}
+
+impl JobContext {
+    /// Store a log message for the job
+    pub async fn log(&self, level: JobLogLevel, message: impl AsRef<str>) {
+        match JobLog::create(&self.db, self.id, level, message.as_ref()).await {
+            Ok(log) => {
+                _ = self.events.send(JobEvent::Log(log));
+            }
+            Err(error) => {
+                tracing::error!(?error, job_id = %self.id, "failed to store job log");
+            }
+        }
+    }
+
+    /// Store the current progress of the job
+    pub async fn set_progress<P: Serialize>(&self, progress: &P) {
+        let progress = match serde_json::to_value(progress) {
+            Ok(value) => value,
+            Err(error) => {
+                tracing::error!(?error, job_id = %self.id, "failed to serialize job progress");
+                return;
+            }
+        };
+
+        if let Err(error) = Job::set_progress(&self.db, self.id, &progress).await {
+            tracing::error!(?error, job_id = %self.id, "failed to store job progress");
+        }
+
+        _ = self.events.send(JobEvent::Progress { progress });
+    }
+
+    /// Check if cancellation of the job has been requested, the database
+    /// is checked so cancellations requested through other manager
+    /// instances are also observed
+    pub async fn is_cancelled(&self) -> bool {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return true;
+        }
+
+        match Job::is_cancel_requested(&self.db, self.id).await {
+            Ok(true) => {
+                self.cancelled.store(true, Ordering::SeqCst);
+                true
+            }
+            Ok(false) => false,
+            Err(error) => {
+                tracing::error!(?error, job_id = %self.id, "failed to check job cancellation");
+                false
+            }
+        }
+    }
+
+    /// Wait until the job is no longer paused, returns early if the job
+    /// is cancelled while paused
+    pub async fn wait_while_paused(&self) {
+        let mut paused = false;
+
+        loop {
+            if self.is_cancelled().await {
+                return;
+            }
+
+            match Job::is_paused(&self.db, self.id).await {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(error) => {
+                    tracing::error!(?error, job_id = %self.id, "failed to check job pause");
+                    break;
+                }
+            }
+
+            if !paused {
+                paused = true;
+                self.log(JobLogLevel::Info, "job paused").await;
+            }
+
+            tokio::time::sleep(PAUSE_POLL_INTERVAL).await;
+        }
+
+        if paused {
+            self.log(JobLogLevel::Info, "job resumed").await;
+        }
+    }
+}
+
+impl Jobs {
+    pub fn new(db: PgPool, instance_id: Uuid) -> Self {
+        Self {
+            db,
+            instance_id,
+            running: Default::default(),
+        }
+    }
+
+    /// Manager instance the jobs are running on
+    pub fn instance_id(&self) -> Uuid {
+        self.instance_id
+    }
+
+    /// Create a job and run `task` in the background, the job status is
+    /// updated with the outcome of the task once it completes
+    pub async fn spawn<F, Fut, O>(
+        self: &Arc<Self>,
+        create: CreateJob,
+        task: F,
+    ) -> anyhow::Result<Job>
+    where
+        F: FnOnce(JobContext) -> Fut + Send + 'static,
+        Fut: Future<Output = anyhow::Result<O>> + Send + 'static,
+        O: Serialize,
+    {
+        let job = Job::create(&self.db, self.instance_id, create).await?;
+        let job_id = job.id;
+
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (events, _) = broadcast::channel(EVENT_CHANNEL_CAPACITY);
+
+        self.running.lock().expect("jobs lock poisoned").insert(
+            job_id,
+            RunningJob {
+                cancelled: cancelled.clone(),
+                events: events.clone(),
+            },
+        );
+
+        let ctx = JobContext {
+            id: job_id,
+            db: self.db.clone(),
+            cancelled,
+            events: events.clone(),
+        };
+
+        let jobs = self.clone();
+
+        tokio::spawn(async move {
+            let db = &jobs.db;
+
+            if let Err(error) = Job::set_running(db, job_id).await {
+                tracing::error!(?error, %job_id, "failed to mark job as running");
+            }
+
+            let result = task(ctx.clone()).await.and_then(|output| {
+                serde_json::to_value(output).map_err(anyhow::Error::new)
+            });
+
+            let result = match result {
+                Ok(output) if ctx.is_cancelled().await => {
+                    tracing::info!(%job_id, "job cancelled");
+                    Job::set_cancelled(db, job_id, output).await
+                }
+                Ok(output) => {
+                    tracing::info!(%job_id, "job completed");
+                    Job::set_succeeded(db, job_id, output).await
+                }
+                Err(error) => {
+                    tracing::error!(?error, %job_id, "job failed");
+                    Job::set_failed(db, job_id, &format!("{error:#}")).await
+                }
+            };
+
+            if let Err(error) = result {
+                tracing::error!(?error, %job_id, "failed to store job outcome");
+            }
+
+            jobs.running
+                .lock()
+                .expect("jobs lock poisoned")
+                .remove(&job_id);
+
+            match Job::find_by_id(db, job_id).await {
+                Ok(Some(job)) => {
+                    _ = events.send(JobEvent::Finished { job });
+                }
+                Ok(None) => {}
+                Err(error) => {
+                    tracing::error!(?error, %job_id, "failed to load finished job");
+                }
+            }
+        });
+
+        Ok(job)
+    }
+
+    /// Request cancellation of a job, returns false if the job does not
+    /// exist or has already finished
+    pub async fn cancel(&self, job_id: Uuid) -> sqlx::Result<bool> {
+        let requested = Job::request_cancel(&self.db, job_id).await?;
+
+        if requested {
+            let running = self.running.lock().expect("jobs lock poisoned");
+            if let Some(running) = running.get(&job_id) {
+                running.cancelled.store(true, Ordering::SeqCst);
+            }
+        }
+
+        Ok(requested)
+    }
+
+    /// Pause or resume a job, returns false if the job does not exist or
+    /// has already finished. Jobs only observe the pause at points where
+    /// they are able to stop
+    pub async fn set_paused(&self, job_id: Uuid, paused: bool) -> sqlx::Result<bool> {
+        Job::set_paused(&self.db, job_id, paused).await
+    }
+
+    /// Watch the events of a job, starting with the current state and
+    /// logs of the job. Returns [None] if the job does not exist
+    pub async fn watch(&self, job_id: Uuid) -> sqlx::Result<Option<mpsc::Receiver<JobEvent>>> {
+        // Subscribe before loading the snapshot so no events are missed
+        let receiver = self
+            .running
+            .lock()
+            .expect("jobs lock poisoned")
+            .get(&job_id)
+            .map(|running| running.events.subscribe());
+
+        let job = match Job::find_by_id(&self.db, job_id).await? {
+            Some(job) => job,
+            None => return Ok(None),
+        };
+        let logs = JobLog::find_by_job(&self.db, job_id).await?;
+
+        let (mut tx, rx) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let db = self.db.clone();
+
+        tokio::spawn(async move {
+            let mut last_log_id = logs.last().map(|log| log.id).unwrap_or_default();
+            let finished = job.status.is_finished();
+
+            if tx.send(JobEvent::Job { job: job.clone() }).await.is_err() {
+                return;
+            }
+
+            for log in logs {
+                if tx.send(JobEvent::Log(log)).await.is_err() {
+                    return;
+                }
+            }
+
+            if finished {
+                _ = tx.send(JobEvent::Finished { job }).await;
+                return;
+            }
+
+            match receiver {
+                Some(receiver) => forward_events(receiver, tx, last_log_id).await,
+                None => {
+                    // Job is running on another manager instance
+                    loop {
+                        tokio::time::sleep(WATCH_POLL_INTERVAL).await;
+
+                        let logs = match JobLog::find_after(&db, job_id, last_log_id).await {
+                            Ok(logs) => logs,
+                            Err(error) => {
+                                tracing::error!(?error, %job_id, "failed to poll job logs");
+                                return;
+                            }
+                        };
+
+                        for log in logs {
+                            last_log_id = log.id;
+                            if tx.send(JobEvent::Log(log)).await.is_err() {
+                                return;
+                            }
+                        }
+
+                        let job = match Job::find_by_id(&db, job_id).await {
+                            Ok(Some(job)) => job,
+                            Ok(None) => return,
+                            Err(error) => {
+                                tracing::error!(?error, %job_id, "failed to poll job");
+                                return;
+                            }
+                        };
+
+                        if job.status.is_finished() {
+                            _ = tx.send(JobEvent::Finished { job }).await;
+                            return;
+                        }
+
+                        if tx.send(JobEvent::Job { job }).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        Ok(Some(rx))
+    }
+}
+
+/// Forward events from a running job until the job finishes or the
+/// watcher goes away
+async fn forward_events(
+    mut receiver: broadcast::Receiver<JobEvent>,
+    mut tx: mpsc::Sender<JobEvent>,
+    last_log_id: i64,
+) {
+    loop {
+        let event = match receiver.recv().await {
+            Ok(event) => event,
+            Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                tracing::warn!(%skipped, "job watcher lagged behind");
+                continue;
+            }
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        // Logs already included in the snapshot
+        if matches!(&event, JobEvent::Log(log) if log.id <= last_log_id) {
+            continue;
+        }
+
+        let finished = matches!(event, JobEvent::Finished { .. });
+
+        if tx.send(event).await.is_err() || finished {
+            return;
+        }
+    }
+}
@@ -0,0 +1,231 @@
+//! Locks preventing concurrent migrations across operators and manager
+//! instances
+//!
+//! Locks are rows in the manager database inserted only when no unexpired
+//! row exists for the lock. The holder extends the expiry while the lock
+//! is held so a lock is freed shortly after its holder exits uncleanly,
+//! without holding a database connection for the lifetime of the lock.
+//! The identity of the holder is stored so conflicting requests can
+//! report who holds it
+
+use crate::error::HttpError;
+use axum::http::StatusCode;
+use chrono::{DateTime, Utc};
+use sqlx::PgPool;
+use std::{fmt::Display, time::Duration};
+use thiserror::Error;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+/// Time after the last renewal that a lock expires
+const LOCK_EXPIRY: Duration = Duration::from_secs(60);
+
+/// Interval between renewals of a held lock
+const LOCK_RENEW_INTERVAL: Duration = Duration::from_secs(15);
+
+/// What a migration lock protects
+#[derive(Debug, Clone)]
+pub enum LockScope {
+    /// Operations across every tenant
+    Fleet,
+    /// Operations on a specific tenant database
+    Tenant { env: String, tenant_id: Uuid },
+}
+
+impl LockScope {
+    pub fn tenant(env: &str, tenant_id: Uuid) -> Self {
+        LockScope::Tenant {
+            env: env.to_string(),
+            tenant_id,
+        }
+    }
+
+    fn key(&self) -> String {
+        match self {
+            LockScope::Fleet => "docbox-manager:migrate:fleet".to_string(),
+            LockScope::Tenant { env, tenant_id } => {
+                format!("docbox-manager:migrate:tenant:{env}:{tenant_id}")
+            }
+        }
+    }
+}
+
+impl Display for LockScope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockScope::Fleet => f.write_str("the fleet"),
+            LockScope::Tenant { env, tenant_id } => write!(f, "tenant {env}/{tenant_id}"),
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum LockError {
+    #[error(
+        "a migration is already in progress for {scope} (held by {} since {})",
+        holder.as_deref().unwrap_or("unknown"),
+        acquired_at.map(|at| at.to_rfc3339()).unwrap_or_else(|| "unknown".to_string())
+    )]
+    Held {
+        scope: LockScope,
+        holder: Option<String>,
+        acquired_at: Option<DateTime<Utc>>,
+    },
+
+    #[error("failed to acquire migration lock")]
+    Database(#[from] sqlx::Error),
+}
+
+impl HttpError for LockError {
+    fn status(&self) -> StatusCode {
+        match self {
+            LockError::Held { .. } => StatusCode::CONFLICT,
+            LockError::Database(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// Held migration lock, released when dropped
+pub struct MigrationLock {
+    db: PgPool,
+    key: String,
+    /// Unique ID of this acquisition of the lock
+    lock_id: Uuid,
+    /// Task extending the expiry of the lock while it is held
+    renewal: JoinHandle<()>,
+    released: bool,
+}
+
+impl MigrationLock {
+    /// Try to acquire the lock for `scope` as `holder` without waiting
+    pub async fn acquire(db: &PgPool, scope: LockScope, holder: &str) -> Result<Self, LockError> {
+        let key = scope.key();
+        let lock_id = Uuid::new_v4();
+
+        // Only replaces the row of a lock whose holder stopped renewing it
+        let acquired: Option<String> = sqlx::query_scalar(
+            r#"
+            INSERT INTO "migration_locks" ("key", "holder", "lock_id", "acquired_at", "expires_at")
+            VALUES ($1, $2, $3, NOW(), NOW() + MAKE_INTERVAL(secs => $4))
+            ON CONFLICT ("key") DO UPDATE
+            SET "holder" = EXCLUDED."holder",
+                "lock_id" = EXCLUDED."lock_id",
+                "acquired_at" = EXCLUDED."acquired_at",
+                "expires_at" = EXCLUDED."expires_at"
+            WHERE "migration_locks"."expires_at" < NOW()
+            RETURNING "key"
+            "#,
+        )
+        .bind(&key)
+        .bind(holder)
+        .bind(lock_id)
+        .bind(LOCK_EXPIRY.as_secs_f64())
+        .fetch_optional(db)
+        .await?;
+
+        if acquired.is_none() {
+            let current: Option<(String, DateTime<Utc>)> = sqlx::query_as(
+                r#"SELECT "holder", "acquired_at" FROM "migration_locks" WHERE "key" = $1"#,
+            )
+            .bind(&key)
+            .fetch_optional(db)
+            .await?;
+            let (holder, acquired_at) = current.unzip();
+
+            return Err(LockError::Held {
+                scope,
+                holder,
+                acquired_at,
+            });
+        }
+
+        let renewal = tokio::spawn(renew_lock(db.clone(), key.clone(), lock_id));
+
+        Ok(Self {
+            db: db.clone(),
+            key,
+            lock_id,
+            renewal,
+            released: false,
+        })
+    }
+
+    /// Remove the lock for `scope` if its holder stopped renewing it
+    pub async fn delete_expired(db: &PgPool, scope: &LockScope) -> sqlx::Result<()> {
+        sqlx::query(r#"DELETE FROM "migration_locks" WHERE "key" = $1 AND "expires_at" < NOW()"#)
+            .bind(scope.key())
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Release the lock
+    pub async fn release(mut self) {
+        self.renewal.abort();
+        self.released = true;
+        delete_lock(&self.db, &self.key, self.lock_id).await;
+    }
+}
+
+impl Drop for MigrationLock {
+    fn drop(&mut self) {
+        self.renewal.abort();
+
+        if self.released {
+            return;
+        }
+
+        // Lock was not explicitly released, remove it in the background
+        // otherwise it is held until it expires
+        if let Ok(runtime) = tokio::runtime::Handle::try_current() {
+            let (db, key, lock_id) = (self.db.clone(), self.key.clone(), self.lock_id);
+            runtime.spawn(async move { delete_lock(&db, &key, lock_id).await });
+        }
+    }
+}
+
+/// Extend the expiry of a held lock until the task is aborted
+async fn renew_lock(db: PgPool, key: String, lock_id: Uuid) {
+    let mut interval = tokio::time::interval(LOCK_RENEW_INTERVAL);
+    // First tick completes immediately
+    interval.tick().await;
+
+    loop {
+        interval.tick().await;
+
+        let result = sqlx::query(
+            r#"
+            UPDATE "migration_locks" SET "expires_at" = NOW() + MAKE_INTERVAL(secs => $3)
+            WHERE "key" = $1 AND "lock_id" = $2
+            "#,
+        )
+        .bind(&key)
+        .bind(lock_id)
+        .bind(LOCK_EXPIRY.as_secs_f64())
+        .execute(&db)
+        .await;
+
+        match result {
+            Ok(result) if result.rows_affected() == 0 => {
+                tracing::error!(%key, "migration lock expired while held");
+                return;
+            }
+            Ok(_) => {}
+            Err(error) => {
+                tracing::error!(?error, %key, "failed to renew migration lock");
+            }
+        }
+    }
+}
+
+async fn delete_lock(db: &PgPool, key: &str, lock_id: Uuid) {
+    if let Err(error) =
+        sqlx::query(r#"DELETE FROM "migration_locks" WHERE "key" = $1 AND "lock_id" = $2"#)
+            .bind(key)
+            .bind(lock_id)
+            .execute(db)
+            .await
+    {
+        tracing::error!(?error, %key, "failed to release migration lock");
+    }
+}
@@ -1,7 +1,15 @@
 use crate::{
-    config::{DatabaseConfig, DocboxServerUrl, ServerPassword},
+    archive::{Purger, spawn_purger},
+    config::{
+        ArchiveConfig, BackupConfig, DatabaseConfig, DocboxServerUrl, ImportConfig, MetricsToken,
+        QueryConsoleConfig, RotationPolicy, ServerPassword, TeardownConfig, TrustedProxy,
+    },
     database::DatabaseProvider,
+    instance::{fail_interrupted, spawn_heartbeat},
+    jobs::Jobs,
+    metadata::{MetadataDatabase, instances::ManagerInstance},
     routes::router,
+    scheduler::spawn_scheduler,
 };
 use axum::Extension;
 use docbox_core::aws::aws_config;
@@ -15,14 +23,36 @@ use std::{
 };
 use tower_http::trace::TraceLayer;
 use tower_sessions::{Expiry, MemoryStore, SessionManagerLayer, cookie::time::Duration};
+use uuid::Uuid;
 
+mod archive;
 mod auth;
+mod backup;
+mod clone;
 mod config;
+mod console;
+mod credentials;
 mod database;
+mod drift;
 mod error;
+mod export;
+mod initialize;
+mod instance;
+mod jobs;
+mod locks;
 mod logging;
+mod metadata;
+mod metrics;
+mod migrations;
 mod models;
+mod rollback;
+mod rollout;
+mod root;
 mod routes;
+mod scheduler;
+mod secrets;
+mod storage;
+mod teardown;
 
 /// Default server address when not specified
 const DEFAULT_SERVER_ADDRESS: SocketAddr =
@@ -31,14 +61,19 @@ const DEFAULT_SERVER_ADDRESS: SocketAddr =
 fn main() -> anyhow::Result<()> {
     _ = dotenvy::dotenv();
 
+    let database_config = DatabaseConfig::from_env()?;
+
+    // Safety: The runtime has not been started yet so no other threads exist
+    unsafe { database_config.tls.export_env() };
+
     tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .expect("Failed building the Runtime")
-        .block_on(server())
+        .block_on(server(database_config))
 }
 
-async fn server() -> anyhow::Result<()> {
+async fn server(database_config: DatabaseConfig) -> anyhow::Result<()> {
     logging::init_logging()?;
 
     let session_store = MemoryStore::default();
@@ -49,9 +84,16 @@ async fn server() -> anyhow::Result<()> {
     // Load AWS configuration
     let aws_config = aws_config().await;
 
-    let database_config = DatabaseConfig::from_env()?;
     let server_password = ServerPassword::from_env()?;
     let server_url = DocboxServerUrl::from_env()?;
+    let metrics_token = MetricsToken::from_env();
+    let trusted_proxy = TrustedProxy::from_env()?;
+    let backup_config = BackupConfig::from_env()?;
+    let rotation_policy = RotationPolicy::from_env()?;
+    let console_config = QueryConsoleConfig::from_env()?;
+    let teardown_config = TeardownConfig::from_env()?;
+    let archive_config = ArchiveConfig::from_env()?;
+    let import_config = ImportConfig::from_env()?;
 
     // Initialize factories
     let secrets = SecretManager::from_config(&aws_config, SecretsManagerConfig::from_env()?);
@@ -63,22 +105,53 @@ async fn server() -> anyhow::Result<()> {
             host: database_config.host.clone(),
             port: database_config.port,
             root_secret_name: database_config.root_secret_name.clone(),
-            max_connections: None,
+            max_connections: database_config.max_connections,
+            ..Default::default()
         },
-        secrets.clone(),
+        secrets.as_ref().clone(),
     ));
 
     let search_factory = SearchIndexFactory::from_config(
         &aws_config,
-        secrets.clone(),
-        db_cache,
+        secrets.as_ref().clone(),
+        db_cache.clone(),
         SearchIndexFactoryConfig::from_env()?,
     )?;
+    let search_factory = Arc::new(search_factory);
+
     let storage_factory =
         StorageLayerFactory::from_config(&aws_config, StorageLayerFactoryConfig::from_env()?);
-    let database_provider = DatabaseProvider {
-        config: database_config.clone(),
-    };
+    let storage_factory = Arc::new(storage_factory);
+
+    // Setup the manager metadata database
+    let metadata = MetadataDatabase::initialize(&database_config).await?;
+
+    // Register this instance so other instances leave its work alone
+    let instance_id = Uuid::new_v4();
+    ManagerInstance::heartbeat(&metadata.pool, instance_id).await?;
+    tracing::info!(%instance_id, "registered manager instance");
+
+    // Work from instances that have stopped can no longer complete
+    fail_interrupted(&metadata.pool).await?;
+    spawn_heartbeat(metadata.pool.clone(), instance_id);
+
+    let jobs = Arc::new(Jobs::new(metadata.pool.clone(), instance_id));
+
+    let metadata = Arc::new(metadata);
+    let database_provider = Arc::new(DatabaseProvider::new(database_config.clone()));
+
+    // Start running scheduled migrations
+    spawn_scheduler(database_provider.clone(), metadata.clone(), jobs.clone());
+
+    // Start purging archived tenants after their retention period
+    spawn_purger(Purger {
+        db_provider: database_provider.clone(),
+        storage_factory: storage_factory.clone(),
+        search_factory: search_factory.clone(),
+        secrets: secrets.clone(),
+        metadata: metadata.clone(),
+        jobs: jobs.clone(),
+    });
 
     // Setup router
     let app = router();
@@ -93,11 +166,22 @@ async fn server() -> anyhow::Result<()> {
     let app = app
         .layer(Extension(Arc::new(server_url)))
         .layer(Extension(Arc::new(server_password)))
+        .layer(Extension(Arc::new(metrics_token)))
+        .layer(Extension(Arc::new(trusted_proxy)))
+        .layer(Extension(Arc::new(backup_config)))
+        .layer(Extension(Arc::new(rotation_policy)))
+        .layer(Extension(Arc::new(console_config)))
+        .layer(Extension(Arc::new(teardown_config)))
+        .layer(Extension(Arc::new(archive_config)))
+        .layer(Extension(Arc::new(import_config)))
         .layer(Extension(Arc::new(database_config)))
-        .layer(Extension(Arc::new(database_provider)))
-        .layer(Extension(Arc::new(secrets)))
-        .layer(Extension(Arc::new(search_factory)))
-        .layer(Extension(Arc::new(storage_factory)))
+        .layer(Extension(database_provider))
+        .layer(Extension(metadata))
+        .layer(Extension(jobs))
+        .layer(Extension(secrets))
+        .layer(Extension(db_cache))
+        .layer(Extension(search_factory))
+        .layer(Extension(storage_factory))
         .layer(session_layer)
         .layer(TraceLayer::new_for_http());
 
@@ -0,0 +1,231 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ArchiveStatus {
+    /// Tenant is disabled and waiting for its retention period to end
+    Archived,
+    /// Tenant resources are being purged
+    Purging,
+    /// Purging the tenant failed, retried on the next purge check
+    PurgeFailed,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ArchivedTenant {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    pub reason: Option<String>,
+    pub status: ArchiveStatus,
+    pub purge_after: DateTime<Utc>,
+    pub job_id: Option<Uuid>,
+    pub error: Option<String>,
+    pub archived_by: String,
+    pub archived_at: DateTime<Utc>,
+    /// Manager instance purging the tenant
+    pub instance_id: Option<Uuid>,
+    /// When purging the tenant last failed
+    pub failed_at: Option<DateTime<Utc>>,
+    /// Number of times purging the tenant has failed
+    pub purge_attempts: i32,
+}
+
+pub struct CreateArchivedTenant {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    pub reason: Option<String>,
+    pub purge_after: DateTime<Utc>,
+    pub archived_by: String,
+}
+
+impl ArchivedTenant {
+    /// Archive a tenant, returns `None` if the tenant is already archived
+    pub async fn create(
+        db: &PgPool,
+        create: CreateArchivedTenant,
+    ) -> sqlx::Result<Option<ArchivedTenant>> {
+        sqlx::query_as(
+            r#"
+            INSERT INTO "archived_tenants" (
+                "env", "tenant_id", "name", "reason", "status", "purge_after", "archived_by"
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT DO NOTHING
+            RETURNING *
+            "#,
+        )
+        .bind(create.env)
+        .bind(create.tenant_id)
+        .bind(create.name)
+        .bind(create.reason)
+        .bind(ArchiveStatus::Archived)
+        .bind(create.purge_after)
+        .bind(create.archived_by)
+        .fetch_optional(db)
+        .await
+    }
+
+    pub async fn find(
+        db: &PgPool,
+        env: &str,
+        tenant_id: Uuid,
+    ) -> sqlx::Result<Option<ArchivedTenant>> {
+        sqlx::query_as(r#"SELECT * FROM "archived_tenants" WHERE "env" = $1 AND "tenant_id" = $2"#)
+            .bind(env)
+            .bind(tenant_id)
+            .fetch_optional(db)
+            .await
+    }
+
+    /// Get every archived tenant, soonest to be purged first
+    pub async fn all(db: &PgPool) -> sqlx::Result<Vec<ArchivedTenant>> {
+        sqlx::query_as(r#"SELECT * FROM "archived_tenants" ORDER BY "purge_after""#)
+            .fetch_all(db)
+            .await
+    }
+
+    /// Check if a tenant is archived
+    pub async fn is_archived(db: &PgPool, env: &str, tenant_id: Uuid) -> sqlx::Result<bool> {
+        sqlx::query_scalar(
+            r#"SELECT EXISTS(SELECT 1 FROM "archived_tenants" WHERE "env" = $1 AND "tenant_id" = $2)"#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .fetch_one(db)
+        .await
+    }
+
+    /// Restore an archived tenant that is not being purged, returns false
+    /// if the tenant was not archived or purging has started
+    pub async fn restore(db: &PgPool, env: &str, tenant_id: Uuid) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM "archived_tenants"
+            WHERE "env" = $1 AND "tenant_id" = $2 AND "status" <> $3
+            "#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .bind(ArchiveStatus::Purging)
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Claim an archived tenant whose retention period has ended for the
+    /// manager instance with `instance_id`, marking it as purging. Claimed
+    /// tenants are skipped by other manager instances. Failed purges are
+    /// only claimed if they failed before `failed_before` so a purge check
+    /// does not retry the tenants that failed during the check
+    pub async fn claim_expired(
+        db: &PgPool,
+        instance_id: Uuid,
+        failed_before: DateTime<Utc>,
+    ) -> sqlx::Result<Option<ArchivedTenant>> {
+        sqlx::query_as(
+            r#"
+            UPDATE "archived_tenants" SET "status" = $1, "error" = NULL, "instance_id" = $4
+            WHERE ("env", "tenant_id") = (
+                SELECT "env", "tenant_id" FROM "archived_tenants"
+                WHERE "purge_after" <= NOW() AND (
+                    "status" = $2
+                    OR ("status" = $3 AND ("failed_at" IS NULL OR "failed_at" < $5))
+                )
+                ORDER BY "purge_after"
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(ArchiveStatus::Purging)
+        .bind(ArchiveStatus::Archived)
+        .bind(ArchiveStatus::PurgeFailed)
+        .bind(instance_id)
+        .bind(failed_before)
+        .fetch_optional(db)
+        .await
+    }
+
+    pub async fn set_job(
+        db: &PgPool,
+        env: &str,
+        tenant_id: Uuid,
+        job_id: Uuid,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"UPDATE "archived_tenants" SET "job_id" = $3 WHERE "env" = $1 AND "tenant_id" = $2"#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .bind(job_id)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Mark purging the tenant as failed, returns the number of failed
+    /// purge attempts including this one
+    pub async fn set_purge_failed(
+        db: &PgPool,
+        env: &str,
+        tenant_id: Uuid,
+        error: &str,
+    ) -> sqlx::Result<i32> {
+        sqlx::query_scalar(
+            r#"
+            UPDATE "archived_tenants"
+            SET "status" = $3, "error" = $4, "failed_at" = NOW(), "purge_attempts" = "purge_attempts" + 1
+            WHERE "env" = $1 AND "tenant_id" = $2
+            RETURNING "purge_attempts"
+            "#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .bind(ArchiveStatus::PurgeFailed)
+        .bind(error)
+        .fetch_one(db)
+        .await
+    }
+
+    /// Remove the archive record once the tenant has been purged
+    pub async fn delete(db: &PgPool, env: &str, tenant_id: Uuid) -> sqlx::Result<()> {
+        sqlx::query(r#"DELETE FROM "archived_tenants" WHERE "env" = $1 AND "tenant_id" = $2"#)
+            .bind(env)
+            .bind(tenant_id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Mark any tenants left purging by a manager instance whose heartbeat
+    /// is older than `expiry` as failed so they are purged again
+    pub async fn fail_interrupted(db: &PgPool, expiry: Duration) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE "archived_tenants"
+            SET "status" = $1, "error" = 'interrupted, manager instance stopped', "failed_at" = NOW(),
+                "purge_attempts" = "purge_attempts" + 1
+            WHERE "status" = $2
+              AND NOT EXISTS (
+                SELECT 1 FROM "manager_instances" "instance"
+                WHERE "instance"."id" = "archived_tenants"."instance_id"
+                  AND "instance"."heartbeat_at" >= NOW() - MAKE_INTERVAL(secs => $3)
+              )
+            "#,
+        )
+        .bind(ArchiveStatus::PurgeFailed)
+        .bind(ArchiveStatus::Purging)
+        .bind(expiry.as_secs_f64())
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
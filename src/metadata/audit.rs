@@ -0,0 +1,111 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+/// Actions recorded in the audit log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditAction {
+    /// Query executed through the query console
+    TenantQuery,
+    /// Tenant rolled back to an earlier migration
+    TenantRollback,
+    /// Tenant attributes updated
+    TenantUpdate,
+    /// Tenant and its resources deleted
+    TenantDelete,
+    /// Tenant archived
+    TenantArchive,
+    /// Archived tenant restored
+    TenantUnarchive,
+    /// Archived tenant purged after its retention period
+    TenantPurge,
+    /// Purging an archived tenant failed, retried by the next purge check
+    TenantPurgeFailed,
+    /// Tenant cloned into a new tenant
+    TenantClone,
+    /// Tenant exported into a portable archive
+    TenantExport,
+    /// Tenant created from a portable archive
+    TenantImport,
+    /// Root database and every tenant destroyed
+    RootTeardown,
+}
+
+impl AuditAction {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditAction::TenantQuery => "tenant_query",
+            AuditAction::TenantRollback => "tenant_rollback",
+            AuditAction::TenantUpdate => "tenant_update",
+            AuditAction::TenantDelete => "tenant_delete",
+            AuditAction::TenantArchive => "tenant_archive",
+            AuditAction::TenantUnarchive => "tenant_unarchive",
+            AuditAction::TenantPurge => "tenant_purge",
+            AuditAction::TenantPurgeFailed => "tenant_purge_failed",
+            AuditAction::TenantClone => "tenant_clone",
+            AuditAction::TenantExport => "tenant_export",
+            AuditAction::TenantImport => "tenant_import",
+            AuditAction::RootTeardown => "root_teardown",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AuditEvent {
+    pub id: Uuid,
+    pub action: String,
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+    pub details: serde_json::Value,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct CreateAuditEvent {
+    pub action: AuditAction,
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+    pub details: serde_json::Value,
+}
+
+impl AuditEvent {
+    pub async fn create(db: &PgPool, create: CreateAuditEvent) -> sqlx::Result<AuditEvent> {
+        sqlx::query_as(
+            r#"
+            INSERT INTO "audit_events" ("id", "action", "env", "tenant_id", "details")
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(create.action.as_str())
+        .bind(create.env)
+        .bind(create.tenant_id)
+        .bind(create.details)
+        .fetch_one(db)
+        .await
+    }
+
+    /// Get the most recent audit events, optionally filtered to a specific tenant
+    pub async fn recent(
+        db: &PgPool,
+        tenant: Option<(&str, Uuid)>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<AuditEvent>> {
+        let (env, tenant_id) = tenant.unzip();
+        sqlx::query_as(
+            r#"
+            SELECT * FROM "audit_events"
+            WHERE ($1::VARCHAR IS NULL OR "env" = $1)
+              AND ($2::UUID IS NULL OR "tenant_id" = $2)
+            ORDER BY "created_at" DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .bind(limit)
+        .fetch_all(db)
+        .await
+    }
+}
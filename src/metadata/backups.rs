@@ -0,0 +1,123 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum BackupReason {
+    /// Backup requested by an operator
+    Manual,
+    /// Backup taken automatically before migrating the tenant
+    PreMigration,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TenantBackup {
+    pub id: Uuid,
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub db_name: String,
+    pub bucket: String,
+    pub key: String,
+    pub size: i64,
+    pub sha256: String,
+    pub reason: BackupReason,
+    pub job_id: Option<Uuid>,
+    /// Tenant migrations that had not been applied at the time of the
+    /// backup, not known for backups taken by older manager versions
+    pub pending_migrations: Option<Vec<String>>,
+    pub created_at: DateTime<Utc>,
+}
+
+pub struct CreateTenantBackup {
+    pub id: Uuid,
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub db_name: String,
+    pub bucket: String,
+    pub key: String,
+    pub size: i64,
+    pub sha256: String,
+    pub reason: BackupReason,
+    pub job_id: Option<Uuid>,
+    pub pending_migrations: Vec<String>,
+}
+
+impl TenantBackup {
+    pub async fn create(db: &PgPool, create: CreateTenantBackup) -> sqlx::Result<TenantBackup> {
+        sqlx::query_as(
+            r#"
+            INSERT INTO "tenant_backups" (
+                "id", "env", "tenant_id", "db_name", "bucket", "key",
+                "size", "sha256", "reason", "job_id", "pending_migrations"
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11)
+            RETURNING *
+            "#,
+        )
+        .bind(create.id)
+        .bind(create.env)
+        .bind(create.tenant_id)
+        .bind(create.db_name)
+        .bind(create.bucket)
+        .bind(create.key)
+        .bind(create.size)
+        .bind(create.sha256)
+        .bind(create.reason)
+        .bind(create.job_id)
+        .bind(create.pending_migrations)
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn find_by_id(
+        db: &PgPool,
+        env: &str,
+        tenant_id: Uuid,
+        id: Uuid,
+    ) -> sqlx::Result<Option<TenantBackup>> {
+        sqlx::query_as(
+            r#"
+            SELECT * FROM "tenant_backups"
+            WHERE "env" = $1 AND "tenant_id" = $2 AND "id" = $3
+            "#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .bind(id)
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Get all backups for a tenant, newest first
+    pub async fn find_by_tenant(
+        db: &PgPool,
+        env: &str,
+        tenant_id: Uuid,
+    ) -> sqlx::Result<Vec<TenantBackup>> {
+        sqlx::query_as(
+            r#"
+            SELECT * FROM "tenant_backups"
+            WHERE "env" = $1 AND "tenant_id" = $2
+            ORDER BY "created_at" DESC
+            "#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .fetch_all(db)
+        .await
+    }
+
+    /// Remove every backup of a tenant from the catalogue, the archives
+    /// themselves are left in the backup bucket
+    pub async fn delete_by_tenant(db: &PgPool, env: &str, tenant_id: Uuid) -> sqlx::Result<()> {
+        sqlx::query(r#"DELETE FROM "tenant_backups" WHERE "env" = $1 AND "tenant_id" = $2"#)
+            .bind(env)
+            .bind(tenant_id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct CredentialRotation {
+    pub id: Uuid,
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+    pub secret_name: String,
+    pub role_name: String,
+    pub rotated_at: DateTime<Utc>,
+}
+
+pub struct CreateCredentialRotation {
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+    pub secret_name: String,
+    pub role_name: String,
+}
+
+impl CredentialRotation {
+    pub async fn create(
+        db: &PgPool,
+        create: CreateCredentialRotation,
+    ) -> sqlx::Result<CredentialRotation> {
+        sqlx::query_as(
+            r#"
+            INSERT INTO "credential_rotations" ("id", "env", "tenant_id", "secret_name", "role_name")
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(create.env)
+        .bind(create.tenant_id)
+        .bind(create.secret_name)
+        .bind(create.role_name)
+        .fetch_one(db)
+        .await
+    }
+
+    /// Get the most recent rotation for every secret
+    pub async fn latest(db: &PgPool) -> sqlx::Result<Vec<CredentialRotation>> {
+        sqlx::query_as(
+            r#"
+            SELECT DISTINCT ON ("secret_name") *
+            FROM "credential_rotations"
+            ORDER BY "secret_name", "rotated_at" DESC
+            "#,
+        )
+        .fetch_all(db)
+        .await
+    }
+
+    /// Get the most recent rotation for a specific secret
+    pub async fn latest_for_secret(
+        db: &PgPool,
+        secret_name: &str,
+    ) -> sqlx::Result<Option<CredentialRotation>> {
+        sqlx::query_as(
+            r#"
+            SELECT * FROM "credential_rotations"
+            WHERE "secret_name" = $1
+            ORDER BY "rotated_at" DESC
+            LIMIT 1
+            "#,
+        )
+        .bind(secret_name)
+        .fetch_optional(db)
+        .await
+    }
+}
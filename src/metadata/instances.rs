@@ -0,0 +1,44 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ManagerInstance {
+    pub id: Uuid,
+    pub started_at: DateTime<Utc>,
+    pub heartbeat_at: DateTime<Utc>,
+}
+
+impl ManagerInstance {
+    /// Record the heartbeat of the instance with `id`, registering the
+    /// instance if it has not been seen before
+    pub async fn heartbeat(db: &PgPool, id: Uuid) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            INSERT INTO "manager_instances" ("id")
+            VALUES ($1)
+            ON CONFLICT ("id") DO UPDATE SET "heartbeat_at" = NOW()
+            "#,
+        )
+        .bind(id)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Remove instances whose heartbeat is older than `expiry`
+    pub async fn delete_expired(db: &PgPool, expiry: Duration) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM "manager_instances"
+            WHERE "heartbeat_at" < NOW() - MAKE_INTERVAL(secs => $1)
+            "#,
+        )
+        .bind(expiry.as_secs_f64())
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,345 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum JobKind {
+    /// Backup of a tenant database
+    TenantBackup,
+    /// Restore of a tenant database backup
+    TenantRestore,
+    /// Migration of many tenants
+    FleetMigration,
+    /// Staged migration of tenants in waves
+    MigrationRollout,
+    /// Rollback of a tenant to an earlier migration
+    TenantRollback,
+    /// Rollback of many tenants to an earlier migration
+    FleetRollback,
+    /// Schema drift check of many tenants
+    DriftCheck,
+    /// Purge of an archived tenant after its retention period
+    TenantPurge,
+    /// Clone of a tenant into another environment
+    TenantClone,
+    /// Export of a tenant into a portable archive
+    TenantExport,
+    /// Import of a tenant from a portable archive
+    TenantImport,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum JobStatus {
+    Pending,
+    Running,
+    Succeeded,
+    Failed,
+    Cancelled,
+}
+
+impl JobStatus {
+    /// Whether the job has finished running
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            self,
+            JobStatus::Succeeded | JobStatus::Failed | JobStatus::Cancelled
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum JobLogLevel {
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct JobLog {
+    pub id: i64,
+    pub job_id: Uuid,
+    pub level: JobLogLevel,
+    pub message: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl JobLog {
+    pub async fn create(
+        db: &PgPool,
+        job_id: Uuid,
+        level: JobLogLevel,
+        message: &str,
+    ) -> sqlx::Result<JobLog> {
+        sqlx::query_as(
+            r#"
+            INSERT INTO "job_logs" ("job_id", "level", "message")
+            VALUES ($1, $2, $3)
+            RETURNING *
+            "#,
+        )
+        .bind(job_id)
+        .bind(level)
+        .bind(message)
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn find_by_job(db: &PgPool, job_id: Uuid) -> sqlx::Result<Vec<JobLog>> {
+        sqlx::query_as(r#"SELECT * FROM "job_logs" WHERE "job_id" = $1 ORDER BY "id""#)
+            .bind(job_id)
+            .fetch_all(db)
+            .await
+    }
+
+    /// Find logs for a job created after the log with `after_id`
+    pub async fn find_after(db: &PgPool, job_id: Uuid, after_id: i64) -> sqlx::Result<Vec<JobLog>> {
+        sqlx::query_as(
+            r#"SELECT * FROM "job_logs" WHERE "job_id" = $1 AND "id" > $2 ORDER BY "id""#,
+        )
+        .bind(job_id)
+        .bind(after_id)
+        .fetch_all(db)
+        .await
+    }
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct Job {
+    pub id: Uuid,
+    pub kind: JobKind,
+    pub status: JobStatus,
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+    pub output: Option<serde_json::Value>,
+    pub error: Option<String>,
+    /// Progress reported by the job while running
+    pub progress: Option<serde_json::Value>,
+    /// Whether cancellation of the job has been requested
+    pub cancel_requested: bool,
+    /// Whether the job has been paused
+    pub paused: bool,
+    /// Manager instance running the job
+    pub instance_id: Option<Uuid>,
+    pub created_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+pub struct CreateJob {
+    pub kind: JobKind,
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+}
+
+impl Job {
+    /// Create a job run by the manager instance with `instance_id`
+    pub async fn create(db: &PgPool, instance_id: Uuid, create: CreateJob) -> sqlx::Result<Job> {
+        sqlx::query_as(
+            r#"
+            INSERT INTO "jobs" ("id", "kind", "status", "env", "tenant_id", "instance_id")
+            VALUES ($1, $2, $3, $4, $5, $6)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(create.kind)
+        .bind(JobStatus::Pending)
+        .bind(create.env)
+        .bind(create.tenant_id)
+        .bind(instance_id)
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn find_by_id(db: &PgPool, id: Uuid) -> sqlx::Result<Option<Job>> {
+        sqlx::query_as(r#"SELECT * FROM "jobs" WHERE "id" = $1"#)
+            .bind(id)
+            .fetch_optional(db)
+            .await
+    }
+
+    /// Get the most recent jobs, optionally filtered to a specific tenant
+    pub async fn recent(
+        db: &PgPool,
+        tenant: Option<(&str, Uuid)>,
+        limit: i64,
+    ) -> sqlx::Result<Vec<Job>> {
+        let (env, tenant_id) = tenant.unzip();
+        sqlx::query_as(
+            r#"
+            SELECT * FROM "jobs"
+            WHERE ($1::VARCHAR IS NULL OR "env" = $1)
+              AND ($2::UUID IS NULL OR "tenant_id" = $2)
+            ORDER BY "created_at" DESC
+            LIMIT $3
+            "#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .bind(limit)
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn set_running(db: &PgPool, id: Uuid) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"UPDATE "jobs" SET "status" = $2, "started_at" = NOW() WHERE "id" = $1"#,
+        )
+        .bind(id)
+        .bind(JobStatus::Running)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_succeeded(
+        db: &PgPool,
+        id: Uuid,
+        output: serde_json::Value,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE "jobs"
+            SET "status" = $2, "output" = $3, "finished_at" = NOW()
+            WHERE "id" = $1
+            "#,
+        )
+        .bind(id)
+        .bind(JobStatus::Succeeded)
+        .bind(output)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_failed(db: &PgPool, id: Uuid, error: &str) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE "jobs"
+            SET "status" = $2, "error" = $3, "finished_at" = NOW()
+            WHERE "id" = $1
+            "#,
+        )
+        .bind(id)
+        .bind(JobStatus::Failed)
+        .bind(error)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_cancelled(
+        db: &PgPool,
+        id: Uuid,
+        output: serde_json::Value,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE "jobs"
+            SET "status" = $2, "output" = $3, "finished_at" = NOW()
+            WHERE "id" = $1
+            "#,
+        )
+        .bind(id)
+        .bind(JobStatus::Cancelled)
+        .bind(output)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn set_progress(
+        db: &PgPool,
+        id: Uuid,
+        progress: &serde_json::Value,
+    ) -> sqlx::Result<()> {
+        sqlx::query(r#"UPDATE "jobs" SET "progress" = $2 WHERE "id" = $1"#)
+            .bind(id)
+            .bind(progress)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    /// Request cancellation of a job that has not finished, returns
+    /// false if the job was not found or already finished
+    pub async fn request_cancel(db: &PgPool, id: Uuid) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE "jobs" SET "cancel_requested" = TRUE
+            WHERE "id" = $1 AND "status" IN ($2, $3)
+            "#,
+        )
+        .bind(id)
+        .bind(JobStatus::Pending)
+        .bind(JobStatus::Running)
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Pause or resume a job that has not finished, returns false if the
+    /// job was not found or already finished
+    pub async fn set_paused(db: &PgPool, id: Uuid, paused: bool) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE "jobs" SET "paused" = $2
+            WHERE "id" = $1 AND "status" IN ($3, $4)
+            "#,
+        )
+        .bind(id)
+        .bind(paused)
+        .bind(JobStatus::Pending)
+        .bind(JobStatus::Running)
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn is_paused(db: &PgPool, id: Uuid) -> sqlx::Result<bool> {
+        sqlx::query_scalar(r#"SELECT "paused" FROM "jobs" WHERE "id" = $1"#)
+            .bind(id)
+            .fetch_one(db)
+            .await
+    }
+
+    pub async fn is_cancel_requested(db: &PgPool, id: Uuid) -> sqlx::Result<bool> {
+        sqlx::query_scalar(r#"SELECT "cancel_requested" FROM "jobs" WHERE "id" = $1"#)
+            .bind(id)
+            .fetch_one(db)
+            .await
+    }
+
+    /// Mark any jobs left pending or running by a manager instance whose
+    /// heartbeat is older than `expiry` as failed, jobs owned by running
+    /// instances are left alone
+    pub async fn fail_interrupted(db: &PgPool, expiry: Duration) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE "jobs"
+            SET "status" = $1, "error" = 'interrupted, manager instance stopped', "finished_at" = NOW()
+            WHERE "status" IN ($2, $3)
+              AND NOT EXISTS (
+                SELECT 1 FROM "manager_instances" "instance"
+                WHERE "instance"."id" = "jobs"."instance_id"
+                  AND "instance"."heartbeat_at" >= NOW() - MAKE_INTERVAL(secs => $4)
+              )
+            "#,
+        )
+        .bind(JobStatus::Failed)
+        .bind(JobStatus::Pending)
+        .bind(JobStatus::Running)
+        .bind(expiry.as_secs_f64())
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,72 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TenantMigrationApplication {
+    pub id: Uuid,
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    pub applied_by: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+impl TenantMigrationApplication {
+    /// Record that `names` were applied to the tenant by `applied_by`
+    pub async fn create_many(
+        db: &PgPool,
+        env: &str,
+        tenant_id: Uuid,
+        names: &[String],
+        applied_by: &str,
+    ) -> sqlx::Result<()> {
+        let ids: Vec<Uuid> = names.iter().map(|_| Uuid::new_v4()).collect();
+
+        sqlx::query(
+            r#"
+            INSERT INTO "tenant_migration_applications" ("id", "env", "tenant_id", "name", "applied_by")
+            SELECT "id", $2, $3, "name", $4
+            FROM UNNEST($1::UUID[], $5::VARCHAR[]) AS "applied" ("id", "name")
+            "#,
+        )
+        .bind(ids)
+        .bind(env)
+        .bind(tenant_id)
+        .bind(applied_by)
+        .bind(names)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn find_by_tenant(
+        db: &PgPool,
+        env: &str,
+        tenant_id: Uuid,
+    ) -> sqlx::Result<Vec<TenantMigrationApplication>> {
+        sqlx::query_as(
+            r#"
+            SELECT * FROM "tenant_migration_applications"
+            WHERE "env" = $1 AND "tenant_id" = $2
+            ORDER BY "applied_at"
+            "#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn delete_by_tenant(db: &PgPool, env: &str, tenant_id: Uuid) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"DELETE FROM "tenant_migration_applications" WHERE "env" = $1 AND "tenant_id" = $2"#,
+        )
+        .bind(env)
+        .bind(tenant_id)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+}
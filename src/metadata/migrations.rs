@@ -0,0 +1,140 @@
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+
+/// Embedded migrations for the manager database, applied in order
+pub const MIGRATIONS: &[(&str, &str)] = &[
+    (
+        "m1_create_audit_events",
+        include_str!("../../migrations/m1_create_audit_events.sql"),
+    ),
+    (
+        "m2_create_jobs",
+        include_str!("../../migrations/m2_create_jobs.sql"),
+    ),
+    (
+        "m3_create_tenant_backups",
+        include_str!("../../migrations/m3_create_tenant_backups.sql"),
+    ),
+    (
+        "m4_create_credential_rotations",
+        include_str!("../../migrations/m4_create_credential_rotations.sql"),
+    ),
+    (
+        "m5_create_tenant_migration_applications",
+        include_str!("../../migrations/m5_create_tenant_migration_applications.sql"),
+    ),
+    (
+        "m6_add_job_progress",
+        include_str!("../../migrations/m6_add_job_progress.sql"),
+    ),
+    (
+        "m7_add_job_pause",
+        include_str!("../../migrations/m7_add_job_pause.sql"),
+    ),
+    (
+        "m8_create_tenant_tags",
+        include_str!("../../migrations/m8_create_tenant_tags.sql"),
+    ),
+    (
+        "m9_add_backup_pending_migrations",
+        include_str!("../../migrations/m9_add_backup_pending_migrations.sql"),
+    ),
+    (
+        "m10_create_scheduled_migrations",
+        include_str!("../../migrations/m10_create_scheduled_migrations.sql"),
+    ),
+    (
+        "m11_create_migration_locks",
+        include_str!("../../migrations/m11_create_migration_locks.sql"),
+    ),
+    (
+        "m12_create_archived_tenants",
+        include_str!("../../migrations/m12_create_archived_tenants.sql"),
+    ),
+];
+
+/// Advisory lock key held while applying migrations to prevent multiple
+/// manager instances migrating at the same time
+const MIGRATIONS_LOCK_KEY: i64 = 0x646f_6362_6f78_6d31;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct AppliedMigration {
+    pub name: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+async fn create_migrations_table(db: &PgPool) -> sqlx::Result<()> {
+    sqlx::query(
+        r#"
+        CREATE TABLE IF NOT EXISTS "manager_migrations" (
+            "name" VARCHAR NOT NULL PRIMARY KEY,
+            "applied_at" TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        "#,
+    )
+    .execute(db)
+    .await?;
+    Ok(())
+}
+
+/// Get all migrations that have been applied to the manager database
+pub async fn get_applied_migrations(db: &PgPool) -> sqlx::Result<Vec<AppliedMigration>> {
+    sqlx::query_as(
+        r#"SELECT "name", "applied_at" FROM "manager_migrations" ORDER BY "applied_at", "name""#,
+    )
+    .fetch_all(db)
+    .await
+}
+
+/// Get the names of all embedded migrations that have not been applied
+pub async fn get_pending_migrations(db: &PgPool) -> sqlx::Result<Vec<String>> {
+    let applied = get_applied_migrations(db).await?;
+
+    Ok(MIGRATIONS
+        .iter()
+        .filter(|(name, _)| !applied.iter().any(|applied| applied.name.eq(name)))
+        .map(|(name, _)| name.to_string())
+        .collect())
+}
+
+/// Apply all pending migrations to the manager database
+pub async fn apply_migrations(db: &PgPool) -> anyhow::Result<()> {
+    create_migrations_table(db)
+        .await
+        .context("failed to create migrations table")?;
+
+    let mut t = db.begin().await?;
+
+    sqlx::query("SELECT pg_advisory_xact_lock($1)")
+        .bind(MIGRATIONS_LOCK_KEY)
+        .execute(&mut *t)
+        .await
+        .context("failed to acquire migrations lock")?;
+
+    let applied: Vec<String> = sqlx::query_scalar(r#"SELECT "name" FROM "manager_migrations""#)
+        .fetch_all(&mut *t)
+        .await?;
+
+    for (name, sql) in MIGRATIONS {
+        if applied.iter().any(|applied| applied.eq(name)) {
+            continue;
+        }
+
+        tracing::info!(%name, "applying manager migration");
+
+        sqlx::raw_sql(sql)
+            .execute(&mut *t)
+            .await
+            .with_context(|| format!("failed to apply manager migration {name}"))?;
+
+        sqlx::query(r#"INSERT INTO "manager_migrations" ("name") VALUES ($1)"#)
+            .bind(*name)
+            .execute(&mut *t)
+            .await?;
+    }
+
+    t.commit().await?;
+    Ok(())
+}
@@ -0,0 +1,78 @@
+//! Manager owned metadata database
+//!
+//! Stores state that belongs to the manager itself rather than to the
+//! docbox root or tenant databases
+
+use crate::{
+    config::DatabaseConfig,
+    database::{
+        DEFAULT_MAX_CONNECTIONS, MAINTENANCE_DATABASE_NAME, create_database, database_exists,
+        is_duplicate_database,
+    },
+};
+use anyhow::Context;
+use sqlx::{PgPool, postgres::PgPoolOptions};
+
+pub mod archives;
+pub mod audit;
+pub mod backups;
+pub mod credentials;
+pub mod instances;
+pub mod jobs;
+pub mod migration_applications;
+pub mod migrations;
+pub mod schedules;
+pub mod tags;
+
+pub struct MetadataDatabase {
+    pub pool: PgPool,
+}
+
+impl MetadataDatabase {
+    /// Connect to the metadata database, creating it and applying any
+    /// pending migrations when required
+    pub async fn initialize(config: &DatabaseConfig) -> anyhow::Result<MetadataDatabase> {
+        create_database_if_missing(config).await?;
+
+        let options = config.connect_options(&config.manager_database_name);
+        let pool = PgPoolOptions::new()
+            .max_connections(config.max_connections.unwrap_or(DEFAULT_MAX_CONNECTIONS))
+            .connect_with(options)
+            .await
+            .context("failed to connect to manager database")?;
+
+        migrations::apply_migrations(&pool).await?;
+
+        Ok(MetadataDatabase { pool })
+    }
+}
+
+/// Create the metadata database if it does not already exist
+async fn create_database_if_missing(config: &DatabaseConfig) -> anyhow::Result<()> {
+    let db = PgPool::connect_with(config.connect_options(MAINTENANCE_DATABASE_NAME))
+        .await
+        .context("failed to connect to maintenance database")?;
+
+    let exists = database_exists(&db, &config.manager_database_name)
+        .await
+        .context("failed to check for manager database")?;
+
+    if !exists {
+        tracing::info!(
+            database = %config.manager_database_name,
+            "creating manager database"
+        );
+
+        match create_database(&db, &config.manager_database_name).await {
+            Ok(_) => {}
+            // Another manager instance created the database first
+            Err(error) if is_duplicate_database(&error) => {}
+            Err(error) => {
+                return Err(anyhow::Error::new(error).context("failed to create manager database"));
+            }
+        }
+    }
+
+    db.close().await;
+    Ok(())
+}
@@ -0,0 +1,228 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use std::time::Duration;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, sqlx::Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "text", rename_all = "snake_case")]
+pub enum ScheduleStatus {
+    /// Waiting for the maintenance window to open
+    Scheduled,
+    /// Migration job is running
+    Running,
+    /// Every tenant migrated successfully
+    Completed,
+    /// Migration failed for one or more tenants
+    Failed,
+    /// Maintenance window closed before the migration could start
+    Missed,
+    Cancelled,
+}
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct ScheduledMigration {
+    pub id: Uuid,
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+    pub target_migration_name: Option<String>,
+    pub skip_failed: bool,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub status: ScheduleStatus,
+    pub job_id: Option<Uuid>,
+    pub error: Option<String>,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub finished_at: Option<DateTime<Utc>>,
+    /// Manager instance running the schedule once claimed
+    pub instance_id: Option<Uuid>,
+}
+
+pub struct CreateScheduledMigration {
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+    pub target_migration_name: Option<String>,
+    pub skip_failed: bool,
+    pub window_start: DateTime<Utc>,
+    pub window_end: DateTime<Utc>,
+    pub created_by: String,
+}
+
+impl ScheduledMigration {
+    pub async fn create(
+        db: &PgPool,
+        create: CreateScheduledMigration,
+    ) -> sqlx::Result<ScheduledMigration> {
+        sqlx::query_as(
+            r#"
+            INSERT INTO "scheduled_migrations" (
+                "id", "env", "tenant_id", "target_migration_name", "skip_failed",
+                "window_start", "window_end", "status", "created_by"
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING *
+            "#,
+        )
+        .bind(Uuid::new_v4())
+        .bind(create.env)
+        .bind(create.tenant_id)
+        .bind(create.target_migration_name)
+        .bind(create.skip_failed)
+        .bind(create.window_start)
+        .bind(create.window_end)
+        .bind(ScheduleStatus::Scheduled)
+        .bind(create.created_by)
+        .fetch_one(db)
+        .await
+    }
+
+    pub async fn find_by_id(db: &PgPool, id: Uuid) -> sqlx::Result<Option<ScheduledMigration>> {
+        sqlx::query_as(r#"SELECT * FROM "scheduled_migrations" WHERE "id" = $1"#)
+            .bind(id)
+            .fetch_optional(db)
+            .await
+    }
+
+    /// Get the most recent schedules, newest window first
+    pub async fn recent(db: &PgPool, limit: i64) -> sqlx::Result<Vec<ScheduledMigration>> {
+        sqlx::query_as(
+            r#"SELECT * FROM "scheduled_migrations" ORDER BY "window_start" DESC LIMIT $1"#,
+        )
+        .bind(limit)
+        .fetch_all(db)
+        .await
+    }
+
+    /// Claim a schedule whose window is open for the manager instance
+    /// with `instance_id`, marking it as running. Claimed schedules are
+    /// skipped by other manager instances
+    pub async fn claim_due(
+        db: &PgPool,
+        instance_id: Uuid,
+    ) -> sqlx::Result<Option<ScheduledMigration>> {
+        sqlx::query_as(
+            r#"
+            UPDATE "scheduled_migrations" SET "status" = $1, "instance_id" = $3
+            WHERE "id" = (
+                SELECT "id" FROM "scheduled_migrations"
+                WHERE "status" = $2 AND "window_start" <= NOW() AND "window_end" > NOW()
+                ORDER BY "window_start"
+                LIMIT 1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind(ScheduleStatus::Running)
+        .bind(ScheduleStatus::Scheduled)
+        .bind(instance_id)
+        .fetch_optional(db)
+        .await
+    }
+
+    /// Mark schedules whose window closed before they started as missed
+    pub async fn mark_missed(db: &PgPool) -> sqlx::Result<Vec<ScheduledMigration>> {
+        sqlx::query_as(
+            r#"
+            UPDATE "scheduled_migrations"
+            SET "status" = $1, "finished_at" = NOW()
+            WHERE "status" = $2 AND "window_end" <= NOW()
+            RETURNING *
+            "#,
+        )
+        .bind(ScheduleStatus::Missed)
+        .bind(ScheduleStatus::Scheduled)
+        .fetch_all(db)
+        .await
+    }
+
+    pub async fn set_job(db: &PgPool, id: Uuid, job_id: Uuid) -> sqlx::Result<()> {
+        sqlx::query(r#"UPDATE "scheduled_migrations" SET "job_id" = $2 WHERE "id" = $1"#)
+            .bind(id)
+            .bind(job_id)
+            .execute(db)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn set_finished(
+        db: &PgPool,
+        id: Uuid,
+        status: ScheduleStatus,
+        error: Option<&str>,
+    ) -> sqlx::Result<()> {
+        sqlx::query(
+            r#"
+            UPDATE "scheduled_migrations"
+            SET "status" = $2, "error" = $3, "finished_at" = NOW()
+            WHERE "id" = $1
+            "#,
+        )
+        .bind(id)
+        .bind(status)
+        .bind(error)
+        .execute(db)
+        .await?;
+        Ok(())
+    }
+
+    /// Cancel a schedule that has not started, returns false if the
+    /// schedule was not found or has already started
+    pub async fn cancel(db: &PgPool, id: Uuid) -> sqlx::Result<bool> {
+        let result = sqlx::query(
+            r#"
+            UPDATE "scheduled_migrations"
+            SET "status" = $2, "finished_at" = NOW()
+            WHERE "id" = $1 AND "status" = $3
+            "#,
+        )
+        .bind(id)
+        .bind(ScheduleStatus::Cancelled)
+        .bind(ScheduleStatus::Scheduled)
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Cancel every schedule that has not started
+    pub async fn cancel_all(db: &PgPool) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE "scheduled_migrations"
+            SET "status" = $1, "finished_at" = NOW()
+            WHERE "status" = $2
+            "#,
+        )
+        .bind(ScheduleStatus::Cancelled)
+        .bind(ScheduleStatus::Scheduled)
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Mark any schedules left running by a manager instance whose
+    /// heartbeat is older than `expiry` as failed, their jobs can no
+    /// longer complete
+    pub async fn fail_interrupted(db: &PgPool, expiry: Duration) -> sqlx::Result<u64> {
+        let result = sqlx::query(
+            r#"
+            UPDATE "scheduled_migrations"
+            SET "status" = $1, "error" = 'interrupted, manager instance stopped', "finished_at" = NOW()
+            WHERE "status" = $2
+              AND NOT EXISTS (
+                SELECT 1 FROM "manager_instances" "instance"
+                WHERE "instance"."id" = "scheduled_migrations"."instance_id"
+                  AND "instance"."heartbeat_at" >= NOW() - MAKE_INTERVAL(secs => $3)
+              )
+            "#,
+        )
+        .bind(ScheduleStatus::Failed)
+        .bind(ScheduleStatus::Running)
+        .bind(expiry.as_secs_f64())
+        .execute(db)
+        .await?;
+        Ok(result.rows_affected())
+    }
+}
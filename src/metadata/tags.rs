@@ -0,0 +1,70 @@
+use serde::Serialize;
+use sqlx::{FromRow, PgPool};
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Serialize, FromRow)]
+pub struct TaggedTenant {
+    pub env: String,
+    pub tenant_id: Uuid,
+}
+
+/// Get the tags assigned to a tenant
+pub async fn get_tenant_tags(db: &PgPool, env: &str, tenant_id: Uuid) -> sqlx::Result<Vec<String>> {
+    sqlx::query_scalar(
+        r#"
+        SELECT "tag" FROM "tenant_tags"
+        WHERE "env" = $1 AND "tenant_id" = $2
+        ORDER BY "tag"
+        "#,
+    )
+    .bind(env)
+    .bind(tenant_id)
+    .fetch_all(db)
+    .await
+}
+
+/// Replace the tags assigned to a tenant
+pub async fn set_tenant_tags(
+    db: &PgPool,
+    env: &str,
+    tenant_id: Uuid,
+    tags: &[String],
+) -> sqlx::Result<()> {
+    let mut t = db.begin().await?;
+
+    sqlx::query(r#"DELETE FROM "tenant_tags" WHERE "env" = $1 AND "tenant_id" = $2"#)
+        .bind(env)
+        .bind(tenant_id)
+        .execute(&mut *t)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO "tenant_tags" ("env", "tenant_id", "tag")
+        SELECT $1, $2, "tag" FROM UNNEST($3::VARCHAR[]) AS "tags" ("tag")
+        ON CONFLICT DO NOTHING
+        "#,
+    )
+    .bind(env)
+    .bind(tenant_id)
+    .bind(tags)
+    .execute(&mut *t)
+    .await?;
+
+    t.commit().await
+}
+
+/// Find the tenants that have every one of `tags`
+pub async fn find_tenants_with_tags(db: &PgPool, tags: &[String]) -> sqlx::Result<Vec<TaggedTenant>> {
+    sqlx::query_as(
+        r#"
+        SELECT "env", "tenant_id" FROM "tenant_tags"
+        WHERE "tag" = ANY($1::VARCHAR[])
+        GROUP BY "env", "tenant_id"
+        HAVING COUNT(DISTINCT "tag") = CARDINALITY($1::VARCHAR[])
+        "#,
+    )
+    .bind(tags)
+    .fetch_all(db)
+    .await
+}
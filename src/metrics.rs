@@ -0,0 +1,209 @@
+//! Prometheus text format rendering for manager metrics
+
+use crate::{database::PoolStats, models::system::DatabaseStatsResponse};
+use std::fmt::Write;
+
+/// Name, help text and value of a per pool metric
+type PoolMetric = (&'static str, &'static str, fn(&PoolStats) -> u64);
+
+/// Render the database statistics in the Prometheus text exposition format
+pub fn render_database_metrics(stats: &DatabaseStatsResponse) -> String {
+    let mut out = String::new();
+
+    let pool_gauges: [PoolMetric; 4] = [
+        (
+            "docbox_manager_pool_max_connections",
+            "Maximum number of connections for the pool",
+            |pool| pool.max_connections as u64,
+        ),
+        (
+            "docbox_manager_pool_size",
+            "Number of open connections in the pool",
+            |pool| pool.size as u64,
+        ),
+        (
+            "docbox_manager_pool_idle",
+            "Number of idle connections in the pool",
+            |pool| pool.idle as u64,
+        ),
+        (
+            "docbox_manager_pool_active",
+            "Number of connections in use from the pool",
+            |pool| pool.active as u64,
+        ),
+    ];
+
+    for (name, help, value) in pool_gauges {
+        _ = writeln!(out, "# HELP {name} {help}");
+        _ = writeln!(out, "# TYPE {name} gauge");
+        for pool in &stats.pools {
+            _ = writeln!(
+                out,
+                "{name}{{database=\"{}\"}} {}",
+                escape_label(&pool.database),
+                value(pool)
+            );
+        }
+    }
+
+    let pool_counters: [PoolMetric; 2] = [
+        (
+            "docbox_manager_pool_acquire_total",
+            "Total number of connections acquired from the pool",
+            |pool| pool.acquire.acquired,
+        ),
+        (
+            "docbox_manager_pool_connections_opened_total",
+            "Total number of connections opened by the pool",
+            |pool| pool.acquire.opened,
+        ),
+    ];
+
+    for (name, help, value) in pool_counters {
+        _ = writeln!(out, "# HELP {name} {help}");
+        _ = writeln!(out, "# TYPE {name} counter");
+        for pool in &stats.pools {
+            _ = writeln!(
+                out,
+                "{name}{{database=\"{}\"}} {}",
+                escape_label(&pool.database),
+                value(pool)
+            );
+        }
+    }
+
+    let name = "docbox_manager_server_connections";
+    _ = writeln!(
+        out,
+        "# HELP {name} Server side connections for each database by state"
+    );
+    _ = writeln!(out, "# TYPE {name} gauge");
+    for database in &stats.server {
+        let activity = &database.activity;
+        let (env, tenant_id) = match &database.tenant {
+            Some(tenant) => (tenant.env.clone(), tenant.id.to_string()),
+            None => (String::new(), String::new()),
+        };
+
+        for (state, value) in [
+            ("active", activity.active),
+            ("idle", activity.idle),
+            ("idle_in_transaction", activity.idle_in_transaction),
+            ("waiting", activity.waiting),
+        ] {
+            _ = writeln!(
+                out,
+                "{name}{{database=\"{}\",env=\"{}\",tenant_id=\"{}\",state=\"{state}\"}} {value}",
+                escape_label(&activity.database),
+                escape_label(&env),
+                tenant_id,
+            );
+        }
+    }
+
+    out
+}
+
+/// Escape a Prometheus label value
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        database::{AcquireStats, ServerActivity},
+        models::system::{DatabaseActivity, DatabaseTenant},
+    };
+    use uuid::Uuid;
+
+    #[test]
+    fn test_escape_label() {
+        assert_eq!(escape_label("docbox"), "docbox");
+        assert_eq!(escape_label("a\"b"), "a\\\"b");
+        assert_eq!(escape_label("a\\b"), "a\\\\b");
+        assert_eq!(escape_label("a\nb"), "a\\nb");
+    }
+
+    #[test]
+    fn test_render_database_metrics() {
+        let tenant_id = Uuid::nil();
+        let stats = DatabaseStatsResponse {
+            pools: vec![PoolStats {
+                database: "docbox_dev_tenant".to_string(),
+                max_connections: 10,
+                size: 3,
+                idle: 2,
+                active: 1,
+                closed: false,
+                acquire: AcquireStats {
+                    acquired: 42,
+                    opened: 3,
+                },
+            }],
+            server: vec![DatabaseActivity {
+                activity: ServerActivity {
+                    database: "docbox_dev_tenant".to_string(),
+                    total: 4,
+                    active: 1,
+                    idle: 2,
+                    idle_in_transaction: 1,
+                    waiting: 0,
+                },
+                tenant: Some(DatabaseTenant {
+                    env: "dev".to_string(),
+                    id: tenant_id,
+                    name: "Tenant".to_string(),
+                }),
+            }],
+        };
+
+        let out = render_database_metrics(&stats);
+        let lines: Vec<&str> = out.lines().collect();
+
+        for line in [
+            "# TYPE docbox_manager_pool_size gauge",
+            "docbox_manager_pool_max_connections{database=\"docbox_dev_tenant\"} 10",
+            "docbox_manager_pool_size{database=\"docbox_dev_tenant\"} 3",
+            "docbox_manager_pool_idle{database=\"docbox_dev_tenant\"} 2",
+            "docbox_manager_pool_active{database=\"docbox_dev_tenant\"} 1",
+            "# TYPE docbox_manager_pool_acquire_total counter",
+            "docbox_manager_pool_acquire_total{database=\"docbox_dev_tenant\"} 42",
+            "docbox_manager_pool_connections_opened_total{database=\"docbox_dev_tenant\"} 3",
+        ] {
+            assert!(lines.contains(&line), "missing {line}");
+        }
+
+        let server = format!(
+            "docbox_manager_server_connections{{database=\"docbox_dev_tenant\",env=\"dev\",tenant_id=\"{tenant_id}\",state=\"idle_in_transaction\"}} 1"
+        );
+        assert!(lines.contains(&server.as_str()), "missing {server}");
+    }
+
+    #[test]
+    fn test_render_database_metrics_escapes_labels() {
+        let stats = DatabaseStatsResponse {
+            pools: vec![],
+            server: vec![DatabaseActivity {
+                activity: ServerActivity {
+                    database: "odd\"name".to_string(),
+                    total: 0,
+                    active: 0,
+                    idle: 0,
+                    idle_in_transaction: 0,
+                    waiting: 0,
+                },
+                tenant: None,
+            }],
+        };
+
+        let out = render_database_metrics(&stats);
+        assert!(out.contains(
+            "docbox_manager_server_connections{database=\"odd\\\"name\",env=\"\",tenant_id=\"\",state=\"active\"} 0"
+        ));
+    }
+}
@@ -0,0 +1,539 @@
+//! Inspection and dry-runs of pending tenant migrations
+
+use crate::{
+    database::DatabaseProvider,
+    jobs::JobContext,
+    locks::{LockScope, MigrationLock},
+    metadata::{
+        archives::ArchivedTenant, jobs::JobLogLevel,
+        migration_applications::TenantMigrationApplication,
+    },
+    models::root::MigrateTenantsRequest,
+};
+use anyhow::Context;
+use chrono::Utc;
+use docbox_database::{
+    ROOT_DATABASE_NAME,
+    migrations::{ROOT_MIGRATIONS, TENANT_MIGRATIONS, apply_tenant_migrations},
+    models::tenant::Tenant,
+};
+use docbox_management::database::DatabaseProvider as _;
+use serde::Serialize;
+use sqlx::{Executor, PgPool};
+use std::time::Instant;
+use uuid::Uuid;
+
+/// Key of the advisory lock held on a tenant database while it is migrated
+const TENANT_MIGRATION_LOCK_KEY: i64 = 0x646f_6362_6f78;
+
+/// Maximum time a dry-run will wait to acquire a lock before failing,
+/// prevents a dry-run from blocking the tenant behind a long held lock
+const DRY_RUN_LOCK_TIMEOUT_MS: u64 = 5_000;
+
+/// Maximum time each dry-run migration may run before failing, locks
+/// taken by the migrations are held on the tenant until the dry-run is
+/// rolled back so a migration needing longer fails the dry-run rather
+/// than blocking the tenant
+const DRY_RUN_STATEMENT_TIMEOUT_MS: u64 = 15_000;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MigrationSql {
+    pub name: String,
+    pub sql: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct DryRunReport {
+    /// Whether every pending migration applied cleanly
+    pub success: bool,
+    pub migrations: Vec<DryRunMigration>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum DryRunMigration {
+    Applied { name: String, duration_ms: u64 },
+    Failed { name: String, error: String },
+    /// Not attempted because an earlier migration failed
+    Skipped { name: String },
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FleetMigrationReport {
+    /// Number of tenants that had migrations applied
+    pub applied: usize,
+    /// Number of tenants that had no pending migrations
+    pub up_to_date: usize,
+    /// Number of tenants that failed to migrate
+    pub failed: usize,
+    /// Number of tenants that were not attempted
+    pub skipped: usize,
+    pub tenants: Vec<TenantMigrationOutcome>,
+}
+
+impl FleetMigrationReport {
+    pub fn push(&mut self, outcome: TenantMigrationOutcome) {
+        match &outcome.status {
+            TenantMigrationStatus::Applied { .. } => self.applied += 1,
+            TenantMigrationStatus::UpToDate => self.up_to_date += 1,
+            TenantMigrationStatus::Failed { .. } => self.failed += 1,
+            TenantMigrationStatus::Skipped { .. } => self.skipped += 1,
+        }
+        self.tenants.push(outcome);
+    }
+}
+
+/// Progress of a fleet migration running as a job
+#[derive(Debug, Serialize)]
+pub struct FleetMigrationProgress {
+    /// Total number of tenants being migrated
+    pub total: usize,
+    /// Number of tenants that have been processed
+    pub completed: usize,
+    pub applied: usize,
+    pub up_to_date: usize,
+    pub failed: usize,
+    pub skipped: usize,
+}
+
+impl FleetMigrationProgress {
+    fn new(total: usize, report: &FleetMigrationReport) -> Self {
+        Self {
+            total,
+            completed: report.tenants.len(),
+            applied: report.applied,
+            up_to_date: report.up_to_date,
+            failed: report.failed,
+            skipped: report.skipped,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TenantMigrationOutcome {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    #[serde(flatten)]
+    pub status: TenantMigrationStatus,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TenantMigrationStatus {
+    /// Migrations were applied to the tenant
+    Applied { migrations: Vec<String> },
+    /// Tenant had no pending migrations
+    UpToDate,
+    /// Migrating the tenant failed
+    Failed { error: String },
+    /// Tenant was not attempted
+    Skipped { reason: String },
+}
+
+impl TenantMigrationStatus {
+    /// Log level and message describing the status within job logs
+    pub fn log_message(&self) -> (JobLogLevel, String) {
+        match self {
+            TenantMigrationStatus::Applied { migrations } => (
+                JobLogLevel::Info,
+                format!("applied {}", migrations.join(", ")),
+            ),
+            TenantMigrationStatus::UpToDate => (JobLogLevel::Info, "already up to date".to_string()),
+            TenantMigrationStatus::Failed { error } => {
+                (JobLogLevel::Error, format!("failed to migrate: {error}"))
+            }
+            TenantMigrationStatus::Skipped { reason } => {
+                (JobLogLevel::Warn, format!("skipped: {reason}"))
+            }
+        }
+    }
+}
+
+impl TenantMigrationOutcome {
+    pub fn new(tenant: Tenant, status: TenantMigrationStatus) -> Self {
+        Self {
+            env: tenant.env,
+            tenant_id: tenant.id,
+            name: tenant.name,
+            status,
+        }
+    }
+}
+
+/// Get the SQL for the tenant migration named `name`
+pub fn tenant_migration_sql(name: &str) -> Option<&'static str> {
+    TENANT_MIGRATIONS
+        .iter()
+        .find(|(migration_name, _)| name.eq(*migration_name))
+        .map(|(_, sql)| *sql)
+}
+
+/// Get the name and SQL of every tenant migration
+pub fn all_tenant_migrations() -> Vec<MigrationSql> {
+    TENANT_MIGRATIONS
+        .iter()
+        .map(|(name, sql)| MigrationSql {
+            name: name.to_string(),
+            sql: sql.to_string(),
+        })
+        .collect()
+}
+
+/// Get the name and SQL of every migration pending for `tenant`
+pub async fn get_pending_migrations_sql(
+    db_provider: &DatabaseProvider,
+    tenant: &Tenant,
+) -> anyhow::Result<Vec<MigrationSql>> {
+    let pending =
+        docbox_management::tenant::get_pending_tenant_migrations::get_pending_tenant_migrations(
+            db_provider,
+            tenant,
+        )
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    pending
+        .into_iter()
+        .map(|name| {
+            let sql = tenant_migration_sql(&name)
+                .with_context(|| format!("unknown tenant migration {name}"))?;
+            Ok(MigrationSql {
+                name,
+                sql: sql.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Apply the pending migrations for `tenant` inside a transaction that is
+/// always rolled back, proving whether they apply cleanly without
+/// changing the tenant database
+pub async fn dry_run_tenant_migrations(
+    db_provider: &DatabaseProvider,
+    tenant: &Tenant,
+) -> anyhow::Result<DryRunReport> {
+    let pending = get_pending_migrations_sql(db_provider, tenant).await?;
+
+    let db = db_provider
+        .connect(&tenant.db_name)
+        .await
+        .context("failed to connect to tenant database")?;
+
+    let mut t = db.begin().await?;
+    sqlx::query(&format!("SET LOCAL lock_timeout = {DRY_RUN_LOCK_TIMEOUT_MS}"))
+        .execute(&mut *t)
+        .await?;
+    sqlx::query(&format!(
+        "SET LOCAL statement_timeout = {DRY_RUN_STATEMENT_TIMEOUT_MS}"
+    ))
+    .execute(&mut *t)
+    .await?;
+
+    let mut success = true;
+    let mut migrations = Vec::with_capacity(pending.len());
+
+    for MigrationSql { name, sql } in pending {
+        if !success {
+            migrations.push(DryRunMigration::Skipped { name });
+            continue;
+        }
+
+        let start = Instant::now();
+        match t.execute(sqlx::raw_sql(&sql)).await {
+            Ok(_) => migrations.push(DryRunMigration::Applied {
+                name,
+                duration_ms: start.elapsed().as_millis() as u64,
+            }),
+            Err(error) => {
+                success = false;
+                migrations.push(DryRunMigration::Failed {
+                    name,
+                    error: error.to_string(),
+                });
+            }
+        }
+    }
+
+    t.rollback().await?;
+
+    Ok(DryRunReport {
+        success,
+        migrations,
+    })
+}
+
+/// Get the names of the root migrations that have not been applied
+pub async fn get_pending_root_migrations(
+    db_provider: &DatabaseProvider,
+) -> anyhow::Result<Vec<String>> {
+    docbox_management::root::get_pending_root_migrations::get_pending_root_migrations(db_provider)
+        .await
+        .map_err(anyhow::Error::new)
+}
+
+/// Most recent root migration applied (in migration order) given the
+/// `pending` root migrations
+pub fn root_schema_version(pending: &[String]) -> Option<String> {
+    ROOT_MIGRATIONS
+        .iter()
+        .map(|(name, _)| *name)
+        .rfind(|name| !pending.iter().any(|pending| pending == name))
+        .map(str::to_string)
+}
+
+/// Apply pending root migrations (up to `target_migration_name` when
+/// provided), returns the names of the applied migrations
+pub async fn migrate_root(
+    db_provider: &DatabaseProvider,
+    target_migration_name: Option<&str>,
+    actor: &str,
+) -> anyhow::Result<Vec<String>> {
+    let pending_before = get_pending_root_migrations(db_provider).await?;
+
+    docbox_management::root::migrate_root::migrate_root(db_provider, target_migration_name)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let pending_after = get_pending_root_migrations(db_provider).await?;
+
+    let applied: Vec<String> = pending_before
+        .into_iter()
+        .filter(|name| !pending_after.contains(name))
+        .collect();
+
+    tracing::info!(?applied, %actor, "migrated root database");
+
+    Ok(applied)
+}
+
+/// Apply pending migrations to `tenant` (up to `target_migration_name` when
+/// provided) and record which migrations were applied by `actor`.
+///
+/// Returns the names of the applied migrations
+pub async fn migrate_tenant_recorded(
+    db_provider: &DatabaseProvider,
+    metadata: &PgPool,
+    tenant: &Tenant,
+    target_migration_name: Option<&str>,
+    actor: &str,
+) -> anyhow::Result<Vec<String>> {
+    let get_pending = || {
+        docbox_management::tenant::get_pending_tenant_migrations::get_pending_tenant_migrations(
+            db_provider,
+            tenant,
+        )
+    };
+
+    let pending_before = get_pending().await.map_err(anyhow::Error::new)?;
+
+    apply_tenant_migrations_locked(db_provider, tenant, target_migration_name).await?;
+
+    let pending_after = get_pending().await.map_err(anyhow::Error::new)?;
+
+    let applied: Vec<String> = pending_before
+        .into_iter()
+        .filter(|name| !pending_after.contains(name))
+        .collect();
+
+    if !applied.is_empty() {
+        TenantMigrationApplication::create_many(metadata, &tenant.env, tenant.id, &applied, actor)
+            .await
+            .context("failed to record applied migrations")?;
+    }
+
+    tracing::info!(?tenant, ?applied, %actor, "migrated tenant");
+
+    Ok(applied)
+}
+
+/// Apply pending migrations to `tenant`, the tenant database is locked with
+/// an advisory lock taken by the transaction applying the migrations. The
+/// lock is tied to the database itself so concurrent migrations are refused
+/// even if the lock in the manager database has expired
+async fn apply_tenant_migrations_locked(
+    db_provider: &DatabaseProvider,
+    tenant: &Tenant,
+    target_migration_name: Option<&str>,
+) -> anyhow::Result<()> {
+    let root_db = db_provider
+        .connect(ROOT_DATABASE_NAME)
+        .await
+        .context("failed to connect to root database")?;
+    let tenant_db = db_provider
+        .connect(&tenant.db_name)
+        .await
+        .context("failed to connect to tenant database")?;
+
+    let mut root_t = root_db.begin().await?;
+    let mut tenant_t = tenant_db.begin().await?;
+
+    let locked: bool = sqlx::query_scalar("SELECT pg_try_advisory_xact_lock($1)")
+        .bind(TENANT_MIGRATION_LOCK_KEY)
+        .fetch_one(&mut *tenant_t)
+        .await
+        .context("failed to lock tenant database")?;
+
+    if !locked {
+        anyhow::bail!("tenant database is locked by another migration");
+    }
+
+    apply_tenant_migrations(&mut root_t, &mut tenant_t, tenant, target_migration_name)
+        .await
+        .context("failed to apply migrations")?;
+
+    tenant_t.commit().await?;
+    root_t.commit().await?;
+
+    Ok(())
+}
+
+/// Migrate every tenant matching the request, reporting the outcome for
+/// each tenant. Stops at the first failure unless `skip_failed` is set,
+/// in which case the remaining tenants are reported as skipped. Archived
+/// tenants are never migrated and are also reported as skipped.
+///
+/// When running as a `job` progress and logs are reported for each tenant
+/// and cancellation is checked before each tenant is migrated
+pub async fn migrate_fleet(
+    db_provider: &DatabaseProvider,
+    metadata: &PgPool,
+    request: &MigrateTenantsRequest,
+    actor: &str,
+    job: Option<&JobContext>,
+) -> anyhow::Result<FleetMigrationReport> {
+    let tenants = docbox_management::tenant::get_tenants::get_tenants(db_provider)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    // Archived tenants are not migrated, they are reported as skipped
+    let archived = ArchivedTenant::all(metadata)
+        .await
+        .context("failed to get archived tenants")?;
+
+    let tenants: Vec<Tenant> = tenants
+        .into_iter()
+        .filter(|tenant| {
+            request.env.as_ref().is_none_or(|env| tenant.env.eq(env))
+                && request.tenant_id.is_none_or(|id| tenant.id == id)
+        })
+        .collect();
+
+    let total = tenants.len();
+    let mut report = FleetMigrationReport::default();
+    let mut halted = false;
+    let mut cancelled = false;
+
+    if let Some(job) = job {
+        job.log(JobLogLevel::Info, format!("migrating {total} tenants"))
+            .await;
+        job.set_progress(&FleetMigrationProgress::new(total, &report))
+            .await;
+    }
+
+    for tenant in tenants {
+        if let (false, Some(job)) = (cancelled, job) {
+            cancelled = job.is_cancelled().await;
+            if cancelled {
+                job.log(JobLogLevel::Warn, "migration cancelled").await;
+            }
+        }
+
+        let is_archived = archived
+            .iter()
+            .any(|archived| archived.env == tenant.env && archived.tenant_id == tenant.id);
+
+        let status = if is_archived {
+            TenantMigrationStatus::Skipped {
+                reason: "tenant is archived".to_string(),
+            }
+        } else if cancelled {
+            TenantMigrationStatus::Skipped {
+                reason: "cancelled".to_string(),
+            }
+        } else if request
+            .deadline
+            .is_some_and(|deadline| Utc::now() >= deadline)
+        {
+            TenantMigrationStatus::Skipped {
+                reason: "deadline passed before the tenant was started".to_string(),
+            }
+        } else if halted {
+            TenantMigrationStatus::Skipped {
+                reason: "a previous tenant failed to migrate".to_string(),
+            }
+        } else {
+            let status = migrate_tenant_status(
+                db_provider,
+                metadata,
+                &tenant,
+                request.target_migration_name.as_deref(),
+                actor,
+            )
+            .await;
+            halted = matches!(status, TenantMigrationStatus::Failed { .. }) && !request.skip_failed;
+            status
+        };
+
+        if let Some(job) = job {
+            log_tenant_status(job, &tenant, &status).await;
+        }
+
+        report.push(TenantMigrationOutcome::new(tenant, status));
+
+        if let Some(job) = job {
+            job.set_progress(&FleetMigrationProgress::new(total, &report))
+                .await;
+        }
+    }
+
+    Ok(report)
+}
+
+/// Migrate `tenant` while holding the tenant migration lock, reporting
+/// the outcome as a [TenantMigrationStatus]
+pub async fn migrate_tenant_status(
+    db_provider: &DatabaseProvider,
+    metadata: &PgPool,
+    tenant: &Tenant,
+    target_migration_name: Option<&str>,
+    actor: &str,
+) -> TenantMigrationStatus {
+    let lock =
+        match MigrationLock::acquire(metadata, LockScope::tenant(&tenant.env, tenant.id), actor)
+            .await
+        {
+            Ok(lock) => lock,
+            Err(error) => {
+                tracing::error!(?error, ?tenant, "failed to lock tenant for migration");
+                return TenantMigrationStatus::Failed {
+                    error: error.to_string(),
+                };
+            }
+        };
+
+    let result =
+        migrate_tenant_recorded(db_provider, metadata, tenant, target_migration_name, actor).await;
+    lock.release().await;
+
+    match result {
+        Ok(migrations) if migrations.is_empty() => TenantMigrationStatus::UpToDate,
+        Ok(migrations) => TenantMigrationStatus::Applied { migrations },
+        Err(error) => {
+            tracing::error!(?error, ?tenant, "failed to migrate tenant");
+            TenantMigrationStatus::Failed {
+                error: format!("{error:#}"),
+            }
+        }
+    }
+}
+
+/// Log the migration `status` of `tenant` to the job logs
+pub async fn log_tenant_status(job: &JobContext, tenant: &Tenant, status: &TenantMigrationStatus) {
+    let (level, message) = status.log_message();
+    job.log(
+        level,
+        format!("{} ({}/{}): {message}", tenant.name, tenant.env, tenant.id),
+    )
+    .await;
+}
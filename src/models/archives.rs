@@ -0,0 +1,10 @@
+use serde::Deserialize;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct ArchiveTenantRequest {
+    /// Reason the tenant is being archived
+    pub reason: Option<String>,
+    /// Number of days to keep the tenant before it is purged, defaults
+    /// to the configured retention period
+    pub retention_days: Option<i64>,
+}
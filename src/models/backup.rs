@@ -0,0 +1,14 @@
+use serde::Deserialize;
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct RestoreRequest {
+    /// Backup to restore
+    pub backup_id: Uuid,
+    /// Name of the database to restore into, defaults to a new name
+    /// derived from the tenant database name
+    pub db_name: Option<String>,
+    /// Switch the tenant to the restored database once validated
+    #[serde(default)]
+    pub swap: bool,
+}
@@ -0,0 +1,20 @@
+use serde::Deserialize;
+
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Deserialize)]
+pub struct QueryRequest {
+    /// SQL query to run
+    pub query: String,
+    /// Format to return the results in
+    #[serde(default)]
+    pub format: QueryFormat,
+    /// Maximum number of rows to return, capped at the configured maximum
+    pub limit: Option<u32>,
+}
@@ -0,0 +1,24 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct TenantCredentialStatus {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    pub secret_name: String,
+    /// When the credentials were last rotated through the manager
+    pub last_rotated_at: Option<DateTime<Utc>>,
+    /// Whether the credentials are older than the rotation policy allows
+    pub overdue: bool,
+}
+
+#[derive(Serialize)]
+pub struct RootCredentialStatus {
+    pub secret_name: String,
+    /// When the credentials were last rotated through the manager
+    pub last_rotated_at: Option<DateTime<Utc>>,
+    /// Whether the credentials are older than the rotation policy allows
+    pub overdue: bool,
+}
@@ -0,0 +1,24 @@
+use docbox_management::tenant::create_tenant::CreateTenantConfig;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct ImportTenantRequest {
+    /// Key of the export archive within the backup bucket, either an
+    /// uploaded archive or an export stored by the manager
+    pub key: String,
+    /// Configuration for the tenant created from the archive
+    pub target: CreateTenantConfig,
+    /// Keep the created tenant when the import fails rather than
+    /// deleting it, allowing the failure to be inspected
+    #[serde(default)]
+    pub keep_on_failure: bool,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportUploadResponse {
+    /// Key of the uploaded archive within the backup bucket, provided
+    /// when importing the archive
+    pub key: String,
+    pub size: u64,
+    pub sha256: String,
+}
@@ -0,0 +1,81 @@
+use chrono::{DateTime, Utc};
+use crate::{drift::DriftReference, models::root::TenantSelector};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize)]
+pub struct AppliedTenantMigration {
+    pub name: String,
+    pub applied_at: DateTime<Utc>,
+    /// Who applied the migration when applied through the manager
+    pub applied_by: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct TenantSchemaVersion {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    /// Most recent migration applied to the tenant (in migration order)
+    pub version: Option<String>,
+    /// Number of applied migrations
+    pub applied: usize,
+    /// Migrations that have not been applied
+    pub pending: Vec<String>,
+    /// When the tenant was last migrated
+    pub last_applied_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize)]
+pub struct RollbackPlanRequest {
+    /// Migration to roll back to, this migration remains applied
+    pub target_migration_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct FleetRollbackPlanRequest {
+    /// Tenants to plan a rollback for
+    #[serde(default)]
+    pub selector: TenantSelector,
+    /// Migration to roll back to, this migration remains applied
+    pub target_migration_name: String,
+}
+
+#[derive(Deserialize)]
+pub struct RollbackRequest {
+    /// Migration to roll back to, this migration remains applied
+    pub target_migration_name: String,
+    /// Backup from the rollback plan to restore
+    pub backup_id: Uuid,
+    /// Confirms the tenant data written since the backup will be lost
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+#[derive(Deserialize)]
+pub struct FleetRollbackRequest {
+    /// Migration to roll back to, this migration remains applied
+    pub target_migration_name: String,
+    /// Tenants to roll back with the backup from their rollback plan
+    pub tenants: Vec<RollbackTenant>,
+    /// Confirms the tenant data written since the backups will be lost
+    #[serde(default)]
+    pub confirm: bool,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct RollbackTenant {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub backup_id: Uuid,
+}
+
+#[derive(Deserialize)]
+pub struct FleetDriftRequest {
+    /// Tenants to check for drift
+    #[serde(default)]
+    pub selector: TenantSelector,
+    /// Schema to compare the tenants against
+    #[serde(default)]
+    pub reference: DriftReference,
+}
@@ -1,3 +1,11 @@
+pub mod archives;
 pub mod auth;
+pub mod backup;
+pub mod console;
+pub mod credentials;
+pub mod export;
+pub mod migrations;
 pub mod root;
+pub mod schedules;
+pub mod system;
 pub mod tenant;
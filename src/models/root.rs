@@ -1,13 +1,141 @@
+use crate::initialize::InitializePlan;
+use chrono::{DateTime, Utc};
 use docbox_database::models::tenant::Tenant;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 
 #[derive(Serialize)]
 pub struct IsInitializedResponse {
     pub initialized: bool,
 }
 
+#[derive(Debug, Default, Deserialize)]
+pub struct InitializeRootRequest {
+    /// Role the root credentials are created for, defaults to the
+    /// docbox root role
+    pub role_name: Option<String>,
+    /// Replace the credentials in the secret when it already exists
+    #[serde(default)]
+    pub overwrite_secret: bool,
+    /// Only plan the initialization without making any changes
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize)]
+pub struct InitializeRootResponse {
+    pub dry_run: bool,
+    pub plan: InitializePlan,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct TeardownRootRequest {
+    /// Confirms every tenant and the root database will be destroyed
+    #[serde(default)]
+    pub confirm: bool,
+    /// Drop the root database even when the resources of some tenants
+    /// failed to delete, the failed resources are left behind
+    #[serde(default)]
+    pub force: bool,
+}
+
+#[derive(Serialize)]
+pub struct RootStatusResponse {
+    pub initialized: bool,
+    /// Most recent root migration applied (in migration order)
+    pub version: Option<String>,
+    /// Root migrations that have not been applied
+    pub pending_migrations: Vec<String>,
+    /// Number of tenants within each environment
+    pub tenants: Vec<EnvTenantCount>,
+}
+
+#[derive(Serialize)]
+pub struct EnvTenantCount {
+    pub env: String,
+    pub tenants: usize,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MigrateRootRequest {
+    /// Only apply migrations up to and including this migration
+    pub target_migration_name: Option<String>,
+}
+
+#[derive(Serialize)]
+pub struct MigrateRootResponse {
+    /// Root migrations that were applied
+    pub applied: Vec<String>,
+}
+
 #[derive(Serialize)]
 pub struct TenantWithMigrations {
     pub tenant: Tenant,
     pub migrations: Vec<String>,
 }
+
+#[derive(Debug, Default, Deserialize)]
+pub struct MigrateTenantsRequest {
+    /// Only migrate tenants within this environment
+    pub env: Option<String>,
+    /// Only migrate this specific tenant
+    pub tenant_id: Option<Uuid>,
+    /// Continue migrating the remaining tenants when a tenant fails
+    #[serde(default)]
+    pub skip_failed: bool,
+    /// Only apply migrations up to and including this migration
+    pub target_migration_name: Option<String>,
+    /// Stop starting new tenants after this time, the remaining tenants
+    /// are reported as skipped
+    pub deadline: Option<DateTime<Utc>>,
+}
+
+/// Selects a set of tenants, tenants must match every provided filter
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct TenantSelector {
+    /// Only select tenants within this environment
+    pub env: Option<String>,
+    /// Only select these specific tenants
+    pub tenant_ids: Option<Vec<Uuid>>,
+    /// Only select tenants that have all of these tags
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RolloutRequest {
+    /// Tenants to migrate
+    #[serde(default)]
+    pub selector: TenantSelector,
+    /// Tenants to migrate first as the canary wave, when not provided the
+    /// first `canary_size` selected tenants are used
+    pub canary_tenant_ids: Option<Vec<Uuid>>,
+    /// Number of tenants in the canary wave when `canary_tenant_ids` is
+    /// not provided
+    #[serde(default = "default_canary_size")]
+    pub canary_size: usize,
+    /// Number of tenants migrated in each wave after the canary
+    #[serde(default = "default_wave_size")]
+    pub wave_size: usize,
+    /// Number of tenants migrated at the same time within a wave (at most 16)
+    #[serde(default = "default_concurrency")]
+    pub concurrency: usize,
+    /// Halt the rollout when the fraction of attempted tenants that
+    /// failed exceeds this threshold (0.0 to 1.0)
+    #[serde(default)]
+    pub max_failure_rate: f64,
+    /// Only apply migrations up to and including this migration
+    pub target_migration_name: Option<String>,
+}
+
+fn default_canary_size() -> usize {
+    1
+}
+
+fn default_wave_size() -> usize {
+    10
+}
+
+fn default_concurrency() -> usize {
+    1
+}
@@ -0,0 +1,30 @@
+use crate::metadata::{jobs::Job, schedules::ScheduledMigration};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Deserialize)]
+pub struct CreateScheduleRequest {
+    /// Only migrate tenants within this environment
+    pub env: Option<String>,
+    /// Only migrate this specific tenant
+    pub tenant_id: Option<Uuid>,
+    /// Continue migrating the remaining tenants when a tenant fails
+    #[serde(default)]
+    pub skip_failed: bool,
+    /// Only apply migrations up to and including this migration
+    pub target_migration_name: Option<String>,
+    /// Start of the maintenance window (RFC 3339, any offset)
+    pub window_start: DateTime<Utc>,
+    /// End of the maintenance window, tenants are not started after this time
+    pub window_end: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct ScheduledMigrationDetails {
+    #[serde(flatten)]
+    pub schedule: ScheduledMigration,
+    /// Job that ran the migration, its output contains the outcome for
+    /// each tenant
+    pub job: Option<Job>,
+}
@@ -0,0 +1,35 @@
+use crate::{
+    database::{PoolStats, ServerActivity},
+    metadata::migrations::AppliedMigration,
+};
+use serde::Serialize;
+use sqlx::types::Uuid;
+
+#[derive(Serialize)]
+pub struct ManagerMigrationsResponse {
+    pub applied: Vec<AppliedMigration>,
+    pub pending: Vec<String>,
+}
+
+#[derive(Serialize)]
+pub struct DatabaseStatsResponse {
+    /// Pools held by the manager
+    pub pools: Vec<PoolStats>,
+    /// Server side connection counts
+    pub server: Vec<DatabaseActivity>,
+}
+
+#[derive(Serialize)]
+pub struct DatabaseActivity {
+    #[serde(flatten)]
+    pub activity: ServerActivity,
+    /// Tenant that owns the database (if any)
+    pub tenant: Option<DatabaseTenant>,
+}
+
+#[derive(Serialize)]
+pub struct DatabaseTenant {
+    pub env: String,
+    pub id: Uuid,
+    pub name: String,
+}
@@ -1 +1,52 @@
+use crate::teardown::TenantResource;
+use docbox_management::tenant::create_tenant::CreateTenantConfig;
+use serde::{Deserialize, Deserializer, Serialize};
 
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TenantTags {
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct UpdateTenantRequest {
+    pub name: Option<String>,
+    /// New event queue URL, `null` removes the event queue
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub event_queue_url: Option<Option<String>>,
+    /// Origins allowed to access the storage bucket, replaces the
+    /// current origins
+    pub storage_cors_origins: Option<Vec<String>>,
+    /// SQS queue ARN to send storage bucket notifications to, replaces
+    /// the queue currently receiving notifications
+    pub storage_s3_queue_arn: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteTenantRequest {
+    /// Name of the tenant, confirms the deletion
+    pub confirm_name: String,
+    /// Resources to keep rather than destroy
+    #[serde(default)]
+    pub keep: Vec<TenantResource>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CloneTenantRequest {
+    /// Configuration for the tenant created as the clone, the environment
+    /// of the clone is taken from this configuration
+    pub target: CreateTenantConfig,
+    /// Keep the created tenant when the clone fails rather than deleting
+    /// it, allowing the failure to be inspected
+    #[serde(default)]
+    pub keep_on_failure: bool,
+}
+
+/// Deserialize a present value (including `null`) as `Some`, allowing
+/// a missing field to be told apart from an explicit `null`
+fn deserialize_some<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
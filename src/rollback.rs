@@ -0,0 +1,425 @@
+//! Rolling tenants back to an earlier migration
+//!
+//! Tenant migrations have no down migrations, a tenant is rolled back by
+//! restoring the newest backup taken before any of the migrations being
+//! reverted were applied
+
+use crate::{
+    backup::restore::{RestoreOutcome, default_restore_db_name, restore_tenant},
+    database::DatabaseProvider,
+    jobs::JobContext,
+    locks::{LockScope, MigrationLock},
+    metadata::{
+        audit::{AuditAction, AuditEvent, CreateAuditEvent},
+        backups::TenantBackup,
+        jobs::JobLogLevel,
+    },
+    models::migrations::RollbackTenant,
+    root::{get_tenant_applied_migrations, switch_tenant_database},
+};
+use anyhow::Context;
+use docbox_database::{migrations::TENANT_MIGRATIONS, models::tenant::Tenant};
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use serde::Serialize;
+use serde_json::json;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize)]
+pub struct TenantRollbackPlan {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    /// Applied migrations after the target that will be reverted
+    pub revert_migrations: Vec<String>,
+    /// Backup that will be restored, data written to the tenant since
+    /// this backup was taken will be lost
+    pub backup: Option<TenantBackup>,
+    /// Reason the tenant cannot be rolled back
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TenantRollbackOutcome {
+    pub env: String,
+    pub tenant_id: Uuid,
+    /// Migrations that were reverted
+    pub reverted_migrations: Vec<String>,
+    pub restore: RestoreOutcome,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FleetRollbackOutcome {
+    pub env: String,
+    pub tenant_id: Uuid,
+    #[serde(flatten)]
+    pub status: FleetRollbackStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FleetRollbackStatus {
+    RolledBack { outcome: TenantRollbackOutcome },
+    Failed { error: String },
+    Skipped { reason: String },
+}
+
+/// Whether `name` is a known tenant migration
+pub fn is_tenant_migration(name: &str) -> bool {
+    TENANT_MIGRATIONS
+        .iter()
+        .any(|(migration, _)| (*migration).eq(name))
+}
+
+/// Whether restoring `backup` rolls the tenant back to `target` reverting
+/// every one of `revert_migrations`
+fn is_backup_eligible(backup: &TenantBackup, target: &str, revert_migrations: &[String]) -> bool {
+    backup.pending_migrations.as_ref().is_some_and(|pending| {
+        !pending.iter().any(|name| name == target)
+            && revert_migrations.iter().all(|name| pending.contains(name))
+    })
+}
+
+/// Plan rolling `tenant` back to the `target` migration
+pub async fn plan_tenant_rollback(
+    db_provider: &DatabaseProvider,
+    metadata: &PgPool,
+    tenant: &Tenant,
+    target: &str,
+) -> anyhow::Result<TenantRollbackPlan> {
+    let applied = get_tenant_applied_migrations(db_provider, &tenant.env, tenant.id).await?;
+
+    let mut plan = TenantRollbackPlan {
+        env: tenant.env.clone(),
+        tenant_id: tenant.id,
+        name: tenant.name.clone(),
+        revert_migrations: Vec::new(),
+        backup: None,
+        error: None,
+    };
+
+    if !applied.iter().any(|migration| migration.name == target) {
+        plan.error = Some(format!("migration {target} is not applied to the tenant"));
+        return Ok(plan);
+    }
+
+    // Applied migrations that come after the target in migration order
+    plan.revert_migrations = TENANT_MIGRATIONS
+        .iter()
+        .map(|(name, _)| *name)
+        .skip_while(|name| *name != target)
+        .skip(1)
+        .filter(|name| applied.iter().any(|migration| migration.name == *name))
+        .map(str::to_string)
+        .collect();
+
+    if plan.revert_migrations.is_empty() {
+        plan.error = Some("no migrations have been applied after the target".to_string());
+        return Ok(plan);
+    }
+
+    let backups = TenantBackup::find_by_tenant(metadata, &tenant.env, tenant.id)
+        .await
+        .context("failed to get tenant backups")?;
+
+    // Backups are newest first, the newest eligible backup loses the least data
+    plan.backup = backups
+        .into_iter()
+        .find(|backup| is_backup_eligible(backup, target, &plan.revert_migrations));
+
+    if plan.backup.is_none() {
+        plan.error = Some("no backup was taken before the migrations were applied".to_string());
+    }
+
+    Ok(plan)
+}
+
+/// Roll `tenant` back to the `target` migration by restoring the backup
+/// with `backup_id` into a new database and switching the tenant to it,
+/// the tenant migration lock is held for the duration of the rollback
+// The services needed to restore a backup are passed individually as they
+// are to restore_tenant, the rest identify the rollback for the audit log
+#[allow(clippy::too_many_arguments)]
+pub async fn rollback_tenant(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    secrets: &SecretManager,
+    metadata: &PgPool,
+    tenant: &Tenant,
+    target: &str,
+    backup_id: Uuid,
+    actor: &str,
+    job_id: Option<Uuid>,
+) -> anyhow::Result<TenantRollbackOutcome> {
+    let lock =
+        MigrationLock::acquire(metadata, LockScope::tenant(&tenant.env, tenant.id), actor).await?;
+
+    let result = rollback_tenant_locked(
+        db_provider,
+        storage_factory,
+        secrets,
+        metadata,
+        tenant,
+        target,
+        backup_id,
+        actor,
+        job_id,
+    )
+    .await;
+
+    lock.release().await;
+    result
+}
+
+// Takes the arguments of rollback_tenant
+#[allow(clippy::too_many_arguments)]
+async fn rollback_tenant_locked(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    secrets: &SecretManager,
+    metadata: &PgPool,
+    tenant: &Tenant,
+    target: &str,
+    backup_id: Uuid,
+    actor: &str,
+    job_id: Option<Uuid>,
+) -> anyhow::Result<TenantRollbackOutcome> {
+    let plan = plan_tenant_rollback(db_provider, metadata, tenant, target).await?;
+
+    if plan.revert_migrations.is_empty() {
+        anyhow::bail!(
+            "{}",
+            plan.error
+                .unwrap_or_else(|| "no migrations to roll back".to_string())
+        );
+    }
+
+    let backup = TenantBackup::find_by_id(metadata, &tenant.env, tenant.id, backup_id)
+        .await?
+        .context("backup not found")?;
+
+    if !is_backup_eligible(&backup, target, &plan.revert_migrations) {
+        anyhow::bail!("backup was not taken before the migrations being reverted were applied");
+    }
+
+    tracing::info!(?tenant, %target, %backup_id, reverted = ?plan.revert_migrations, "rolling back tenant");
+
+    // Restored without switching so the tenant database and its recorded
+    // migrations are changed together once the restore has succeeded
+    let mut restore = restore_tenant(
+        db_provider,
+        storage_factory,
+        secrets,
+        tenant,
+        &backup,
+        default_restore_db_name(tenant),
+        false,
+    )
+    .await?;
+
+    switch_tenant_database(
+        db_provider,
+        &tenant.env,
+        tenant.id,
+        &restore.db_name,
+        &plan.revert_migrations,
+    )
+    .await
+    .with_context(|| {
+        format!(
+            "backup was restored into {} but the tenant was not switched to it",
+            restore.db_name
+        )
+    })?;
+    restore.swapped = true;
+    tracing::info!(?tenant, db_name = %restore.db_name, "tenant switched to restored database");
+
+    AuditEvent::create(
+        metadata,
+        CreateAuditEvent {
+            action: AuditAction::TenantRollback,
+            env: Some(tenant.env.clone()),
+            tenant_id: Some(tenant.id),
+            details: json!({
+                "actor": actor,
+                "target_migration_name": target,
+                "reverted_migrations": plan.revert_migrations,
+                "backup_id": backup.id,
+                "backup_created_at": backup.created_at,
+                "db_name": restore.db_name,
+                "previous_db_name": restore.previous_db_name,
+                "job_id": job_id,
+            }),
+        },
+    )
+    .await
+    .context("failed to record rollback in audit log")?;
+
+    Ok(TenantRollbackOutcome {
+        env: tenant.env.clone(),
+        tenant_id: tenant.id,
+        reverted_migrations: plan.revert_migrations,
+        restore,
+    })
+}
+
+/// Roll back each of `tenants` to the `target` migration, reporting the
+/// outcome for each tenant. Cancelling the job skips the remaining tenants
+// The services needed to restore a backup are passed individually as they
+// are to rollback_tenant
+#[allow(clippy::too_many_arguments)]
+pub async fn rollback_fleet(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    secrets: &SecretManager,
+    metadata: &PgPool,
+    tenants: &[RollbackTenant],
+    target: &str,
+    actor: &str,
+    job: &JobContext,
+) -> anyhow::Result<Vec<FleetRollbackOutcome>> {
+    let mut outcomes = Vec::with_capacity(tenants.len());
+    let mut cancelled = false;
+
+    for rollback in tenants {
+        if !cancelled && job.is_cancelled().await {
+            job.log(JobLogLevel::Warn, "rollback cancelled").await;
+            cancelled = true;
+        }
+
+        let status = if cancelled {
+            FleetRollbackStatus::Skipped {
+                reason: "cancelled".to_string(),
+            }
+        } else {
+            let result = async {
+                let tenant = docbox_management::tenant::get_tenant::get_tenant(
+                    db_provider,
+                    &rollback.env,
+                    rollback.tenant_id,
+                )
+                .await
+                .map_err(anyhow::Error::new)?
+                .context("tenant not found")?;
+
+                rollback_tenant(
+                    db_provider,
+                    storage_factory,
+                    secrets,
+                    metadata,
+                    &tenant,
+                    target,
+                    rollback.backup_id,
+                    actor,
+                    Some(job.id),
+                )
+                .await
+            }
+            .await;
+
+            match result {
+                Ok(outcome) => {
+                    job.log(
+                        JobLogLevel::Info,
+                        format!(
+                            "{}/{}: reverted {}",
+                            rollback.env,
+                            rollback.tenant_id,
+                            outcome.reverted_migrations.join(", ")
+                        ),
+                    )
+                    .await;
+                    FleetRollbackStatus::RolledBack { outcome }
+                }
+                Err(error) => {
+                    tracing::error!(?error, env = %rollback.env, tenant_id = %rollback.tenant_id, "failed to roll back tenant");
+                    let error = format!("{error:#}");
+                    job.log(
+                        JobLogLevel::Error,
+                        format!(
+                            "{}/{}: failed to roll back: {error}",
+                            rollback.env, rollback.tenant_id
+                        ),
+                    )
+                    .await;
+                    FleetRollbackStatus::Failed { error }
+                }
+            }
+        };
+
+        outcomes.push(FleetRollbackOutcome {
+            env: rollback.env.clone(),
+            tenant_id: rollback.tenant_id,
+            status,
+        });
+    }
+
+    Ok(outcomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::metadata::backups::BackupReason;
+    use chrono::Utc;
+
+    fn backup(pending_migrations: Option<&[&str]>) -> TenantBackup {
+        TenantBackup {
+            id: Uuid::from_u128(1),
+            env: "dev".to_string(),
+            tenant_id: Uuid::from_u128(2),
+            db_name: "docbox_dev_tenant".to_string(),
+            bucket: "docbox-backups".to_string(),
+            key: "backups/dev/tenant.tar.gz".to_string(),
+            size: 0,
+            sha256: String::new(),
+            reason: BackupReason::PreMigration,
+            job_id: None,
+            pending_migrations: pending_migrations
+                .map(|pending| pending.iter().map(|name| name.to_string()).collect()),
+            created_at: Utc::now(),
+        }
+    }
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn test_is_backup_eligible() {
+        let revert = names(&["m3", "m4"]);
+
+        // Taken after the target was applied but before any reverted migration
+        assert!(is_backup_eligible(
+            &backup(Some(&["m3", "m4"])),
+            "m2",
+            &revert
+        ));
+
+        // Taken before later migrations that are not being reverted
+        assert!(is_backup_eligible(
+            &backup(Some(&["m3", "m4", "m5"])),
+            "m2",
+            &revert
+        ));
+    }
+
+    #[test]
+    fn test_is_backup_eligible_rejected() {
+        let revert = names(&["m3", "m4"]);
+
+        // Taken before the target was applied
+        assert!(!is_backup_eligible(
+            &backup(Some(&["m2", "m3", "m4"])),
+            "m2",
+            &revert
+        ));
+
+        // Taken after one of the reverted migrations was applied
+        assert!(!is_backup_eligible(&backup(Some(&["m4"])), "m2", &revert));
+
+        // Pending migrations unknown
+        assert!(!is_backup_eligible(&backup(None), "m2", &revert));
+    }
+}
@@ -0,0 +1,334 @@
+//! Staged rollout of tenant migrations in waves
+
+use crate::{
+    database::DatabaseProvider,
+    jobs::JobContext,
+    metadata::{archives::ArchivedTenant, jobs::JobLogLevel, tags::find_tenants_with_tags},
+    migrations::{
+        FleetMigrationReport, TenantMigrationOutcome, TenantMigrationStatus, log_tenant_status,
+        migrate_tenant_status,
+    },
+    models::root::{RolloutRequest, TenantSelector},
+};
+use anyhow::Context;
+use docbox_database::models::tenant::Tenant;
+use futures::StreamExt;
+use serde::Serialize;
+use sqlx::PgPool;
+
+/// Maximum number of tenants migrated at the same time within a wave
+pub const MAX_CONCURRENCY: usize = 16;
+
+#[derive(Debug, Default, Serialize)]
+pub struct RolloutReport {
+    /// Reason the rollout was halted before completing
+    pub halted_reason: Option<String>,
+    pub waves: Vec<RolloutWaveReport>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RolloutWaveReport {
+    /// Whether this was the canary wave
+    pub canary: bool,
+    #[serde(flatten)]
+    pub report: FleetMigrationReport,
+}
+
+/// Progress of a rollout running as a job
+#[derive(Debug, Serialize)]
+pub struct RolloutProgress {
+    pub total_waves: usize,
+    pub completed_waves: usize,
+    /// Number of tenants a migration was attempted on
+    pub attempted: usize,
+    /// Number of tenants that failed to migrate
+    pub failed: usize,
+}
+
+struct RolloutWave {
+    canary: bool,
+    tenants: Vec<Tenant>,
+}
+
+/// Get the tenants matching the `selector`, archived tenants are excluded
+pub async fn select_tenants(
+    db_provider: &DatabaseProvider,
+    metadata: &PgPool,
+    selector: &TenantSelector,
+) -> anyhow::Result<Vec<Tenant>> {
+    let tenants = docbox_management::tenant::get_tenants::get_tenants(db_provider)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let tagged = if selector.tags.is_empty() {
+        None
+    } else {
+        Some(
+            find_tenants_with_tags(metadata, &selector.tags)
+                .await
+                .context("failed to find tagged tenants")?,
+        )
+    };
+
+    let archived = ArchivedTenant::all(metadata)
+        .await
+        .context("failed to get archived tenants")?;
+
+    Ok(tenants
+        .into_iter()
+        .filter(|tenant| {
+            !archived
+                .iter()
+                .any(|archived| archived.env == tenant.env && archived.tenant_id == tenant.id)
+                && selector.env.as_ref().is_none_or(|env| tenant.env.eq(env))
+                && selector
+                    .tenant_ids
+                    .as_ref()
+                    .is_none_or(|ids| ids.contains(&tenant.id))
+                && tagged.as_ref().is_none_or(|tagged| {
+                    tagged
+                        .iter()
+                        .any(|tag| tag.env == tenant.env && tag.tenant_id == tenant.id)
+                })
+        })
+        .collect())
+}
+
+/// Split the selected tenants into the canary wave followed by waves of
+/// at most `wave_size` tenants
+fn plan_waves(mut tenants: Vec<Tenant>, request: &RolloutRequest) -> Vec<RolloutWave> {
+    let canary = match &request.canary_tenant_ids {
+        Some(ids) => {
+            let (canary, rest) = tenants
+                .into_iter()
+                .partition(|tenant| ids.contains(&tenant.id));
+            tenants = rest;
+            canary
+        }
+        None => {
+            let rest = tenants.split_off(request.canary_size.min(tenants.len()));
+            std::mem::replace(&mut tenants, rest)
+        }
+    };
+
+    let mut waves = Vec::new();
+
+    if !canary.is_empty() {
+        waves.push(RolloutWave {
+            canary: true,
+            tenants: canary,
+        });
+    }
+
+    while !tenants.is_empty() {
+        let rest = tenants.split_off(request.wave_size.min(tenants.len()));
+        waves.push(RolloutWave {
+            canary: false,
+            tenants: std::mem::replace(&mut tenants, rest),
+        });
+    }
+
+    waves
+}
+
+/// Migrate the selected tenants in waves, starting with the canary wave.
+///
+/// The rollout halts when the fraction of attempted tenants that failed
+/// exceeds the threshold, the remaining tenants are reported as skipped.
+/// Pausing and cancelling the job takes effect between waves
+pub async fn run_rollout(
+    db_provider: &DatabaseProvider,
+    metadata: &PgPool,
+    request: &RolloutRequest,
+    actor: &str,
+    job: &JobContext,
+) -> anyhow::Result<RolloutReport> {
+    let tenants = select_tenants(db_provider, metadata, &request.selector).await?;
+    let total_tenants = tenants.len();
+    let waves = plan_waves(tenants, request);
+    let total_waves = waves.len();
+
+    job.log(
+        JobLogLevel::Info,
+        format!("rolling out migrations to {total_tenants} tenants in {total_waves} waves"),
+    )
+    .await;
+
+    let mut report = RolloutReport::default();
+    let mut attempted = 0;
+    let mut failed = 0;
+
+    for (index, wave) in waves.into_iter().enumerate() {
+        if report.halted_reason.is_none() {
+            job.wait_while_paused().await;
+
+            if job.is_cancelled().await {
+                job.log(JobLogLevel::Warn, "rollout cancelled").await;
+                report.halted_reason = Some("cancelled".to_string());
+            }
+        }
+
+        let mut wave_report = FleetMigrationReport::default();
+
+        if let Some(reason) = &report.halted_reason {
+            for tenant in wave.tenants {
+                let status = TenantMigrationStatus::Skipped {
+                    reason: reason.clone(),
+                };
+                wave_report.push(TenantMigrationOutcome::new(tenant, status));
+            }
+        } else {
+            job.log(
+                JobLogLevel::Info,
+                format!(
+                    "starting {} wave {}/{} with {} tenants",
+                    if wave.canary { "canary" } else { "rollout" },
+                    index + 1,
+                    total_waves,
+                    wave.tenants.len()
+                ),
+            )
+            .await;
+
+            let outcomes: Vec<(Tenant, TenantMigrationStatus)> =
+                futures::stream::iter(wave.tenants)
+                    .map(|tenant| async move {
+                        let status = migrate_tenant_status(
+                            db_provider,
+                            metadata,
+                            &tenant,
+                            request.target_migration_name.as_deref(),
+                            actor,
+                        )
+                        .await;
+                        (tenant, status)
+                    })
+                    .buffer_unordered(request.concurrency)
+                    .collect()
+                    .await;
+
+            for (tenant, status) in outcomes {
+                log_tenant_status(job, &tenant, &status).await;
+                wave_report.push(TenantMigrationOutcome::new(tenant, status));
+            }
+
+            attempted += wave_report.tenants.len();
+            failed += wave_report.failed;
+
+            let failure_rate = failed as f64 / attempted.max(1) as f64;
+            if failure_rate > request.max_failure_rate {
+                let reason = format!(
+                    "failure rate of {:.1}% exceeded the threshold of {:.1}%",
+                    failure_rate * 100.0,
+                    request.max_failure_rate * 100.0
+                );
+                job.log(JobLogLevel::Error, format!("halting rollout: {reason}"))
+                    .await;
+                report.halted_reason = Some(reason);
+            }
+        }
+
+        report.waves.push(RolloutWaveReport {
+            canary: wave.canary,
+            report: wave_report,
+        });
+
+        job.set_progress(&RolloutProgress {
+            total_waves,
+            completed_waves: index + 1,
+            attempted,
+            failed,
+        })
+        .await;
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    fn tenants(count: u128) -> Vec<Tenant> {
+        (1..=count)
+            .map(|index| Tenant {
+                id: Uuid::from_u128(index),
+                name: format!("tenant-{index}"),
+                db_name: format!("docbox_dev_tenant_{index}"),
+                db_secret_name: format!("postgres/docbox/dev/tenant-{index}"),
+                s3_name: format!("docbox-dev-tenant-{index}"),
+                os_index_name: format!("docbox-dev-tenant-{index}"),
+                env: "dev".to_string(),
+                event_queue_url: None,
+            })
+            .collect()
+    }
+
+    fn request(value: serde_json::Value) -> RolloutRequest {
+        serde_json::from_value(value).unwrap()
+    }
+
+    /// Tenant numbers within each wave along with whether it is the canary
+    fn wave_ids(waves: &[RolloutWave]) -> Vec<(bool, Vec<u128>)> {
+        waves
+            .iter()
+            .map(|wave| {
+                let ids = wave.tenants.iter().map(|tenant| tenant.id.as_u128());
+                (wave.canary, ids.collect())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_plan_waves_canary_size() {
+        let waves = plan_waves(
+            tenants(6),
+            &request(json!({ "canary_size": 1, "wave_size": 2 })),
+        );
+
+        assert_eq!(
+            wave_ids(&waves),
+            vec![
+                (true, vec![1]),
+                (false, vec![2, 3]),
+                (false, vec![4, 5]),
+                (false, vec![6]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_plan_waves_canary_ids() {
+        let canary = [Uuid::from_u128(3), Uuid::from_u128(5), Uuid::from_u128(9)];
+        let waves = plan_waves(
+            tenants(5),
+            &request(json!({ "canary_tenant_ids": canary, "wave_size": 10 })),
+        );
+
+        // Unknown canary IDs are ignored
+        assert_eq!(
+            wave_ids(&waves),
+            vec![(true, vec![3, 5]), (false, vec![1, 2, 4])]
+        );
+    }
+
+    #[test]
+    fn test_plan_waves_no_canary() {
+        let waves = plan_waves(
+            tenants(3),
+            &request(json!({ "canary_size": 0, "wave_size": 3 })),
+        );
+        assert_eq!(wave_ids(&waves), vec![(false, vec![1, 2, 3])]);
+
+        // Canary larger than the selection takes every tenant
+        let waves = plan_waves(tenants(2), &request(json!({ "canary_size": 5 })));
+        assert_eq!(wave_ids(&waves), vec![(true, vec![1, 2])]);
+    }
+
+    #[test]
+    fn test_plan_waves_empty() {
+        assert!(plan_waves(Vec::new(), &request(json!({}))).is_empty());
+    }
+}
@@ -0,0 +1,171 @@
+//! Direct access to the docbox root database for tenant changes that
+//! are not provided by docbox-management
+
+use crate::database::DatabaseProvider;
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use docbox_database::{DbPool, ROOT_DATABASE_NAME};
+use docbox_management::database::DatabaseProvider as _;
+use sqlx::FromRow;
+use uuid::Uuid;
+
+/// Tenant migration recorded in the root database
+#[derive(Debug, Clone, FromRow)]
+pub struct RootTenantMigration {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    pub applied_at: DateTime<Utc>,
+}
+
+async fn connect_root(db_provider: &DatabaseProvider) -> anyhow::Result<DbPool> {
+    db_provider
+        .connect(ROOT_DATABASE_NAME)
+        .await
+        .context("failed to connect to root database")
+}
+
+/// Get the migrations applied to a specific tenant
+pub async fn get_tenant_applied_migrations(
+    db_provider: &DatabaseProvider,
+    env: &str,
+    tenant_id: Uuid,
+) -> anyhow::Result<Vec<RootTenantMigration>> {
+    let db = connect_root(db_provider).await?;
+    let migrations = sqlx::query_as(
+        r#"
+        SELECT "env", "tenant_id", "name", "applied_at"
+        FROM "docbox_tenants_migrations"
+        WHERE "env" = $1 AND "tenant_id" = $2
+        ORDER BY "applied_at", "name"
+        "#,
+    )
+    .bind(env)
+    .bind(tenant_id)
+    .fetch_all(&db)
+    .await
+    .context("failed to get applied tenant migrations")?;
+    Ok(migrations)
+}
+
+/// Get the migrations applied to every tenant
+pub async fn get_all_applied_migrations(
+    db_provider: &DatabaseProvider,
+) -> anyhow::Result<Vec<RootTenantMigration>> {
+    let db = connect_root(db_provider).await?;
+    let migrations = sqlx::query_as(
+        r#"
+        SELECT "env", "tenant_id", "name", "applied_at"
+        FROM "docbox_tenants_migrations"
+        ORDER BY "env", "tenant_id", "applied_at", "name"
+        "#,
+    )
+    .fetch_all(&db)
+    .await
+    .context("failed to get applied tenant migrations")?;
+    Ok(migrations)
+}
+
+/// Point the tenant at a different database
+pub async fn set_tenant_db_name(
+    db_provider: &DatabaseProvider,
+    env: &str,
+    tenant_id: Uuid,
+    db_name: &str,
+) -> anyhow::Result<()> {
+    let db = connect_root(db_provider).await?;
+
+    let result =
+        sqlx::query(r#"UPDATE "docbox_tenants" SET "db_name" = $1 WHERE "env" = $2 AND "id" = $3"#)
+            .bind(db_name)
+            .bind(env)
+            .bind(tenant_id)
+            .execute(&db)
+            .await
+            .context("failed to update tenant database name")?;
+
+    if result.rows_affected() == 0 {
+        anyhow::bail!("tenant not found");
+    }
+
+    Ok(())
+}
+
+/// Point the tenant at a restored database and remove the record of the
+/// `pending_migrations` that are not applied to it, both changes are made
+/// in a single transaction so the tenant is never left pointing at the
+/// restored database with the reverted migrations still recorded
+pub async fn switch_tenant_database(
+    db_provider: &DatabaseProvider,
+    env: &str,
+    tenant_id: Uuid,
+    db_name: &str,
+    pending_migrations: &[String],
+) -> anyhow::Result<()> {
+    let db = connect_root(db_provider).await?;
+    let mut t = db.begin().await.context("failed to begin transaction")?;
+
+    let result =
+        sqlx::query(r#"UPDATE "docbox_tenants" SET "db_name" = $1 WHERE "env" = $2 AND "id" = $3"#)
+            .bind(db_name)
+            .bind(env)
+            .bind(tenant_id)
+            .execute(&mut *t)
+            .await
+            .context("failed to update tenant database name")?;
+
+    if result.rows_affected() == 0 {
+        anyhow::bail!("tenant not found");
+    }
+
+    sqlx::query(
+        r#"
+        DELETE FROM "docbox_tenants_migrations"
+        WHERE "env" = $1 AND "tenant_id" = $2 AND "name" = ANY($3::VARCHAR[])
+        "#,
+    )
+    .bind(env)
+    .bind(tenant_id)
+    .bind(pending_migrations)
+    .execute(&mut *t)
+    .await
+    .context("failed to remove tenant migrations")?;
+
+    t.commit().await.context("failed to commit transaction")?;
+
+    Ok(())
+}
+
+/// Update the attributes of a tenant, `None` leaves the attribute unchanged
+pub async fn update_tenant(
+    db_provider: &DatabaseProvider,
+    env: &str,
+    tenant_id: Uuid,
+    name: Option<&str>,
+    event_queue_url: Option<Option<&str>>,
+) -> anyhow::Result<()> {
+    let db = connect_root(db_provider).await?;
+
+    let result = sqlx::query(
+        r#"
+        UPDATE "docbox_tenants"
+        SET "name" = COALESCE($1, "name"),
+            "event_queue_url" = CASE WHEN $2 THEN $3 ELSE "event_queue_url" END
+        WHERE "env" = $4 AND "id" = $5
+        "#,
+    )
+    .bind(name)
+    .bind(event_queue_url.is_some())
+    .bind(event_queue_url.flatten())
+    .bind(env)
+    .bind(tenant_id)
+    .execute(&db)
+    .await
+    .context("failed to update tenant")?;
+
+    if result.rows_affected() == 0 {
+        anyhow::bail!("tenant not found");
+    }
+
+    Ok(())
+}
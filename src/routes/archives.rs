@@ -0,0 +1,145 @@
+use crate::{
+    auth::Actor,
+    config::ArchiveConfig,
+    database::DatabaseProvider,
+    error::{DynHttpError, HttpResult, TenantError},
+    metadata::{
+        MetadataDatabase,
+        archives::{ArchiveStatus, ArchivedTenant, CreateArchivedTenant},
+        audit::{AuditAction, AuditEvent, CreateAuditEvent},
+    },
+    models::archives::ArchiveTenantRequest,
+};
+use anyhow::Context;
+use axum::{Extension, Json, extract::Path, http::StatusCode};
+use chrono::{TimeDelta, Utc};
+use serde_json::json;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// GET /tenant/archived
+///
+/// Get every archived tenant, soonest to be purged first
+pub async fn get_all(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+) -> HttpResult<Vec<ArchivedTenant>> {
+    let archives = ArchivedTenant::all(&metadata.pool)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(Json(archives))
+}
+
+/// GET /tenant/{env}/{id}/archive
+///
+/// Get the archive of an archived tenant
+pub async fn get(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> HttpResult<ArchivedTenant> {
+    let archive = ArchivedTenant::find(&metadata.pool, &env, tenant_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .ok_or(TenantError::NotArchived)?;
+    Ok(Json(archive))
+}
+
+/// POST /tenant/{env}/{id}/archive
+///
+/// Archive a tenant, the tenant is disabled and excluded from migrations
+/// then purged once the retention period ends unless it is restored
+pub async fn archive(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(archive_config): Extension<Arc<ArchiveConfig>>,
+    actor: Actor,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+    Json(req): Json<ArchiveTenantRequest>,
+) -> Result<(StatusCode, Json<ArchivedTenant>), DynHttpError> {
+    let retention_days = req.retention_days.unwrap_or(archive_config.retention_days);
+    if retention_days < 0 {
+        return Err(TenantError::InvalidRetention.into());
+    }
+
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let archive = ArchivedTenant::create(
+        &metadata.pool,
+        CreateArchivedTenant {
+            env: tenant.env.clone(),
+            tenant_id: tenant.id,
+            name: tenant.name.clone(),
+            reason: req.reason,
+            purge_after: Utc::now() + TimeDelta::days(retention_days),
+            archived_by: actor.0.clone(),
+        },
+    )
+    .await
+    .map_err(anyhow::Error::new)?
+    .ok_or(TenantError::Archived)?;
+
+    AuditEvent::create(
+        &metadata.pool,
+        CreateAuditEvent {
+            action: AuditAction::TenantArchive,
+            env: Some(env),
+            tenant_id: Some(tenant_id),
+            details: json!({
+                "actor": actor.0,
+                "reason": archive.reason,
+                "purge_after": archive.purge_after,
+            }),
+        },
+    )
+    .await
+    .context("failed to record tenant archive in audit log")?;
+
+    tracing::info!(?archive, "tenant archived");
+
+    Ok((StatusCode::CREATED, Json(archive)))
+}
+
+/// POST /tenant/{env}/{id}/archive/restore
+///
+/// Restore an archived tenant that has not been purged
+pub async fn restore(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    actor: Actor,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<StatusCode, DynHttpError> {
+    let archive = ArchivedTenant::find(&metadata.pool, &env, tenant_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .ok_or(TenantError::NotArchived)?;
+
+    if archive.status == ArchiveStatus::Purging
+        || !ArchivedTenant::restore(&metadata.pool, &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+    {
+        return Err(TenantError::Purging.into());
+    }
+
+    AuditEvent::create(
+        &metadata.pool,
+        CreateAuditEvent {
+            action: AuditAction::TenantUnarchive,
+            env: Some(env),
+            tenant_id: Some(tenant_id),
+            details: json!({
+                "actor": actor.0,
+                "archived_by": archive.archived_by,
+                "archived_at": archive.archived_at,
+            }),
+        },
+    )
+    .await
+    .context("failed to record tenant restore in audit log")?;
+
+    tracing::info!(?archive, "archived tenant restored");
+
+    Ok(StatusCode::OK)
+}
@@ -0,0 +1,31 @@
+use crate::{
+    error::HttpResult,
+    metadata::{MetadataDatabase, audit::AuditEvent},
+};
+use axum::{Extension, Json, extract::Query};
+use serde::Deserialize;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Maximum number of audit events to return
+const RECENT_EVENTS_LIMIT: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct AuditQuery {
+    pub env: Option<String>,
+    pub tenant_id: Option<Uuid>,
+}
+
+/// GET /audit
+///
+/// Get the most recent audit events, optionally filtered to a tenant
+pub async fn get_all(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Query(query): Query<AuditQuery>,
+) -> HttpResult<Vec<AuditEvent>> {
+    let tenant = query.env.as_deref().zip(query.tenant_id);
+    let events = AuditEvent::recent(&metadata.pool, tenant, RECENT_EVENTS_LIMIT)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(Json(events))
+}
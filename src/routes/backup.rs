@@ -0,0 +1,133 @@
+use crate::{
+    backup::{
+        backup_tenant,
+        restore::{default_restore_db_name, restore_tenant},
+    },
+    config::BackupConfig,
+    database::DatabaseProvider,
+    error::DynHttpError,
+    jobs::Jobs,
+    metadata::{
+        MetadataDatabase,
+        backups::{BackupReason, TenantBackup},
+        jobs::{CreateJob, Job, JobKind},
+    },
+    models::backup::RestoreRequest,
+};
+use anyhow::Context;
+use axum::{Extension, Json, extract::Path, http::StatusCode};
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// GET /tenant/{env}/{id}/backups
+///
+/// Get the backup catalogue for a tenant
+pub async fn get_all(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<Json<Vec<TenantBackup>>, DynHttpError> {
+    let backups = TenantBackup::find_by_tenant(&metadata.pool, &env, tenant_id)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(Json(backups))
+}
+
+/// POST /tenant/{env}/{id}/backups
+///
+/// Start a background job backing up the tenant database
+pub async fn create(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(backup_config): Extension<Arc<BackupConfig>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    // Ensure backups are configured before starting a job
+    backup_config.bucket()?;
+
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::TenantBackup,
+                env: Some(env),
+                tenant_id: Some(tenant_id),
+            },
+            move |ctx| async move {
+                backup_tenant(
+                    &db_provider,
+                    &storage_factory,
+                    &metadata.pool,
+                    &backup_config,
+                    &tenant,
+                    BackupReason::Manual,
+                    Some(ctx.id),
+                )
+                .await
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// POST /tenant/{env}/{id}/restore
+///
+/// Start a background job restoring a backup into a new database,
+/// optionally switching the tenant to the restored database
+pub async fn restore(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+    Json(req): Json<RestoreRequest>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let backup = TenantBackup::find_by_id(&metadata.pool, &env, tenant_id, req.backup_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .context("backup not found")?;
+
+    let db_name = req
+        .db_name
+        .unwrap_or_else(|| default_restore_db_name(&tenant));
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::TenantRestore,
+                env: Some(env),
+                tenant_id: Some(tenant_id),
+            },
+            move |_ctx| async move {
+                restore_tenant(
+                    &db_provider,
+                    &storage_factory,
+                    &secrets,
+                    &tenant,
+                    &backup,
+                    db_name,
+                    req.swap,
+                )
+                .await
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
@@ -0,0 +1,119 @@
+use crate::{
+    auth::Actor,
+    config::QueryConsoleConfig,
+    console::{run_read_only_query, to_csv},
+    database::DatabaseProvider,
+    error::{DynHttpError, QueryConsoleError},
+    metadata::{
+        MetadataDatabase,
+        audit::{AuditAction, AuditEvent, CreateAuditEvent},
+    },
+    models::console::{QueryFormat, QueryRequest},
+    secrets::get_database_secret,
+};
+use anyhow::Context;
+use axum::{
+    Extension, Json,
+    extract::Path,
+    http::{HeaderValue, header},
+    response::{IntoResponse, Response},
+};
+use docbox_secrets::SecretManager;
+use serde_json::json;
+use sqlx::{Connection, PgConnection};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// POST /tenant/{env}/{id}/query
+///
+/// Run a read-only query against the tenant database as the tenant
+/// role, every query is recorded in the audit log
+pub async fn query(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(console_config): Extension<Arc<QueryConsoleConfig>>,
+    actor: Actor,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+    Json(req): Json<QueryRequest>,
+) -> Result<Response, DynHttpError> {
+    if !console_config.enabled {
+        return Err(QueryConsoleError::Disabled.into());
+    }
+
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let limit = req
+        .limit
+        .unwrap_or(console_config.max_rows)
+        .min(console_config.max_rows);
+
+    let secret = get_database_secret(&secrets, &tenant.db_secret_name).await?;
+    let options = db_provider.config.connect_options_as(
+        &tenant.db_name,
+        &secret.username,
+        &secret.password,
+    );
+    let mut conn = PgConnection::connect_with(&options)
+        .await
+        .context("failed to connect to tenant database")?;
+
+    let result = run_read_only_query(
+        &mut conn,
+        &req.query,
+        limit,
+        console_config.statement_timeout_ms,
+    )
+    .await;
+
+    _ = conn.close().await;
+
+    let details = match &result {
+        Ok(result) => json!({
+            "actor": actor.0,
+            "query": req.query,
+            "limit": limit,
+            "rows": result.rows.len(),
+            "truncated": result.truncated,
+            "duration_ms": result.duration_ms,
+        }),
+        Err(error) => json!({
+            "actor": actor.0,
+            "query": req.query,
+            "limit": limit,
+            "error": format!("{error:#}"),
+        }),
+    };
+
+    AuditEvent::create(
+        &metadata.pool,
+        CreateAuditEvent {
+            action: AuditAction::TenantQuery,
+            env: Some(env),
+            tenant_id: Some(tenant_id),
+            details,
+        },
+    )
+    .await
+    .context("failed to record query in audit log")?;
+
+    let result = result.map_err(|error| QueryConsoleError::Query(format!("{error:#}")))?;
+
+    let response = match req.format {
+        QueryFormat::Json => Json(result).into_response(),
+        QueryFormat::Csv => (
+            [(
+                header::CONTENT_TYPE,
+                HeaderValue::from_static("text/csv; charset=utf-8"),
+            )],
+            to_csv(&result),
+        )
+            .into_response(),
+    };
+
+    Ok(response)
+}
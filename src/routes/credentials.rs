@@ -0,0 +1,76 @@
+use crate::{
+    config::RotationPolicy,
+    credentials::rotate_tenant_credentials,
+    database::DatabaseProvider,
+    error::{DynHttpError, HttpResult},
+    metadata::{MetadataDatabase, credentials::CredentialRotation},
+    models::credentials::TenantCredentialStatus,
+};
+use anyhow::Context;
+use axum::{Extension, Json, extract::Path};
+use docbox_database::DatabasePoolCache;
+use docbox_secrets::SecretManager;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// GET /tenant/credentials
+///
+/// Get the credential rotation status of every tenant
+pub async fn get_status(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(rotation_policy): Extension<Arc<RotationPolicy>>,
+) -> HttpResult<Vec<TenantCredentialStatus>> {
+    let tenants = docbox_management::tenant::get_tenants::get_tenants(db_provider.as_ref())
+        .await
+        .map_err(anyhow::Error::new)?;
+    let rotations = CredentialRotation::latest(&metadata.pool)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let status = tenants
+        .into_iter()
+        .map(|tenant| {
+            let last_rotated_at = rotations
+                .iter()
+                .find(|rotation| rotation.secret_name == tenant.db_secret_name)
+                .map(|rotation| rotation.rotated_at);
+
+            TenantCredentialStatus {
+                env: tenant.env,
+                tenant_id: tenant.id,
+                name: tenant.name,
+                secret_name: tenant.db_secret_name,
+                last_rotated_at,
+                overdue: rotation_policy.is_overdue(last_rotated_at),
+            }
+        })
+        .collect();
+
+    Ok(Json(status))
+}
+
+/// POST /tenant/{env}/{id}/rotate-credentials
+///
+/// Rotate the password of the tenant database role, the cached pool
+/// using the previous credentials is closed
+pub async fn rotate(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(db_cache): Extension<Arc<DatabasePoolCache>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<Json<CredentialRotation>, DynHttpError> {
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let rotation = rotate_tenant_credentials(&db_provider, &secrets, &metadata.pool, &tenant)
+        .await?;
+
+    db_cache.close_tenant_pool(&tenant).await;
+
+    Ok(Json(rotation))
+}
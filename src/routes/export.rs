@@ -0,0 +1,315 @@
+use crate::{
+    auth::Actor,
+    backup::archive::SpoolSender,
+    config::{BackupConfig, ImportConfig},
+    database::DatabaseProvider,
+    error::{DynHttpError, TenantError},
+    export::{
+        EXPORT_CONTENT_TYPE, export_tenant, import_key, import_tenant, read_export_archive,
+        store_tenant_export,
+    },
+    jobs::Jobs,
+    locks::{LockScope, MigrationLock},
+    metadata::{
+        MetadataDatabase,
+        audit::{AuditAction, AuditEvent, CreateAuditEvent},
+        jobs::{CreateJob, Job, JobKind},
+    },
+    models::export::{ImportTenantRequest, ImportUploadResponse},
+    storage::{bucket_storage_layer, download_spooled, upload_spooled},
+};
+use anyhow::Context;
+use axum::{
+    Extension, Json,
+    body::Body,
+    extract::Path,
+    http::{HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use docbox_search::SearchIndexFactory;
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use futures::TryStreamExt;
+use serde_json::json;
+use std::{
+    io::{Seek, SeekFrom},
+    sync::Arc,
+};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// GET /tenant/{env}/{id}/export
+///
+/// Export the tenant into a portable archive and download it, the archive
+/// is built in a temporary file then streamed in the response
+pub async fn download(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    actor: Actor,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<Response, DynHttpError> {
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    // Held while exporting so the database and storage objects are not
+    // changed by a migration, rollback or deletion part way through
+    let lock = MigrationLock::acquire(
+        &metadata.pool,
+        LockScope::tenant(&tenant.env, tenant.id),
+        &actor.0,
+    )
+    .await?;
+
+    let result = export_tenant(&db_provider, &storage_factory, &tenant, None).await;
+    lock.release().await;
+    let (export_id, archive) = result?;
+
+    AuditEvent::create(
+        &metadata.pool,
+        CreateAuditEvent {
+            action: AuditAction::TenantExport,
+            env: Some(env),
+            tenant_id: Some(tenant_id),
+            details: json!({
+                "actor": actor.0,
+                "export_id": export_id,
+                "size": archive.size,
+                "sha256": archive.sha256,
+            }),
+        },
+    )
+    .await
+    .context("failed to record tenant export in audit log")?;
+
+    let disposition = format!("attachment; filename=\"{tenant_id}-{export_id}.tar.gz\"");
+    let disposition = HeaderValue::from_str(&disposition).context("invalid content disposition")?;
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static(EXPORT_CONTENT_TYPE),
+            ),
+            (header::CONTENT_DISPOSITION, disposition),
+            (header::CONTENT_LENGTH, HeaderValue::from(archive.size)),
+        ],
+        Body::from_stream(ReaderStream::new(tokio::fs::File::from_std(archive.file))),
+    )
+        .into_response())
+}
+
+/// POST /tenant/{env}/{id}/export
+///
+/// Start a background job exporting the tenant into a portable archive
+/// stored in the backup bucket
+pub async fn create(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(backup_config): Extension<Arc<BackupConfig>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    actor: Actor,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    // Ensure backups are configured before starting a job
+    let bucket = backup_config.bucket()?.to_string();
+
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    // Held until the job completes
+    let lock = MigrationLock::acquire(
+        &metadata.pool,
+        LockScope::tenant(&tenant.env, tenant.id),
+        &actor.0,
+    )
+    .await?;
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::TenantExport,
+                env: Some(env),
+                tenant_id: Some(tenant_id),
+            },
+            move |ctx| async move {
+                let result =
+                    store_tenant_export(&db_provider, &storage_factory, &bucket, &tenant, &ctx)
+                        .await;
+
+                lock.release().await;
+                let export = result?;
+
+                AuditEvent::create(
+                    &metadata.pool,
+                    CreateAuditEvent {
+                        action: AuditAction::TenantExport,
+                        env: Some(tenant.env.clone()),
+                        tenant_id: Some(tenant.id),
+                        details: json!({
+                            "actor": actor.0,
+                            "export": export,
+                            "job_id": ctx.id,
+                        }),
+                    },
+                )
+                .await
+                .context("failed to record tenant export in audit log")?;
+
+                anyhow::Ok(export)
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// POST /tenant/import/upload
+///
+/// Upload an export archive into the backup bucket so it can be imported,
+/// the archive is written to a temporary file and its manifest is checked
+/// before the archive is stored
+pub async fn upload(
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(backup_config): Extension<Arc<BackupConfig>>,
+    Extension(import_config): Extension<Arc<ImportConfig>>,
+    body: Body,
+) -> Result<(StatusCode, Json<ImportUploadResponse>), DynHttpError> {
+    let bucket = backup_config.bucket()?;
+    let max_size = import_config.max_size;
+
+    let mut stream = body.into_data_stream();
+    let mut sender = SpoolSender::spawn();
+    let mut size = 0;
+
+    while let Some(chunk) = stream
+        .try_next()
+        .await
+        .context("failed to read import archive")?
+    {
+        size += chunk.len() as u64;
+        if size > max_size {
+            return Err(TenantError::ExportTooLarge(max_size).into());
+        }
+
+        sender
+            .send(chunk)
+            .await
+            .context("failed to write import file")?;
+    }
+
+    let mut archive = sender
+        .finish()
+        .await
+        .context("failed to write import file")?;
+
+    let file = archive
+        .file
+        .try_clone()
+        .context("failed to read import file")?;
+    read_export_archive(file)
+        .await
+        .map_err(|error| TenantError::InvalidExport(format!("{error:#}")))?;
+    archive
+        .file
+        .seek(SeekFrom::Start(0))
+        .context("failed to read import file")?;
+
+    let key = import_key(Uuid::new_v4());
+    let size = archive.size;
+    let sha256 = archive.sha256.clone();
+
+    upload_spooled(
+        &bucket_storage_layer(&storage_factory, bucket),
+        &key,
+        EXPORT_CONTENT_TYPE,
+        archive,
+    )
+    .await
+    .context("failed to upload import archive")?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(ImportUploadResponse { key, size, sha256 }),
+    ))
+}
+
+/// POST /tenant/import
+///
+/// Start a background job creating a new tenant from an export archive
+/// in the backup bucket, restoring its database and storage objects and
+/// rebuilding its search index
+// Each extractor is a separate argument of the handler
+#[allow(clippy::too_many_arguments)]
+pub async fn import(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(search_factory): Extension<Arc<SearchIndexFactory>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(backup_config): Extension<Arc<BackupConfig>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    actor: Actor,
+    Json(req): Json<ImportTenantRequest>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    let bucket = backup_config.bucket()?.to_string();
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::TenantImport,
+                env: Some(req.target.env.clone()),
+                tenant_id: Some(req.target.id),
+            },
+            move |ctx| async move {
+                let archive =
+                    download_spooled(&bucket_storage_layer(&storage_factory, &bucket), &req.key)
+                        .await
+                        .context("failed to download export archive")?;
+
+                let outcome = import_tenant(
+                    &db_provider,
+                    &storage_factory,
+                    &search_factory,
+                    &secrets,
+                    archive.file,
+                    req.target,
+                    req.keep_on_failure,
+                    &ctx,
+                )
+                .await?;
+
+                AuditEvent::create(
+                    &metadata.pool,
+                    CreateAuditEvent {
+                        action: AuditAction::TenantImport,
+                        env: Some(outcome.tenant.env.clone()),
+                        tenant_id: Some(outcome.tenant.id),
+                        details: json!({
+                            "actor": actor.0,
+                            "bucket": bucket,
+                            "key": req.key,
+                            "export_id": outcome.export_id,
+                            "source_env": outcome.source.env,
+                            "source_tenant_id": outcome.source.id,
+                            "job_id": ctx.id,
+                        }),
+                    },
+                )
+                .await
+                .context("failed to record tenant import in audit log")?;
+
+                anyhow::Ok(outcome)
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
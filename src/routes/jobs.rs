@@ -0,0 +1,152 @@
+use crate::{
+    error::{DynHttpError, HttpResult, JobError},
+    jobs::Jobs,
+    metadata::{
+        MetadataDatabase,
+        jobs::{Job, JobLog},
+    },
+};
+use anyhow::Context;
+use axum::{
+    Extension, Json,
+    extract::Path,
+    http::StatusCode,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use futures::{Stream, StreamExt};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Maximum number of jobs to return when listing jobs
+const RECENT_JOBS_LIMIT: i64 = 100;
+
+/// GET /jobs
+///
+/// Get the most recent jobs
+pub async fn get_all(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+) -> HttpResult<Vec<Job>> {
+    let jobs = Job::recent(&metadata.pool, None, RECENT_JOBS_LIMIT)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(Json(jobs))
+}
+
+/// GET /jobs/{id}
+///
+/// Get a specific job
+pub async fn get(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path(job_id): Path<Uuid>,
+) -> Result<Json<Job>, DynHttpError> {
+    let job = Job::find_by_id(&metadata.pool, job_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .context("job not found")?;
+    Ok(Json(job))
+}
+
+/// GET /jobs/{id}/logs
+///
+/// Get the logs produced by a job
+pub async fn get_logs(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path(job_id): Path<Uuid>,
+) -> HttpResult<Vec<JobLog>> {
+    let logs = JobLog::find_by_job(&metadata.pool, job_id)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(Json(logs))
+}
+
+/// GET /jobs/{id}/events
+///
+/// Stream the state, logs and progress of a job as server-sent events
+/// until the job finishes
+pub async fn events(
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Path(job_id): Path<Uuid>,
+) -> Result<Sse<impl Stream<Item = Result<Event, axum::Error>>>, DynHttpError> {
+    let events = jobs
+        .watch(job_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .ok_or(JobError::NotFound)?;
+
+    let stream = events.map(|event| Event::default().event(event.name()).json_data(event));
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// POST /jobs/{id}/cancel
+///
+/// Request cancellation of a running job, the job stops at the next
+/// point it is able to
+pub async fn cancel(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Path(job_id): Path<Uuid>,
+) -> Result<StatusCode, DynHttpError> {
+    if jobs.cancel(job_id).await.map_err(anyhow::Error::new)? {
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    Err(unchanged_job_error(&metadata, job_id).await?)
+}
+
+/// POST /jobs/{id}/pause
+///
+/// Pause a running job, the job pauses at the next point it is able to
+pub async fn pause(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Path(job_id): Path<Uuid>,
+) -> Result<StatusCode, DynHttpError> {
+    if jobs
+        .set_paused(job_id, true)
+        .await
+        .map_err(anyhow::Error::new)?
+    {
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    Err(unchanged_job_error(&metadata, job_id).await?)
+}
+
+/// POST /jobs/{id}/resume
+///
+/// Resume a paused job
+pub async fn resume(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Path(job_id): Path<Uuid>,
+) -> Result<StatusCode, DynHttpError> {
+    if jobs
+        .set_paused(job_id, false)
+        .await
+        .map_err(anyhow::Error::new)?
+    {
+        return Ok(StatusCode::ACCEPTED);
+    }
+
+    Err(unchanged_job_error(&metadata, job_id).await?)
+}
+
+/// Error for a job that could not be changed, either because it does not
+/// exist or because it has already finished
+async fn unchanged_job_error(
+    metadata: &MetadataDatabase,
+    job_id: Uuid,
+) -> Result<DynHttpError, DynHttpError> {
+    let exists = Job::find_by_id(&metadata.pool, job_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .is_some();
+
+    Ok(if exists {
+        JobError::Finished
+    } else {
+        JobError::NotFound
+    }
+    .into())
+}
@@ -0,0 +1,203 @@
+use crate::{
+    database::DatabaseProvider,
+    drift::{TenantDriftReport, check_fleet_drift, check_tenant_drift},
+    error::{DynHttpError, HttpResult},
+    jobs::Jobs,
+    metadata::{
+        MetadataDatabase,
+        jobs::{CreateJob, Job, JobKind},
+        migration_applications::TenantMigrationApplication,
+    },
+    migrations::{
+        DryRunReport, MigrationSql, all_tenant_migrations, dry_run_tenant_migrations,
+        get_pending_migrations_sql,
+    },
+    models::migrations::{AppliedTenantMigration, FleetDriftRequest, TenantSchemaVersion},
+    root::{get_all_applied_migrations, get_tenant_applied_migrations},
+    rollout::select_tenants,
+};
+use anyhow::Context;
+use axum::{Extension, Json, extract::Path, http::StatusCode};
+use docbox_database::migrations::TENANT_MIGRATIONS;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// GET /root/migrations/sql
+///
+/// Get the SQL for every tenant migration
+pub async fn get_all_sql() -> HttpResult<Vec<MigrationSql>> {
+    Ok(Json(all_tenant_migrations()))
+}
+
+/// GET /root/migrations/versions
+///
+/// Get the schema version of every tenant
+pub async fn get_versions(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+) -> HttpResult<Vec<TenantSchemaVersion>> {
+    let tenants = docbox_management::tenant::get_tenants::get_tenants(db_provider.as_ref())
+        .await
+        .map_err(anyhow::Error::new)?;
+    let applied = get_all_applied_migrations(&db_provider).await?;
+
+    let versions = tenants
+        .into_iter()
+        .map(|tenant| {
+            let tenant_applied: Vec<_> = applied
+                .iter()
+                .filter(|migration| migration.env == tenant.env && migration.tenant_id == tenant.id)
+                .collect();
+
+            let is_applied =
+                |name: &str| tenant_applied.iter().any(|migration| migration.name == name);
+
+            let version = TENANT_MIGRATIONS
+                .iter()
+                .rev()
+                .find(|(name, _)| is_applied(name))
+                .map(|(name, _)| name.to_string());
+
+            let pending = TENANT_MIGRATIONS
+                .iter()
+                .filter(|(name, _)| !is_applied(name))
+                .map(|(name, _)| name.to_string())
+                .collect();
+
+            TenantSchemaVersion {
+                version,
+                applied: tenant_applied.len(),
+                pending,
+                last_applied_at: tenant_applied
+                    .iter()
+                    .map(|migration| migration.applied_at)
+                    .max(),
+                env: tenant.env,
+                tenant_id: tenant.id,
+                name: tenant.name,
+            }
+        })
+        .collect();
+
+    Ok(Json(versions))
+}
+
+/// GET /tenant/{env}/{id}/migrations
+///
+/// Get the migrations applied to the tenant
+pub async fn get_applied(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<Json<Vec<AppliedTenantMigration>>, DynHttpError> {
+    let applied = get_tenant_applied_migrations(&db_provider, &env, tenant_id).await?;
+    let applications = TenantMigrationApplication::find_by_tenant(&metadata.pool, &env, tenant_id)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let applied = applied
+        .into_iter()
+        .map(|migration| {
+            // Applications are oldest first, a migration rolled back and
+            // applied again was last applied by the latest application
+            let applied_by = applications
+                .iter()
+                .rfind(|application| application.name == migration.name)
+                .map(|application| application.applied_by.clone());
+
+            AppliedTenantMigration {
+                name: migration.name,
+                applied_at: migration.applied_at,
+                applied_by,
+            }
+        })
+        .collect();
+
+    Ok(Json(applied))
+}
+
+/// GET /tenant/{env}/{id}/migrations/pending
+///
+/// Get the name and SQL of every migration pending for the tenant
+pub async fn get_pending(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<Json<Vec<MigrationSql>>, DynHttpError> {
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let pending = get_pending_migrations_sql(&db_provider, &tenant).await?;
+    Ok(Json(pending))
+}
+
+/// POST /tenant/{env}/{id}/migrations/dry-run
+///
+/// Apply the pending migrations for the tenant inside a transaction
+/// that is rolled back, reporting whether each migration applied
+///
+/// The dry-run runs against the live tenant database and takes the same
+/// locks as the real migrations (e.g. `ACCESS EXCLUSIVE` for `ALTER TABLE`),
+/// tenant requests touching the locked tables wait until the dry-run is
+/// rolled back. Each migration may wait at most 5 seconds for a lock and
+/// run for at most 15 seconds, so the tenant can be blocked for up to 15
+/// seconds per pending migration
+pub async fn dry_run(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<Json<DryRunReport>, DynHttpError> {
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let report = dry_run_tenant_migrations(&db_provider, &tenant).await?;
+    Ok(Json(report))
+}
+
+/// GET /tenant/{env}/{id}/drift
+///
+/// Compare the tenant schema with a scratch database migrated to the
+/// same version and report any differences
+pub async fn get_drift(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> HttpResult<TenantDriftReport> {
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let report = check_tenant_drift(&db_provider, &tenant).await?;
+    Ok(Json(report))
+}
+
+/// POST /root/drift
+///
+/// Start a background job checking the selected tenants for schema drift
+pub async fn check_drift(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    Json(req): Json<FleetDriftRequest>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    let tenants = select_tenants(&db_provider, &metadata.pool, &req.selector).await?;
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::DriftCheck,
+                env: req.selector.env.clone(),
+                tenant_id: None,
+            },
+            move |ctx| async move {
+                check_fleet_drift(&db_provider, tenants, req.reference, &ctx).await
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
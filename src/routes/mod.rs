@@ -5,9 +5,20 @@ use axum::{
 
 use crate::auth::auth_middleware;
 
+pub mod archives;
+pub mod audit;
 pub mod auth;
+pub mod backup;
+pub mod console;
+pub mod credentials;
+pub mod export;
+pub mod jobs;
+pub mod migrations;
 pub mod public;
+pub mod rollback;
 pub mod root;
+pub mod schedules;
+pub mod system;
 pub mod tenant;
 
 pub fn router() -> Router {
@@ -16,11 +27,17 @@ pub fn router() -> Router {
             "/api",
             Router::new()
                 .nest("/auth", auth_router())
+                // Metrics use their own token authentication
+                .route("/metrics", get(system::metrics))
                 // Authenticated routes
                 .merge(
                     Router::new()
                         .nest("/tenant", tenant_router())
                         .nest("/root", root_router())
+                        .nest("/system", system_router())
+                        .nest("/jobs", jobs_router())
+                        .nest("/schedules", schedules_router())
+                        .route("/audit", get(audit::get_all))
                         .layer(axum::middleware::from_fn(auth_middleware)),
                 ),
         )
@@ -38,18 +55,74 @@ fn root_router() -> Router {
     Router::new()
         .route("/initialized", get(root::is_initialized))
         .route("/initialize", post(root::initialize))
+        .route("/status", get(root::get_status))
+        .route("/migrate-root", post(root::migrate_root_database))
         .route("/migrations", get(root::get_pending_migrations))
+        .route("/migrations/sql", get(migrations::get_all_sql))
+        .route("/migrations/versions", get(migrations::get_versions))
+        .route("/drift", post(migrations::check_drift))
         .route("/migrate", post(root::migrate))
+        .route("/migrate/jobs", post(root::migrate_job))
+        .route("/migrate/rollout", post(root::rollout))
+        .route("/rollback", post(rollback::rollback_all))
+        .route("/rollback/plan", post(rollback::plan_all))
+        .route("/credentials", get(root::get_credentials_status))
+        .route("/rotate-credentials", post(root::rotate_credentials))
+        .route("/teardown", post(root::teardown))
+}
+
+fn system_router() -> Router {
+    Router::new()
+        .route("/migrations", get(system::get_migrations))
+        .route("/database", get(system::get_database_stats))
+}
+
+fn jobs_router() -> Router {
+    Router::new()
+        .route("/", get(jobs::get_all))
+        .route("/{job_id}", get(jobs::get))
+        .route("/{job_id}/logs", get(jobs::get_logs))
+        .route("/{job_id}/events", get(jobs::events))
+        .route("/{job_id}/cancel", post(jobs::cancel))
+        .route("/{job_id}/pause", post(jobs::pause))
+        .route("/{job_id}/resume", post(jobs::resume))
+}
+
+fn schedules_router() -> Router {
+    Router::new()
+        .route("/", get(schedules::get_all).post(schedules::create))
+        .route("/{schedule_id}", get(schedules::get))
+        .route("/{schedule_id}/cancel", post(schedules::cancel))
 }
 
 fn tenant_router() -> Router {
     Router::new()
         .route("/", get(tenant::get_all).post(tenant::create))
+        .route("/credentials", get(credentials::get_status))
+        .route("/archived", get(archives::get_all))
+        .route("/import", post(export::import))
+        .route("/import/upload", post(export::upload))
         .nest(
             "/{env}/{tenant_id}",
             Router::new()
-                .route("/", get(tenant::get).delete(tenant::delete))
+                .route("/", get(tenant::get).patch(tenant::update).delete(tenant::delete))
+                .route("/deletion-plan", get(tenant::deletion_plan))
+                .route("/archive", get(archives::get).post(archives::archive))
+                .route("/archive/restore", post(archives::restore))
+                .route("/clone", post(tenant::clone))
+                .route("/export", get(export::download).post(export::create))
                 .route("/migrate", post(tenant::migrate))
+                .route("/migrations", get(migrations::get_applied))
+                .route("/migrations/pending", get(migrations::get_pending))
+                .route("/migrations/dry-run", post(migrations::dry_run))
+                .route("/drift", get(migrations::get_drift))
+                .route("/rollback", post(rollback::rollback))
+                .route("/rollback/plan", post(rollback::plan))
+                .route("/backups", get(backup::get_all).post(backup::create))
+                .route("/restore", post(backup::restore))
+                .route("/rotate-credentials", post(credentials::rotate))
+                .route("/tags", get(tenant::get_tags).put(tenant::set_tags))
+                .route("/query", post(console::query))
                 .route("/gateway/{*tail}", any(tenant::docbox_gateway)),
         )
 }
@@ -0,0 +1,191 @@
+use crate::{
+    auth::Actor,
+    database::DatabaseProvider,
+    error::{DynHttpError, HttpResult, RollbackError},
+    jobs::Jobs,
+    metadata::{
+        MetadataDatabase,
+        jobs::{CreateJob, Job, JobKind},
+    },
+    models::migrations::{
+        FleetRollbackPlanRequest, FleetRollbackRequest, RollbackPlanRequest, RollbackRequest,
+    },
+    rollback::{
+        TenantRollbackPlan, is_tenant_migration, plan_tenant_rollback, rollback_fleet,
+        rollback_tenant,
+    },
+    rollout::select_tenants,
+};
+use anyhow::Context;
+use axum::{Extension, Json, extract::Path, http::StatusCode};
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use futures::{TryStreamExt, stream::FuturesOrdered};
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// POST /tenant/{env}/{id}/rollback/plan
+///
+/// Plan rolling the tenant back to an earlier migration, shows the
+/// migrations that will be reverted and the backup that will be restored
+pub async fn plan(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+    Json(req): Json<RollbackPlanRequest>,
+) -> HttpResult<TenantRollbackPlan> {
+    if !is_tenant_migration(&req.target_migration_name) {
+        return Err(RollbackError::UnknownMigration(req.target_migration_name).into());
+    }
+
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let plan = plan_tenant_rollback(
+        &db_provider,
+        &metadata.pool,
+        &tenant,
+        &req.target_migration_name,
+    )
+    .await?;
+
+    Ok(Json(plan))
+}
+
+/// POST /tenant/{env}/{id}/rollback
+///
+/// Start a background job rolling the tenant back to an earlier migration
+/// by restoring the backup from the rollback plan
+// Each extractor is a separate argument of the handler
+#[allow(clippy::too_many_arguments)]
+pub async fn rollback(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    actor: Actor,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+    Json(req): Json<RollbackRequest>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    if !req.confirm {
+        return Err(RollbackError::NotConfirmed.into());
+    }
+
+    if !is_tenant_migration(&req.target_migration_name) {
+        return Err(RollbackError::UnknownMigration(req.target_migration_name).into());
+    }
+
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::TenantRollback,
+                env: Some(env),
+                tenant_id: Some(tenant_id),
+            },
+            move |ctx| async move {
+                rollback_tenant(
+                    &db_provider,
+                    &storage_factory,
+                    &secrets,
+                    &metadata.pool,
+                    &tenant,
+                    &req.target_migration_name,
+                    req.backup_id,
+                    &actor.0,
+                    Some(ctx.id),
+                )
+                .await
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// POST /root/rollback/plan
+///
+/// Plan rolling the selected tenants back to an earlier migration
+pub async fn plan_all(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Json(req): Json<FleetRollbackPlanRequest>,
+) -> HttpResult<Vec<TenantRollbackPlan>> {
+    if !is_tenant_migration(&req.target_migration_name) {
+        return Err(RollbackError::UnknownMigration(req.target_migration_name).into());
+    }
+
+    let tenants = select_tenants(&db_provider, &metadata.pool, &req.selector).await?;
+
+    let plans = tenants
+        .iter()
+        .map(|tenant| {
+            plan_tenant_rollback(
+                &db_provider,
+                &metadata.pool,
+                tenant,
+                &req.target_migration_name,
+            )
+        })
+        .collect::<FuturesOrdered<_>>()
+        .try_collect::<Vec<_>>()
+        .await?;
+
+    Ok(Json(plans))
+}
+
+/// POST /root/rollback
+///
+/// Start a background job rolling back many tenants to an earlier
+/// migration using the backups from their rollback plans
+pub async fn rollback_all(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    actor: Actor,
+    Json(req): Json<FleetRollbackRequest>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    if !req.confirm {
+        return Err(RollbackError::NotConfirmed.into());
+    }
+
+    if !is_tenant_migration(&req.target_migration_name) {
+        return Err(RollbackError::UnknownMigration(req.target_migration_name).into());
+    }
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::FleetRollback,
+                env: None,
+                tenant_id: None,
+            },
+            move |ctx| async move {
+                rollback_fleet(
+                    &db_provider,
+                    &storage_factory,
+                    &secrets,
+                    &metadata.pool,
+                    &req.tenants,
+                    &req.target_migration_name,
+                    &actor.0,
+                    &ctx,
+                )
+                .await
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
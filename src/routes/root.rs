@@ -1,13 +1,45 @@
 use crate::{
-    config::DatabaseConfig,
+    auth::Actor,
+    config::{DatabaseConfig, RotationPolicy, TeardownConfig},
+    credentials::rotate_root_credentials,
     database::DatabaseProvider,
-    error::{DynHttpError, HttpResult},
-    models::root::{IsInitializedResponse, TenantWithMigrations},
+    error::{DynHttpError, HttpResult, InitializeError, RolloutError, TeardownError},
+    initialize::{
+        DEFAULT_ROOT_ROLE_NAME, InitializeOptions, execute_initialize, is_valid_role_name,
+        plan_initialize,
+    },
+    jobs::Jobs,
+    locks::{LockScope, MigrationLock},
+    metadata::{
+        MetadataDatabase,
+        audit::{AuditAction, AuditEvent, CreateAuditEvent},
+        credentials::CredentialRotation,
+        jobs::{CreateJob, Job, JobKind},
+    },
+    migrations::{
+        FleetMigrationReport, get_pending_root_migrations, migrate_fleet, migrate_root,
+        root_schema_version,
+    },
+    models::{
+        credentials::RootCredentialStatus,
+        root::{
+            EnvTenantCount, InitializeRootRequest, InitializeRootResponse, IsInitializedResponse,
+            MigrateRootRequest, MigrateRootResponse, MigrateTenantsRequest, RolloutRequest,
+            RootStatusResponse, TeardownRootRequest, TenantWithMigrations,
+        },
+    },
+    rollout::{MAX_CONCURRENCY, run_rollout},
+    teardown::{RootTeardownReport, teardown_root},
 };
+use anyhow::Context;
 use axum::{Extension, Json, http::StatusCode};
-use docbox_management::tenant::migrate_tenants::MigrateTenantsConfig;
+use docbox_database::{DatabasePoolCache, migrations::ROOT_MIGRATIONS};
+use docbox_search::SearchIndexFactory;
 use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
 use futures::{TryStreamExt, stream::FuturesOrdered};
+use itertools::Itertools;
+use serde_json::json;
 use std::sync::Arc;
 
 /// GET /root/initialized
@@ -22,25 +54,124 @@ pub async fn is_initialized(
     Ok(Json(IsInitializedResponse { initialized }))
 }
 
+/// GET /root/status
+///
+/// Get the status of the root database, its schema version and the
+/// number of tenants in each environment
+pub async fn get_status(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+) -> HttpResult<RootStatusResponse> {
+    let initialized = docbox_management::root::initialize::is_initialized(db_provider.as_ref())
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    if !initialized {
+        return Ok(Json(RootStatusResponse {
+            initialized,
+            version: None,
+            pending_migrations: ROOT_MIGRATIONS
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            tenants: Vec::new(),
+        }));
+    }
+
+    let pending_migrations = get_pending_root_migrations(&db_provider).await?;
+    let tenants = docbox_management::tenant::get_tenants::get_tenants(db_provider.as_ref())
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let tenants = tenants
+        .into_iter()
+        .counts_by(|tenant| tenant.env)
+        .into_iter()
+        .map(|(env, tenants)| EnvTenantCount { env, tenants })
+        .sorted_by(|a, b| a.env.cmp(&b.env))
+        .collect();
+
+    Ok(Json(RootStatusResponse {
+        initialized,
+        version: root_schema_version(&pending_migrations),
+        pending_migrations,
+        tenants,
+    }))
+}
+
+/// POST /root/migrate-root
+///
+/// Applies pending migrations to the root database, separately from
+/// the tenant migrations
+pub async fn migrate_root_database(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    actor: Actor,
+    Json(req): Json<MigrateRootRequest>,
+) -> HttpResult<MigrateRootResponse> {
+    let lock = MigrationLock::acquire(&metadata.pool, LockScope::Fleet, &actor.0).await?;
+    let result = migrate_root(&db_provider, req.target_migration_name.as_deref(), &actor.0).await;
+    lock.release().await;
+
+    Ok(Json(MigrateRootResponse { applied: result? }))
+}
+
 /// POST /root/initialize
 ///
 /// - Create the root database
 /// - Setup the root database role
 /// - Store the root database credentials
 /// - Setup the root database
+///
+/// Steps that are already complete are skipped, with `dry_run` the
+/// planned steps are returned without being performed
 pub async fn initialize(
     Extension(database_config): Extension<Arc<DatabaseConfig>>,
     Extension(secrets): Extension<Arc<SecretManager>>,
     Extension(db_provider): Extension<Arc<DatabaseProvider>>,
-) -> Result<StatusCode, DynHttpError> {
-    docbox_management::root::initialize::initialize(
-        db_provider.as_ref(),
-        &secrets,
-        &database_config.root_secret_name,
-    )
-    .await
-    .map_err(anyhow::Error::new)?;
-    Ok(StatusCode::CREATED)
+    req: Option<Json<InitializeRootRequest>>,
+) -> Result<(StatusCode, Json<InitializeRootResponse>), DynHttpError> {
+    let Json(req) = req.unwrap_or_default();
+
+    let role_name = req
+        .role_name
+        .unwrap_or_else(|| DEFAULT_ROOT_ROLE_NAME.to_string());
+
+    if !is_valid_role_name(&role_name) {
+        return Err(InitializeError::InvalidRoleName.into());
+    }
+
+    let options = InitializeOptions {
+        role_name,
+        secret_name: database_config.root_secret_name.clone(),
+        overwrite_secret: req.overwrite_secret,
+    };
+
+    let plan = plan_initialize(&db_provider, &secrets, &options).await?;
+
+    // The plan reports whether the secret exists when only planning
+    if req.dry_run {
+        return Ok((
+            StatusCode::OK,
+            Json(InitializeRootResponse {
+                dry_run: true,
+                plan,
+            }),
+        ));
+    }
+
+    if plan.secret_exists && !options.overwrite_secret {
+        return Err(InitializeError::SecretExists(plan.secret_name).into());
+    }
+
+    execute_initialize(&db_provider, &secrets, &plan).await?;
+
+    Ok((
+        StatusCode::CREATED,
+        Json(InitializeRootResponse {
+            dry_run: false,
+            plan,
+        }),
+    ))
 }
 
 /// GET /root/migrations
@@ -75,16 +206,215 @@ pub async fn get_pending_migrations(
 
 /// POST /root/migrate
 ///
-/// Applies migrations against all tenants
+/// Applies migrations against all tenants, responds with the outcome for
+/// each tenant using 207 Multi-Status when any tenant failed
 pub async fn migrate(
     Extension(db_provider): Extension<Arc<DatabaseProvider>>,
-    Json(migrate): Json<MigrateTenantsConfig>,
-) -> Result<StatusCode, DynHttpError> {
-    let outcome =
-        docbox_management::tenant::migrate_tenants::migrate_tenants(db_provider.as_ref(), migrate)
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    actor: Actor,
+    Json(migrate): Json<MigrateTenantsRequest>,
+) -> Result<(StatusCode, Json<FleetMigrationReport>), DynHttpError> {
+    let lock = MigrationLock::acquire(&metadata.pool, LockScope::Fleet, &actor.0).await?;
+    let result = migrate_fleet(&db_provider, &metadata.pool, &migrate, &actor.0, None).await;
+    lock.release().await;
+    let report = result?;
+
+    tracing::debug!(?report, "completed migrations");
+
+    let status = if report.failed > 0 {
+        StatusCode::MULTI_STATUS
+    } else {
+        StatusCode::OK
+    };
+
+    Ok((status, Json(report)))
+}
+
+/// POST /root/migrate/jobs
+///
+/// Start a background job applying migrations against all tenants, the
+/// job output is the outcome for each tenant
+pub async fn migrate_job(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    actor: Actor,
+    Json(migrate): Json<MigrateTenantsRequest>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    // Held until the job completes
+    let lock = MigrationLock::acquire(&metadata.pool, LockScope::Fleet, &actor.0).await?;
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::FleetMigration,
+                env: migrate.env.clone(),
+                tenant_id: migrate.tenant_id,
+            },
+            move |ctx| async move {
+                let result =
+                    migrate_fleet(&db_provider, &metadata.pool, &migrate, &actor.0, Some(&ctx))
+                        .await;
+                lock.release().await;
+                result
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// POST /root/migrate/rollout
+///
+/// Start a background job migrating the selected tenants in waves,
+/// starting with a canary wave and halting when the failure rate
+/// exceeds the threshold. The job can be paused and resumed between waves
+pub async fn rollout(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    actor: Actor,
+    Json(rollout): Json<RolloutRequest>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    if rollout.wave_size == 0 {
+        return Err(RolloutError::WaveSize.into());
+    }
+
+    if !(1..=MAX_CONCURRENCY).contains(&rollout.concurrency) {
+        return Err(RolloutError::Concurrency.into());
+    }
+
+    if !(0.0..=1.0).contains(&rollout.max_failure_rate) {
+        return Err(RolloutError::FailureRate.into());
+    }
+
+    // Held until the job completes
+    let lock = MigrationLock::acquire(&metadata.pool, LockScope::Fleet, &actor.0).await?;
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::MigrationRollout,
+                env: rollout.selector.env.clone(),
+                tenant_id: None,
+            },
+            move |ctx| async move {
+                let result =
+                    run_rollout(&db_provider, &metadata.pool, &rollout, &actor.0, &ctx).await;
+                lock.release().await;
+                result
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// GET /root/credentials
+///
+/// Get the rotation status of the root database credentials
+pub async fn get_credentials_status(
+    Extension(database_config): Extension<Arc<DatabaseConfig>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(rotation_policy): Extension<Arc<RotationPolicy>>,
+) -> HttpResult<RootCredentialStatus> {
+    let last_rotated_at =
+        CredentialRotation::latest_for_secret(&metadata.pool, &database_config.root_secret_name)
             .await
-            .map_err(anyhow::Error::new)?;
+            .map_err(anyhow::Error::new)?
+            .map(|rotation| rotation.rotated_at);
+
+    Ok(Json(RootCredentialStatus {
+        secret_name: database_config.root_secret_name.clone(),
+        last_rotated_at,
+        overdue: rotation_policy.is_overdue(last_rotated_at),
+    }))
+}
+
+/// POST /root/rotate-credentials
+///
+/// Rotate the password of the root database role, cached pools using
+/// the previous credentials are closed
+pub async fn rotate_credentials(
+    Extension(database_config): Extension<Arc<DatabaseConfig>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(db_cache): Extension<Arc<DatabasePoolCache>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+) -> HttpResult<CredentialRotation> {
+    let rotation = rotate_root_credentials(
+        &db_provider,
+        &secrets,
+        &metadata.pool,
+        &database_config.root_secret_name,
+    )
+    .await?;
+
+    db_cache.close_all().await;
+
+    Ok(Json(rotation))
+}
+
+/// POST /root/teardown
+///
+/// Destroy every tenant along with the root database, its role and the
+/// root secret leaving the server uninitialized. Only available when
+/// teardown is enabled for development environments
+// Each extractor is a separate argument of the handler
+#[allow(clippy::too_many_arguments)]
+pub async fn teardown(
+    Extension(teardown_config): Extension<Arc<TeardownConfig>>,
+    Extension(database_config): Extension<Arc<DatabaseConfig>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(db_cache): Extension<Arc<DatabasePoolCache>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(search_factory): Extension<Arc<SearchIndexFactory>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    actor: Actor,
+    Json(req): Json<TeardownRootRequest>,
+) -> HttpResult<RootTeardownReport> {
+    if !teardown_config.enabled {
+        return Err(TeardownError::Disabled.into());
+    }
+
+    if !req.confirm {
+        return Err(TeardownError::NotConfirmed.into());
+    }
+
+    let lock = MigrationLock::acquire(&metadata.pool, LockScope::Fleet, &actor.0).await?;
+
+    tracing::warn!(actor = %actor.0, "tearing down root database");
+
+    let result = teardown_root(
+        &db_provider,
+        &db_cache,
+        &storage_factory,
+        &search_factory,
+        &secrets,
+        &metadata.pool,
+        &database_config.root_secret_name,
+        req.force,
+    )
+    .await;
+
+    lock.release().await;
+    let report = result?;
+
+    AuditEvent::create(
+        &metadata.pool,
+        CreateAuditEvent {
+            action: AuditAction::RootTeardown,
+            env: None,
+            tenant_id: None,
+            details: json!({
+                "actor": actor.0,
+                "report": report,
+            }),
+        },
+    )
+    .await
+    .context("failed to record teardown in audit log")?;
 
-    tracing::debug!(?outcome, "completed migrations");
-    Ok(StatusCode::OK)
+    Ok(Json(report))
 }
@@ -0,0 +1,115 @@
+use crate::{
+    auth::Actor,
+    error::{DynHttpError, HttpResult, ScheduleError},
+    metadata::{
+        MetadataDatabase,
+        jobs::Job,
+        schedules::{CreateScheduledMigration, ScheduledMigration},
+    },
+    models::schedules::{CreateScheduleRequest, ScheduledMigrationDetails},
+};
+use axum::{Extension, Json, extract::Path, http::StatusCode};
+use chrono::Utc;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Maximum number of schedules to return when listing schedules
+const RECENT_SCHEDULES_LIMIT: i64 = 100;
+
+/// GET /schedules
+///
+/// Get the most recent scheduled migrations
+pub async fn get_all(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+) -> HttpResult<Vec<ScheduledMigration>> {
+    let schedules = ScheduledMigration::recent(&metadata.pool, RECENT_SCHEDULES_LIMIT)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(Json(schedules))
+}
+
+/// POST /schedules
+///
+/// Schedule a migration to run automatically within a maintenance window
+pub async fn create(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    actor: Actor,
+    Json(req): Json<CreateScheduleRequest>,
+) -> Result<(StatusCode, Json<ScheduledMigration>), DynHttpError> {
+    if req.window_end <= req.window_start {
+        return Err(ScheduleError::InvalidWindow.into());
+    }
+
+    if req.window_end <= Utc::now() {
+        return Err(ScheduleError::WindowEnded.into());
+    }
+
+    let schedule = ScheduledMigration::create(
+        &metadata.pool,
+        CreateScheduledMigration {
+            env: req.env,
+            tenant_id: req.tenant_id,
+            target_migration_name: req.target_migration_name,
+            skip_failed: req.skip_failed,
+            window_start: req.window_start,
+            window_end: req.window_end,
+            created_by: actor.0,
+        },
+    )
+    .await
+    .map_err(anyhow::Error::new)?;
+
+    tracing::info!(?schedule, "scheduled migration");
+
+    Ok((StatusCode::CREATED, Json(schedule)))
+}
+
+/// GET /schedules/{id}
+///
+/// Get a scheduled migration along with the job that ran it
+pub async fn get(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path(schedule_id): Path<Uuid>,
+) -> HttpResult<ScheduledMigrationDetails> {
+    let schedule = ScheduledMigration::find_by_id(&metadata.pool, schedule_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .ok_or(ScheduleError::NotFound)?;
+
+    let job = match schedule.job_id {
+        Some(job_id) => Job::find_by_id(&metadata.pool, job_id)
+            .await
+            .map_err(anyhow::Error::new)?,
+        None => None,
+    };
+
+    Ok(Json(ScheduledMigrationDetails { schedule, job }))
+}
+
+/// POST /schedules/{id}/cancel
+///
+/// Cancel a scheduled migration that has not started, running
+/// migrations are cancelled through their job
+pub async fn cancel(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path(schedule_id): Path<Uuid>,
+) -> Result<StatusCode, DynHttpError> {
+    if ScheduledMigration::cancel(&metadata.pool, schedule_id)
+        .await
+        .map_err(anyhow::Error::new)?
+    {
+        return Ok(StatusCode::OK);
+    }
+
+    let exists = ScheduledMigration::find_by_id(&metadata.pool, schedule_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .is_some();
+
+    Err(if exists {
+        ScheduleError::AlreadyStarted
+    } else {
+        ScheduleError::NotFound
+    }
+    .into())
+}
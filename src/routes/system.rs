@@ -0,0 +1,127 @@
+use crate::{
+    config::MetricsToken,
+    database::{DatabaseProvider, get_server_activity},
+    error::{DynHttpError, HttpResult},
+    metadata::{MetadataDatabase, migrations},
+    metrics::render_database_metrics,
+    models::system::{
+        DatabaseActivity, DatabaseStatsResponse, DatabaseTenant, ManagerMigrationsResponse,
+    },
+};
+use axum::{
+    Extension, Json,
+    http::{HeaderMap, HeaderValue, StatusCode, header},
+    response::{IntoResponse, Response},
+};
+use sha2::{Digest, Sha256};
+use std::sync::Arc;
+
+/// GET /system/migrations
+///
+/// Get the applied and pending migrations for the manager database
+pub async fn get_migrations(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+) -> HttpResult<ManagerMigrationsResponse> {
+    let applied = migrations::get_applied_migrations(&metadata.pool)
+        .await
+        .map_err(anyhow::Error::new)?;
+    let pending = migrations::get_pending_migrations(&metadata.pool)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    Ok(Json(ManagerMigrationsResponse { applied, pending }))
+}
+
+/// GET /system/database
+///
+/// Get statistics about the manager database pools and the server
+/// side connections for each database
+pub async fn get_database_stats(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+) -> HttpResult<DatabaseStatsResponse> {
+    let stats = collect_database_stats(&db_provider, &metadata).await?;
+    Ok(Json(stats))
+}
+
+/// GET /metrics
+///
+/// Database statistics in the Prometheus text format, requires the
+/// configured metrics token as a bearer token
+pub async fn metrics(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(metrics_token): Extension<Arc<MetricsToken>>,
+    headers: HeaderMap,
+) -> Result<Response, DynHttpError> {
+    let Some(expected_token) = metrics_token.0.as_deref() else {
+        return Ok(StatusCode::NOT_FOUND.into_response());
+    };
+
+    let token = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if !token.is_some_and(|token| tokens_match(token, expected_token)) {
+        return Ok(StatusCode::UNAUTHORIZED.into_response());
+    }
+
+    let stats = collect_database_stats(&db_provider, &metadata).await?;
+    let body = render_database_metrics(&stats);
+
+    Ok((
+        [(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("text/plain; version=0.0.4"),
+        )],
+        body,
+    )
+        .into_response())
+}
+
+/// Compare tokens in constant time, the tokens are hashed first so the
+/// comparison does not reveal the length of the expected token
+fn tokens_match(token: &str, expected: &str) -> bool {
+    let token = Sha256::digest(token.as_bytes());
+    let expected = Sha256::digest(expected.as_bytes());
+
+    token
+        .iter()
+        .zip(expected.iter())
+        .fold(0, |diff, (a, b)| diff | (a ^ b))
+        == 0
+}
+
+async fn collect_database_stats(
+    db_provider: &DatabaseProvider,
+    metadata: &MetadataDatabase,
+) -> anyhow::Result<DatabaseStatsResponse> {
+    let pools = db_provider.pool_stats();
+    let activity = get_server_activity(&metadata.pool).await?;
+
+    // Tenants are only used to label the databases, the stats are still
+    // useful when the root database is unavailable
+    let tenants = docbox_management::tenant::get_tenants::get_tenants(db_provider)
+        .await
+        .inspect_err(|error| tracing::warn!(?error, "failed to load tenants for database stats"))
+        .unwrap_or_default();
+
+    let server = activity
+        .into_iter()
+        .map(|activity| {
+            let tenant = tenants
+                .iter()
+                .find(|tenant| tenant.db_name == activity.database)
+                .map(|tenant| DatabaseTenant {
+                    env: tenant.env.clone(),
+                    id: tenant.id,
+                    name: tenant.name.clone(),
+                });
+
+            DatabaseActivity { activity, tenant }
+        })
+        .collect();
+
+    Ok(DatabaseStatsResponse { pools, server })
+}
@@ -1,4 +1,28 @@
-use crate::{config::DocboxServerUrl, database::DatabaseProvider, error::DynHttpError};
+use crate::{
+    auth::Actor,
+    backup::backup_tenant,
+    clone::clone_tenant,
+    config::{BackupConfig, DocboxServerUrl},
+    database::DatabaseProvider,
+    error::{DynHttpError, TenantError},
+    jobs::Jobs,
+    locks::{LockScope, MigrationLock},
+    metadata::{
+        MetadataDatabase,
+        archives::ArchivedTenant,
+        audit::{AuditAction, AuditEvent, CreateAuditEvent},
+        backups::BackupReason,
+        jobs::{CreateJob, Job, JobKind},
+        tags::{get_tenant_tags, set_tenant_tags},
+    },
+    migrations::migrate_tenant_recorded,
+    models::tenant::{CloneTenantRequest, DeleteTenantRequest, TenantTags, UpdateTenantRequest},
+    root::update_tenant,
+    teardown::{
+        ResourceStatus, TenantDeletionPlan, TenantDeletionReport, delete_tenant,
+        plan_tenant_deletion,
+    },
+};
 use anyhow::Context;
 use axum::{
     Extension, Json,
@@ -14,6 +38,7 @@ use docbox_secrets::SecretManager;
 use docbox_storage::StorageLayerFactory;
 use futures::TryStreamExt;
 use reqwest::Client;
+use serde_json::json;
 use std::sync::Arc;
 
 /// POST /tenant
@@ -67,24 +92,299 @@ pub async fn get(
     Ok(Json(tenant))
 }
 
+/// PATCH /tenant/{env}/{id}
+///
+/// Update the attributes of a tenant, changes to the storage CORS origins
+/// and S3 queue ARN are applied to the tenant storage bucket. Storage is
+/// updated first as its previous configuration cannot be restored, the
+/// changes that were applied are audited even when the update fails
+pub async fn update(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    actor: Actor,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+    Json(req): Json<UpdateTenantRequest>,
+) -> Result<Json<Tenant>, DynHttpError> {
+    if req.name.as_deref().is_some_and(|name| name.trim().is_empty()) {
+        return Err(TenantError::EmptyName.into());
+    }
+
+    if req.name.is_none()
+        && req.event_queue_url.is_none()
+        && req.storage_cors_origins.is_none()
+        && req.storage_s3_queue_arn.is_none()
+    {
+        return Err(TenantError::NoChanges.into());
+    }
+
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    let mut applied = Vec::new();
+
+    let result = async {
+        // Reconcile the storage bucket with the requested configuration
+        let storage = storage_factory.create_storage_layer(&tenant);
+
+        if let Some(origins) = &req.storage_cors_origins {
+            storage
+                .set_bucket_cors_origins(origins.clone())
+                .await
+                .context("failed to update storage bucket cors origins")?;
+            applied.push("storage_cors_origins");
+        }
+
+        // Replaces the notification configuration of the bucket so the
+        // previous queue stops receiving notifications
+        if let Some(s3_queue_arn) = &req.storage_s3_queue_arn {
+            storage
+                .add_bucket_notifications(s3_queue_arn)
+                .await
+                .context("failed to update storage bucket notifications")?;
+            applied.push("storage_s3_queue_arn");
+        }
+
+        if req.name.is_some() || req.event_queue_url.is_some() {
+            update_tenant(
+                &db_provider,
+                &env,
+                tenant_id,
+                req.name.as_deref(),
+                req.event_queue_url
+                    .as_ref()
+                    .map(|event_queue_url| event_queue_url.as_deref()),
+            )
+            .await?;
+            applied.push("tenant");
+        }
+
+        anyhow::Ok(())
+    }
+    .await;
+
+    AuditEvent::create(
+        &metadata.pool,
+        CreateAuditEvent {
+            action: AuditAction::TenantUpdate,
+            env: Some(env.clone()),
+            tenant_id: Some(tenant_id),
+            details: json!({
+                "actor": actor.0,
+                "previous_name": tenant.name,
+                "previous_event_queue_url": tenant.event_queue_url,
+                "name": req.name,
+                "event_queue_url": req.event_queue_url,
+                "storage_cors_origins": req.storage_cors_origins,
+                "storage_s3_queue_arn": req.storage_s3_queue_arn,
+                "applied": applied,
+                "error": result.as_ref().err().map(|error| format!("{error:#}")),
+            }),
+        },
+    )
+    .await
+    .context("failed to record tenant update in audit log")?;
+
+    result?;
+
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    tracing::info!(?tenant, "tenant updated");
+    Ok(Json(tenant))
+}
+
+/// POST /tenant/{env}/{id}/clone
+///
+/// Start a background job cloning the tenant into a new tenant, copying
+/// the database contents and storage objects and rebuilding the search
+/// index of the clone
+// Each extractor is a separate argument of the handler
+#[allow(clippy::too_many_arguments)]
+pub async fn clone(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(search_factory): Extension<Arc<SearchIndexFactory>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(jobs): Extension<Arc<Jobs>>,
+    actor: Actor,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+    Json(req): Json<CloneTenantRequest>,
+) -> Result<(StatusCode, Json<Job>), DynHttpError> {
+    let source =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    // Held until the job completes so the source is not migrated,
+    // rolled back or deleted while it is copied
+    let lock = MigrationLock::acquire(
+        &metadata.pool,
+        LockScope::tenant(&source.env, source.id),
+        &actor.0,
+    )
+    .await?;
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::TenantClone,
+                env: Some(env),
+                tenant_id: Some(tenant_id),
+            },
+            move |ctx| async move {
+                let result = clone_tenant(
+                    &db_provider,
+                    &storage_factory,
+                    &search_factory,
+                    &secrets,
+                    &source,
+                    req.target,
+                    req.keep_on_failure,
+                    &ctx,
+                )
+                .await;
+
+                lock.release().await;
+                let outcome = result?;
+
+                AuditEvent::create(
+                    &metadata.pool,
+                    CreateAuditEvent {
+                        action: AuditAction::TenantClone,
+                        env: Some(source.env.clone()),
+                        tenant_id: Some(source.id),
+                        details: json!({
+                            "actor": actor.0,
+                            "target_env": outcome.tenant.env,
+                            "target_tenant_id": outcome.tenant.id,
+                            "job_id": ctx.id,
+                        }),
+                    },
+                )
+                .await
+                .context("failed to record tenant clone in audit log")?;
+
+                anyhow::Ok(outcome)
+            },
+        )
+        .await?;
+
+    Ok((StatusCode::ACCEPTED, Json(job)))
+}
+
+/// GET /tenant/{env}/{id}/deletion-plan
+///
+/// Get the resources that will be destroyed when deleting the tenant
+pub async fn deletion_plan(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<Json<TenantDeletionPlan>, DynHttpError> {
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    Ok(Json(plan_tenant_deletion(&db_provider, &secrets, &tenant).await))
+}
+
 /// DELETE /tenant/{env}/{id}
 ///
-/// Delete a specific tenant
+/// Delete a specific tenant and the resources backing it, resources
+/// listed in `keep` are left in place. The tenant name must be provided
+/// to confirm the deletion
+// Each extractor is a separate argument of the handler
+#[allow(clippy::too_many_arguments)]
 pub async fn delete(
     Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(search_factory): Extension<Arc<SearchIndexFactory>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(secrets): Extension<Arc<SecretManager>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    actor: Actor,
     Path((env, tenant_id)): Path<(String, Uuid)>,
-) -> Result<StatusCode, DynHttpError> {
-    docbox_management::tenant::delete_tenant::delete_tenant(db_provider.as_ref(), &env, tenant_id)
-        .await
-        .map_err(anyhow::Error::new)?;
-    Ok(StatusCode::OK)
+    Json(req): Json<DeleteTenantRequest>,
+) -> Result<Json<TenantDeletionReport>, DynHttpError> {
+    let tenant =
+        docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+            .await
+            .map_err(anyhow::Error::new)?
+            .context("tenant not found")?;
+
+    if req.confirm_name != tenant.name {
+        return Err(TenantError::NameMismatch.into());
+    }
+
+    let lock = MigrationLock::acquire(
+        &metadata.pool,
+        LockScope::tenant(&tenant.env, tenant.id),
+        &actor.0,
+    )
+    .await?;
+
+    tracing::warn!(?tenant, keep = ?req.keep, "deleting tenant");
+
+    let report = delete_tenant(
+        &db_provider,
+        &storage_factory,
+        &search_factory,
+        &secrets,
+        &tenant,
+        &req.keep,
+    )
+    .await;
+
+    lock.release().await;
+
+    if matches!(report.record, ResourceStatus::Deleted) {
+        set_tenant_tags(&metadata.pool, &env, tenant_id, &[])
+            .await
+            .context("failed to remove tenant tags")?;
+        ArchivedTenant::delete(&metadata.pool, &env, tenant_id)
+            .await
+            .context("failed to remove tenant archive")?;
+    }
+
+    AuditEvent::create(
+        &metadata.pool,
+        CreateAuditEvent {
+            action: AuditAction::TenantDelete,
+            env: Some(env.clone()),
+            tenant_id: Some(tenant_id),
+            details: json!({
+                "actor": actor.0,
+                "name": tenant.name,
+                "keep": req.keep,
+                "report": report,
+            }),
+        },
+    )
+    .await
+    .context("failed to record tenant deletion in audit log")?;
+
+    Ok(Json(report))
 }
 
 /// POST /tenant/{env}/{id}/migrate
 ///
-/// Applies migrations against the tenant
+/// Applies migrations against the tenant, backing up the tenant
+/// first when pre-migration backups are enabled
 pub async fn migrate(
     Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(storage_factory): Extension<Arc<StorageLayerFactory>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Extension(backup_config): Extension<Arc<BackupConfig>>,
+    actor: Actor,
     Path((env, tenant_id)): Path<(String, Uuid)>,
 ) -> Result<StatusCode, DynHttpError> {
     let tenant =
@@ -93,21 +393,100 @@ pub async fn migrate(
             .map_err(anyhow::Error::new)?
             .context("tenant not found")?;
 
-    docbox_management::tenant::migrate_tenant::migrate_tenant(db_provider.as_ref(), &tenant, None)
+    if ArchivedTenant::is_archived(&metadata.pool, &env, tenant_id)
         .await
-        .map_err(anyhow::Error::new)?;
+        .context("failed to check tenant archive")?
+    {
+        return Err(TenantError::Archived.into());
+    }
+
+    let lock = MigrationLock::acquire(
+        &metadata.pool,
+        LockScope::tenant(&tenant.env, tenant.id),
+        &actor.0,
+    )
+    .await?;
+
+    let result = async {
+        if backup_config.before_migrate {
+            backup_tenant(
+                &db_provider,
+                &storage_factory,
+                &metadata.pool,
+                &backup_config,
+                &tenant,
+                BackupReason::PreMigration,
+                None,
+            )
+            .await
+            .context("failed to backup tenant before migrating")?;
+        }
+
+        migrate_tenant_recorded(&db_provider, &metadata.pool, &tenant, None, &actor.0).await
+    }
+    .await;
+
+    lock.release().await;
+    result?;
 
     Ok(StatusCode::OK)
 }
 
+/// GET /tenant/{env}/{id}/tags
+///
+/// Get the tags assigned to a tenant
+pub async fn get_tags(
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+) -> Result<Json<TenantTags>, DynHttpError> {
+    let tags = get_tenant_tags(&metadata.pool, &env, tenant_id)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(Json(TenantTags { tags }))
+}
+
+/// PUT /tenant/{env}/{id}/tags
+///
+/// Replace the tags assigned to a tenant
+pub async fn set_tags(
+    Extension(db_provider): Extension<Arc<DatabaseProvider>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
+    Path((env, tenant_id)): Path<(String, Uuid)>,
+    Json(req): Json<TenantTags>,
+) -> Result<Json<TenantTags>, DynHttpError> {
+    // Ensure the tenant exists before tagging it
+    docbox_management::tenant::get_tenant::get_tenant(db_provider.as_ref(), &env, tenant_id)
+        .await
+        .map_err(anyhow::Error::new)?
+        .context("tenant not found")?;
+
+    set_tenant_tags(&metadata.pool, &env, tenant_id, &req.tags)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let tags = get_tenant_tags(&metadata.pool, &env, tenant_id)
+        .await
+        .map_err(anyhow::Error::new)?;
+    Ok(Json(TenantTags { tags }))
+}
+
 /// ANY /tenant/{env}/{id}/gateway/{*tail}
 ///
 /// Gateway to request resources from the docbox server
 pub async fn docbox_gateway(
     Path((env, tenant_id, tail)): Path<(String, Uuid, String)>,
     Extension(docbox_server): Extension<Arc<DocboxServerUrl>>,
+    Extension(metadata): Extension<Arc<MetadataDatabase>>,
     request: Request,
 ) -> Result<Response, DynHttpError> {
+    // Archived tenants are disabled
+    if ArchivedTenant::is_archived(&metadata.pool, &env, tenant_id)
+        .await
+        .context("failed to check tenant archive")?
+    {
+        return Err(TenantError::Archived.into());
+    }
+
     let (parts, body) = request.into_parts();
 
     // Read the full body
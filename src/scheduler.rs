@@ -0,0 +1,142 @@
+//! Runs scheduled tenant migrations once their maintenance window opens
+
+use crate::{
+    database::DatabaseProvider,
+    jobs::Jobs,
+    locks::{LockScope, MigrationLock},
+    metadata::{
+        MetadataDatabase,
+        jobs::{CreateJob, JobKind},
+        schedules::{ScheduleStatus, ScheduledMigration},
+    },
+    migrations::migrate_fleet,
+    models::root::MigrateTenantsRequest,
+};
+use std::{sync::Arc, time::Duration};
+
+/// Interval between checks for schedules whose window has opened
+const SCHEDULER_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Spawn the background task starting scheduled migrations
+pub fn spawn_scheduler(
+    db_provider: Arc<DatabaseProvider>,
+    metadata: Arc<MetadataDatabase>,
+    jobs: Arc<Jobs>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(SCHEDULER_POLL_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(error) = run_due_schedules(&db_provider, &metadata, &jobs).await {
+                tracing::error!(?error, "failed to run scheduled migrations");
+            }
+        }
+    });
+}
+
+async fn run_due_schedules(
+    db_provider: &Arc<DatabaseProvider>,
+    metadata: &Arc<MetadataDatabase>,
+    jobs: &Arc<Jobs>,
+) -> anyhow::Result<()> {
+    for schedule in ScheduledMigration::mark_missed(&metadata.pool).await? {
+        tracing::warn!(?schedule, "maintenance window closed before migration started");
+    }
+
+    while let Some(schedule) =
+        ScheduledMigration::claim_due(&metadata.pool, jobs.instance_id()).await?
+    {
+        let schedule_id = schedule.id;
+
+        if let Err(error) = start_schedule(db_provider, metadata, jobs, schedule).await {
+            tracing::error!(?error, %schedule_id, "failed to start scheduled migration");
+            ScheduledMigration::set_finished(
+                &metadata.pool,
+                schedule_id,
+                ScheduleStatus::Failed,
+                Some(&format!("{error:#}")),
+            )
+            .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Start the migration job for a claimed schedule, tenants are not
+/// started once the maintenance window closes
+async fn start_schedule(
+    db_provider: &Arc<DatabaseProvider>,
+    metadata: &Arc<MetadataDatabase>,
+    jobs: &Arc<Jobs>,
+    schedule: ScheduledMigration,
+) -> anyhow::Result<()> {
+    tracing::info!(?schedule, "starting scheduled migration");
+
+    let request = MigrateTenantsRequest {
+        env: schedule.env.clone(),
+        tenant_id: schedule.tenant_id,
+        skip_failed: schedule.skip_failed,
+        target_migration_name: schedule.target_migration_name.clone(),
+        deadline: Some(schedule.window_end),
+    };
+
+    let schedule_id = schedule.id;
+    let db_provider = db_provider.clone();
+    let metadata = metadata.clone();
+    let metadata_pool = metadata.pool.clone();
+
+    // Held until the job completes
+    let lock =
+        MigrationLock::acquire(&metadata_pool, LockScope::Fleet, &schedule.created_by).await?;
+
+    let job = jobs
+        .spawn(
+            CreateJob {
+                kind: JobKind::FleetMigration,
+                env: schedule.env.clone(),
+                tenant_id: schedule.tenant_id,
+            },
+            move |ctx| async move {
+                let result = migrate_fleet(
+                    &db_provider,
+                    &metadata.pool,
+                    &request,
+                    &schedule.created_by,
+                    Some(&ctx),
+                )
+                .await;
+
+                lock.release().await;
+
+                let (status, error) = match &result {
+                    Ok(report) if report.failed > 0 => (
+                        ScheduleStatus::Failed,
+                        Some(format!("{} tenants failed to migrate", report.failed)),
+                    ),
+                    Ok(_) => (ScheduleStatus::Completed, None),
+                    Err(error) => (ScheduleStatus::Failed, Some(format!("{error:#}"))),
+                };
+
+                if let Err(error) = ScheduledMigration::set_finished(
+                    &metadata.pool,
+                    schedule.id,
+                    status,
+                    error.as_deref(),
+                )
+                .await
+                {
+                    tracing::error!(?error, schedule_id = %schedule.id, "failed to store schedule outcome");
+                }
+
+                result
+            },
+        )
+        .await?;
+
+    ScheduledMigration::set_job(&metadata_pool, schedule_id, job.id).await?;
+
+    Ok(())
+}
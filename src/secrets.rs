@@ -0,0 +1,54 @@
+//! Helpers for database credentials stored in the secret manager
+
+use anyhow::Context;
+use docbox_secrets::{Secret, SecretManager};
+use serde::{Deserialize, Serialize};
+
+/// Database credentials in the format docbox stores them
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DatabaseSecret {
+    pub username: String,
+    pub password: String,
+}
+
+/// Load the database credentials stored under `name`
+pub async fn get_database_secret(
+    secrets: &SecretManager,
+    name: &str,
+) -> anyhow::Result<DatabaseSecret> {
+    let secret = secrets
+        .get_secret(name)
+        .await
+        .with_context(|| format!("failed to get secret {name}"))?
+        .with_context(|| format!("secret {name} does not exist"))?;
+
+    let value = match secret {
+        Secret::String(value) => value,
+        Secret::Binary(value) => String::from_utf8(value).context("secret is not valid utf8")?,
+    };
+
+    serde_json::from_str(&value).context("secret is not a valid database secret")
+}
+
+/// Store the database credentials under `name`
+pub async fn set_database_secret(
+    secrets: &SecretManager,
+    name: &str,
+    secret: &DatabaseSecret,
+) -> anyhow::Result<()> {
+    let value = serde_json::to_string(secret)?;
+    secrets
+        .set_secret(name, &value)
+        .await
+        .with_context(|| format!("failed to update secret {name}"))?;
+    Ok(())
+}
+
+/// Check if a secret named `name` exists
+pub async fn secret_exists(secrets: &SecretManager, name: &str) -> anyhow::Result<bool> {
+    let secret = secrets
+        .get_secret(name)
+        .await
+        .with_context(|| format!("failed to get secret {name}"))?;
+    Ok(secret.is_some())
+}
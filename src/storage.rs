@@ -0,0 +1,308 @@
+//! Helpers for accessing storage outside of a tenant bucket
+
+use crate::backup::archive::{SpoolSender, SpooledFile};
+use anyhow::Context;
+use aws_sdk_s3::{
+    config::Credentials,
+    types::{Delete, ObjectIdentifier},
+};
+use docbox_storage::{
+    StorageLayerFactory, TenantStorageLayer,
+    s3::{S3Endpoint, S3StorageLayerFactoryConfig},
+};
+use futures::TryStreamExt;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use tokio::sync::OnceCell;
+
+/// Maximum number of objects S3 will list or delete in a single request
+const MAX_OBJECTS_PER_REQUEST: i32 = 1000;
+
+/// Version of an object within a bucket, buckets that have never had
+/// versioning enabled have a single "null" version of each object
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectVersion {
+    pub key: String,
+    pub version_id: Option<String>,
+}
+
+/// Create a storage layer for a bucket that is not owned by a tenant
+pub fn bucket_storage_layer(factory: &StorageLayerFactory, bucket: &str) -> TenantStorageLayer {
+    match factory {
+        StorageLayerFactory::S3(factory) => {
+            TenantStorageLayer::S3(factory.create_storage_layer(bucket.to_string()))
+        }
+    }
+}
+
+/// Download the file at `key` into a temporary file without reading it
+/// into memory
+pub async fn download_spooled(layer: &TenantStorageLayer, key: &str) -> anyhow::Result<SpooledFile> {
+    let mut stream = layer.get_file(key).await?;
+    let mut sender = SpoolSender::spawn();
+
+    while let Some(chunk) = stream.try_next().await? {
+        sender.send(chunk).await?;
+    }
+
+    sender.finish().await
+}
+
+/// Upload the contents of a spooled file to `key` without reading it into
+/// memory, the file is streamed to a presigned upload URL
+pub async fn upload_spooled(
+    layer: &TenantStorageLayer,
+    key: &str,
+    content_type: &str,
+    spooled: SpooledFile,
+) -> anyhow::Result<()> {
+    let (request, _) = layer.create_presigned(key, spooled.size as i64).await?;
+
+    let method = reqwest::Method::from_bytes(request.method().as_bytes())
+        .context("invalid presigned upload method")?;
+    let mut builder = reqwest::Client::new().request(method, request.uri());
+    for (name, value) in request.headers() {
+        builder = builder.header(name, value);
+    }
+
+    builder
+        .header(CONTENT_TYPE, content_type)
+        .header(CONTENT_LENGTH, spooled.size)
+        .body(tokio::fs::File::from_std(spooled.file))
+        .send()
+        .await
+        .and_then(|response| response.error_for_status())
+        .with_context(|| format!("failed to upload {key}"))?;
+
+    Ok(())
+}
+
+/// S3 client for bucket operations the storage layer does not provide,
+/// configured from the same environment as the storage layer
+async fn s3_client() -> anyhow::Result<&'static aws_sdk_s3::Client> {
+    static CLIENT: OnceCell<aws_sdk_s3::Client> = OnceCell::const_new();
+
+    CLIENT
+        .get_or_try_init(|| async {
+            let aws_config = docbox_core::aws::aws_config().await;
+            let config = S3StorageLayerFactoryConfig::from_env()?;
+
+            let client = match config.endpoint {
+                S3Endpoint::Aws => aws_sdk_s3::Client::new(&aws_config),
+                S3Endpoint::Custom {
+                    endpoint,
+                    access_key_id,
+                    access_key_secret,
+                } => {
+                    let credentials = Credentials::new(
+                        access_key_id,
+                        access_key_secret,
+                        None,
+                        None,
+                        "docbox_key_provider",
+                    );
+                    let config = aws_sdk_s3::config::Builder::from(&aws_config)
+                        .force_path_style(true)
+                        .endpoint_url(endpoint)
+                        .credentials_provider(credentials)
+                        .build();
+                    aws_sdk_s3::Client::from_conf(config)
+                }
+            };
+
+            anyhow::Ok(client)
+        })
+        .await
+}
+
+/// Delete every object in `bucket` including previous versions and delete
+/// markers, S3 refuses to delete a bucket that is not empty. Returns the
+/// number of object versions deleted
+pub async fn empty_bucket(bucket: &str) -> anyhow::Result<u64> {
+    let client = s3_client().await?;
+
+    drain_bucket(
+        || list_object_versions(client, bucket),
+        |versions| delete_object_versions(client, bucket, versions),
+    )
+    .await
+}
+
+/// Delete the object versions returned by `list` until it returns none.
+/// The first page is listed each time so versions that were deleted never
+/// have to be skipped over
+async fn drain_bucket<L, LFut, D, DFut>(mut list: L, mut delete: D) -> anyhow::Result<u64>
+where
+    L: FnMut() -> LFut,
+    LFut: Future<Output = anyhow::Result<Vec<ObjectVersion>>>,
+    D: FnMut(Vec<ObjectVersion>) -> DFut,
+    DFut: Future<Output = anyhow::Result<()>>,
+{
+    let mut deleted = 0;
+
+    loop {
+        let versions = list().await?;
+        if versions.is_empty() {
+            return Ok(deleted);
+        }
+
+        deleted += versions.len() as u64;
+        delete(versions).await?;
+    }
+}
+
+/// List the first page of object versions and delete markers in `bucket`
+async fn list_object_versions(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+) -> anyhow::Result<Vec<ObjectVersion>> {
+    let output = client
+        .list_object_versions()
+        .bucket(bucket)
+        .max_keys(MAX_OBJECTS_PER_REQUEST)
+        .send()
+        .await
+        .context("failed to list object versions")?;
+
+    let versions = output
+        .versions()
+        .iter()
+        .map(|version| (version.key(), version.version_id()))
+        .chain(
+            output
+                .delete_markers()
+                .iter()
+                .map(|marker| (marker.key(), marker.version_id())),
+        )
+        .filter_map(|(key, version_id)| {
+            Some(ObjectVersion {
+                key: key?.to_string(),
+                version_id: version_id.map(str::to_string),
+            })
+        })
+        .collect();
+
+    Ok(versions)
+}
+
+/// Permanently delete `versions` from `bucket`
+async fn delete_object_versions(
+    client: &aws_sdk_s3::Client,
+    bucket: &str,
+    versions: Vec<ObjectVersion>,
+) -> anyhow::Result<()> {
+    let objects = versions
+        .into_iter()
+        .map(|version| {
+            ObjectIdentifier::builder()
+                .key(version.key)
+                .set_version_id(version.version_id)
+                .build()
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .context("invalid object identifier")?;
+
+    let delete = Delete::builder()
+        .set_objects(Some(objects))
+        .quiet(true)
+        .build()
+        .context("invalid delete request")?;
+
+    let output = client
+        .delete_objects()
+        .bucket(bucket)
+        .delete(delete)
+        .send()
+        .await
+        .context("failed to delete objects")?;
+
+    // Only failed deletions are reported in quiet mode
+    if let Some(error) = output.errors().first() {
+        anyhow::bail!(
+            "failed to delete {}: {}",
+            error.key().unwrap_or_default(),
+            error.message().unwrap_or_default()
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    fn list_first_page(
+        bucket: &Mutex<Vec<ObjectVersion>>,
+    ) -> impl Future<Output = anyhow::Result<Vec<ObjectVersion>>> + use<> {
+        let versions = bucket
+            .lock()
+            .unwrap()
+            .iter()
+            .take(MAX_OBJECTS_PER_REQUEST as usize)
+            .cloned()
+            .collect();
+        async move { Ok(versions) }
+    }
+
+    #[tokio::test]
+    async fn test_drain_bucket_non_empty() {
+        // More versions than a single listing returns, with several
+        // versions of each key
+        let bucket = Mutex::new(
+            (0..2500)
+                .map(|index| ObjectVersion {
+                    key: format!("file-{}", index % 1000),
+                    version_id: Some(index.to_string()),
+                })
+                .collect::<Vec<_>>(),
+        );
+
+        let deleted = drain_bucket(
+            || list_first_page(&bucket),
+            |versions| {
+                bucket
+                    .lock()
+                    .unwrap()
+                    .retain(|version| !versions.contains(version));
+                async { Ok(()) }
+            },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(deleted, 2500);
+        assert!(bucket.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_drain_bucket_empty() {
+        let bucket = Mutex::new(Vec::new());
+
+        let deleted = drain_bucket(
+            || list_first_page(&bucket),
+            |_| async { panic!("nothing to delete") },
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(deleted, 0);
+    }
+
+    #[tokio::test]
+    async fn test_drain_bucket_delete_failure() {
+        let bucket = Mutex::new(vec![ObjectVersion {
+            key: "locked".to_string(),
+            version_id: None,
+        }]);
+
+        let result = drain_bucket(
+            || list_first_page(&bucket),
+            |_| async { Err(anyhow::anyhow!("object is locked")) },
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(bucket.lock().unwrap().len(), 1);
+    }
+}
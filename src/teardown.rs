@@ -0,0 +1,756 @@
+//! Removal of the resources backing tenants and the root database
+//!
+//! Resources are removed independently so a failure removing one
+//! resource does not prevent the others from being removed, the outcome
+//! for each resource is reported to the caller. Resources that no longer
+//! exist are reported as deleted so a failed teardown can be retried
+
+use crate::{
+    database::{DatabaseProvider, MAINTENANCE_DATABASE_NAME, drop_database, quote_ident},
+    locks::{LockScope, MigrationLock},
+    metadata::{
+        archives::ArchivedTenant, backups::TenantBackup,
+        migration_applications::TenantMigrationApplication, schedules::ScheduledMigration,
+        tags::set_tenant_tags,
+    },
+    secrets::get_database_secret,
+    storage::empty_bucket,
+};
+use anyhow::Context;
+use docbox_database::{DatabasePoolCache, ROOT_DATABASE_NAME, models::tenant::Tenant};
+use docbox_management::database::DatabaseProvider as _;
+use docbox_search::SearchIndexFactory;
+use docbox_secrets::SecretManager;
+use docbox_storage::StorageLayerFactory;
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+/// Resource backing a tenant
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TenantResource {
+    SearchIndex,
+    StorageBucket,
+    Database,
+    DatabaseRole,
+    DatabaseSecret,
+}
+
+impl TenantResource {
+    /// Resources in the order they are removed, the database role is
+    /// found through the database grants or the secret so must be removed
+    /// before them
+    pub const ALL: [TenantResource; 5] = [
+        TenantResource::SearchIndex,
+        TenantResource::StorageBucket,
+        TenantResource::Database,
+        TenantResource::DatabaseRole,
+        TenantResource::DatabaseSecret,
+    ];
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResourceOutcome<R> {
+    pub resource: R,
+    /// Name of the resource
+    pub name: String,
+    #[serde(flatten)]
+    pub status: ResourceStatus,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ResourceStatus {
+    Deleted,
+    Kept,
+    Failed { error: String },
+}
+
+impl From<anyhow::Result<()>> for ResourceStatus {
+    fn from(result: anyhow::Result<()>) -> Self {
+        match result {
+            Ok(()) => ResourceStatus::Deleted,
+            Err(error) => ResourceStatus::Failed {
+                error: format!("{error:#}"),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct TenantTeardown {
+    pub env: String,
+    pub tenant_id: Uuid,
+    pub name: String,
+    pub resources: Vec<ResourceOutcome<TenantResource>>,
+}
+
+impl TenantTeardown {
+    /// Whether any of the resources failed to delete
+    pub fn failed(&self) -> bool {
+        has_failed(&self.resources)
+    }
+}
+
+fn has_failed(resources: &[ResourceOutcome<TenantResource>]) -> bool {
+    resources
+        .iter()
+        .any(|outcome| matches!(outcome.status, ResourceStatus::Failed { .. }))
+}
+
+/// Resource that will be destroyed when deleting a tenant
+#[derive(Debug, Serialize)]
+pub struct PlannedResource {
+    pub resource: TenantResource,
+    pub name: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TenantDeletionPlan {
+    pub env: String,
+    pub tenant_id: Uuid,
+    /// Name of the tenant, must be provided to confirm the deletion
+    pub name: String,
+    pub resources: Vec<PlannedResource>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TenantDeletionReport {
+    #[serde(flatten)]
+    pub teardown: TenantTeardown,
+    /// Outcome of removing the tenant from the root database
+    pub record: ResourceStatus,
+}
+
+/// Resource backing the root database
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RootResource {
+    Database,
+    DatabaseRole,
+    DatabaseSecret,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RootTeardownReport {
+    pub tenants: Vec<TenantTeardown>,
+    pub root: Vec<ResourceOutcome<RootResource>>,
+    /// Outcome of removing the manager metadata of the removed tenants
+    pub metadata: ResourceStatus,
+}
+
+/// Name of `resource` for `tenant`
+pub fn tenant_resource_name(tenant: &Tenant, resource: TenantResource) -> String {
+    match resource {
+        TenantResource::SearchIndex => tenant.os_index_name.clone(),
+        TenantResource::StorageBucket => tenant.s3_name.clone(),
+        TenantResource::Database => tenant.db_name.clone(),
+        TenantResource::DatabaseRole => format!("role from secret {}", tenant.db_secret_name),
+        TenantResource::DatabaseSecret => tenant.db_secret_name.clone(),
+    }
+}
+
+/// Name of the role granted access to connect to the database `db_name`,
+/// `None` when there is no single role other than the owner
+async fn database_connect_role(db: &PgPool, db_name: &str) -> sqlx::Result<Option<String>> {
+    let roles: Vec<String> = sqlx::query_scalar(
+        "SELECT r.rolname::text
+        FROM pg_database d
+        CROSS JOIN LATERAL aclexplode(d.datacl) acl
+        JOIN pg_roles r ON r.oid = acl.grantee
+        WHERE d.datname = $1 AND acl.privilege_type = 'CONNECT'
+            AND acl.grantee <> d.datdba AND NOT r.rolsuper",
+    )
+    .bind(db_name)
+    .fetch_all(db)
+    .await?;
+
+    Ok(match roles.as_slice() {
+        [role] => Some(role.clone()),
+        _ => None,
+    })
+}
+
+/// Drop the role named `role_name` if it exists
+async fn drop_role(db: &PgPool, role_name: &str) -> sqlx::Result<()> {
+    sqlx::query(&format!("DROP ROLE IF EXISTS {}", quote_ident(role_name)))
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Remove a single `resource` backing `tenant`, `role_name` is the
+/// tenant database role found by [resolve_role_name]. Resources that
+/// have already been removed are treated as deleted
+async fn delete_tenant_resource(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    search_factory: &SearchIndexFactory,
+    secrets: &SecretManager,
+    tenant: &Tenant,
+    role_name: &anyhow::Result<Option<String>>,
+    resource: TenantResource,
+) -> anyhow::Result<()> {
+    match resource {
+        TenantResource::SearchIndex => {
+            let index = search_factory.create_search_index(tenant);
+            if !index
+                .index_exists()
+                .await
+                .context("failed to check search index")?
+            {
+                return Ok(());
+            }
+
+            index
+                .delete_index()
+                .await
+                .context("failed to delete search index")?;
+        }
+        TenantResource::StorageBucket => {
+            let storage = storage_factory.create_storage_layer(tenant);
+            if !storage
+                .bucket_exists()
+                .await
+                .context("failed to check storage bucket")?
+            {
+                return Ok(());
+            }
+
+            // Buckets must be emptied of every object version first
+            empty_bucket(&tenant.s3_name)
+                .await
+                .context("failed to empty storage bucket")?;
+            storage
+                .delete_bucket()
+                .await
+                .context("failed to delete storage bucket")?;
+        }
+        TenantResource::Database => {
+            db_provider.close_pool(&tenant.db_name).await;
+
+            let db = db_provider
+                .connect(MAINTENANCE_DATABASE_NAME)
+                .await
+                .context("failed to connect to maintenance database")?;
+            drop_database(&db, &tenant.db_name)
+                .await
+                .context("failed to drop tenant database")?;
+        }
+        TenantResource::DatabaseRole => {
+            let role_name = match role_name {
+                Ok(Some(role_name)) => role_name,
+                // Neither the database nor the secret remain, the secret is
+                // only removed once the role has been removed
+                Ok(None) => return Ok(()),
+                Err(error) => anyhow::bail!("failed to find tenant role: {error:#}"),
+            };
+
+            let db = db_provider
+                .connect(MAINTENANCE_DATABASE_NAME)
+                .await
+                .context("failed to connect to maintenance database")?;
+            drop_role(&db, role_name)
+                .await
+                .context("failed to drop tenant role")?;
+        }
+        TenantResource::DatabaseSecret => {
+            if !secrets
+                .has_secret(&tenant.db_secret_name)
+                .await
+                .context("failed to check tenant secret")?
+            {
+                return Ok(());
+            }
+
+            secrets
+                .delete_secret(&tenant.db_secret_name, true)
+                .await
+                .context("failed to delete tenant secret")?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Remove the resources backing `tenant` other than those in `keep`,
+/// the tenant itself is left in the root database
+pub async fn teardown_tenant(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    search_factory: &SearchIndexFactory,
+    secrets: &SecretManager,
+    tenant: &Tenant,
+    keep: &[TenantResource],
+) -> TenantTeardown {
+    // Resolved before the database and secret it is found through are removed
+    let role_name = &resolve_role_name(db_provider, secrets, tenant).await;
+
+    let resources = teardown_resources(
+        keep,
+        |resource| match (resource, role_name) {
+            (TenantResource::DatabaseRole, Ok(Some(role_name))) => role_name.clone(),
+            (resource, _) => tenant_resource_name(tenant, resource),
+        },
+        |resource| async move {
+            let result = delete_tenant_resource(
+                db_provider,
+                storage_factory,
+                search_factory,
+                secrets,
+                tenant,
+                role_name,
+                resource,
+            )
+            .await;
+
+            if let Err(error) = &result {
+                tracing::error!(
+                    ?error,
+                    ?tenant,
+                    ?resource,
+                    "failed to delete tenant resource"
+                );
+            }
+
+            result
+        },
+    )
+    .await;
+
+    TenantTeardown {
+        env: tenant.env.clone(),
+        tenant_id: tenant.id,
+        name: tenant.name.clone(),
+        resources,
+    }
+}
+
+/// Remove every resource other than those in `keep` using `delete`. The
+/// secret is kept when the role fails to delete so the role can still be
+/// found when the teardown is retried
+async fn teardown_resources<F, Fut>(
+    keep: &[TenantResource],
+    name: impl Fn(TenantResource) -> String,
+    mut delete: F,
+) -> Vec<ResourceOutcome<TenantResource>>
+where
+    F: FnMut(TenantResource) -> Fut,
+    Fut: Future<Output = anyhow::Result<()>>,
+{
+    let mut resources: Vec<ResourceOutcome<TenantResource>> =
+        Vec::with_capacity(TenantResource::ALL.len());
+
+    for resource in TenantResource::ALL {
+        let name = name(resource);
+
+        if keep.contains(&resource) {
+            resources.push(ResourceOutcome {
+                resource,
+                name,
+                status: ResourceStatus::Kept,
+            });
+            continue;
+        }
+
+        let role_failed = resources.iter().any(|outcome| {
+            outcome.resource == TenantResource::DatabaseRole
+                && matches!(outcome.status, ResourceStatus::Failed { .. })
+        });
+
+        let status = if resource == TenantResource::DatabaseSecret && role_failed {
+            ResourceStatus::Failed {
+                error: "secret was kept as the database role failed to delete".to_string(),
+            }
+        } else {
+            delete(resource).await.into()
+        };
+
+        resources.push(ResourceOutcome {
+            resource,
+            name,
+            status,
+        });
+    }
+
+    resources
+}
+
+/// Name of the tenant database role, found through the grants on the
+/// tenant database falling back to the tenant secret. `None` when neither
+/// remain as the role has already been removed
+async fn resolve_role_name(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    tenant: &Tenant,
+) -> anyhow::Result<Option<String>> {
+    let db = db_provider
+        .connect(MAINTENANCE_DATABASE_NAME)
+        .await
+        .context("failed to connect to maintenance database")?;
+
+    if let Some(role_name) = database_connect_role(&db, &tenant.db_name)
+        .await
+        .context("failed to get tenant database grants")?
+    {
+        return Ok(Some(role_name));
+    }
+
+    if !secrets
+        .has_secret(&tenant.db_secret_name)
+        .await
+        .context("failed to check tenant secret")?
+    {
+        return Ok(None);
+    }
+
+    let secret = get_database_secret(secrets, &tenant.db_secret_name).await?;
+    Ok(Some(secret.username))
+}
+
+/// Plan deleting `tenant`, listing every resource that will be destroyed
+pub async fn plan_tenant_deletion(
+    db_provider: &DatabaseProvider,
+    secrets: &SecretManager,
+    tenant: &Tenant,
+) -> TenantDeletionPlan {
+    let role_name = resolve_role_name(db_provider, secrets, tenant).await;
+
+    let resources = TenantResource::ALL
+        .into_iter()
+        .map(|resource| PlannedResource {
+            resource,
+            name: match (resource, &role_name) {
+                (TenantResource::DatabaseRole, Ok(Some(role_name))) => role_name.clone(),
+                (resource, _) => tenant_resource_name(tenant, resource),
+            },
+        })
+        .collect();
+
+    TenantDeletionPlan {
+        env: tenant.env.clone(),
+        tenant_id: tenant.id,
+        name: tenant.name.clone(),
+        resources,
+    }
+}
+
+/// Delete `tenant` removing every resource backing it other than those
+/// in `keep`. The tenant is only removed from the root database once all
+/// other resources are removed so failed resources can be retried
+pub async fn delete_tenant(
+    db_provider: &DatabaseProvider,
+    storage_factory: &StorageLayerFactory,
+    search_factory: &SearchIndexFactory,
+    secrets: &SecretManager,
+    tenant: &Tenant,
+    keep: &[TenantResource],
+) -> TenantDeletionReport {
+    let teardown = teardown_tenant(
+        db_provider,
+        storage_factory,
+        search_factory,
+        secrets,
+        tenant,
+        keep,
+    )
+    .await;
+
+    let record = if teardown.failed() {
+        ResourceStatus::Failed {
+            error: "tenant was kept as other resources failed to delete".to_string(),
+        }
+    } else {
+        delete_tenant_record(db_provider, tenant).await.into()
+    };
+
+    TenantDeletionReport { teardown, record }
+}
+
+/// Remove `tenant` from the root database
+async fn delete_tenant_record(db_provider: &DatabaseProvider, tenant: &Tenant) -> anyhow::Result<()> {
+    let db = db_provider
+        .connect(ROOT_DATABASE_NAME)
+        .await
+        .context("failed to connect to root database")?;
+    tenant
+        .clone()
+        .delete(&db)
+        .await
+        .context("failed to delete tenant")?;
+    Ok(())
+}
+
+/// Remove the manager metadata belonging to a removed tenant
+async fn delete_tenant_metadata(metadata: &PgPool, tenant: &Tenant) -> anyhow::Result<()> {
+    let (env, tenant_id) = (tenant.env.as_str(), tenant.id);
+
+    set_tenant_tags(metadata, env, tenant_id, &[])
+        .await
+        .context("failed to remove tenant tags")?;
+    ArchivedTenant::delete(metadata, env, tenant_id)
+        .await
+        .context("failed to remove tenant archive")?;
+    TenantBackup::delete_by_tenant(metadata, env, tenant_id)
+        .await
+        .context("failed to remove tenant backups")?;
+    TenantMigrationApplication::delete_by_tenant(metadata, env, tenant_id)
+        .await
+        .context("failed to remove tenant migration history")?;
+    MigrationLock::delete_expired(metadata, &LockScope::tenant(env, tenant_id))
+        .await
+        .context("failed to remove tenant lock")?;
+
+    Ok(())
+}
+
+/// Remove the resources of every tenant then drop the root database,
+/// its role and the root secret, leaving the server uninitialized. The
+/// root is kept when any tenant resource fails to delete unless `force`
+/// is set, otherwise the failed resources could no longer be found
+// Takes every service holding tenant resources along with the root details
+#[allow(clippy::too_many_arguments)]
+pub async fn teardown_root(
+    db_provider: &DatabaseProvider,
+    db_cache: &DatabasePoolCache,
+    storage_factory: &StorageLayerFactory,
+    search_factory: &SearchIndexFactory,
+    secrets: &SecretManager,
+    metadata: &PgPool,
+    root_secret_name: &str,
+    force: bool,
+) -> anyhow::Result<RootTeardownReport> {
+    let tenants = docbox_management::tenant::get_tenants::get_tenants(db_provider)
+        .await
+        .map_err(anyhow::Error::new)?;
+
+    let mut tenant_outcomes = Vec::with_capacity(tenants.len());
+    for tenant in &tenants {
+        tracing::warn!(?tenant, "tearing down tenant");
+        tenant_outcomes.push(
+            teardown_tenant(
+                db_provider,
+                storage_factory,
+                search_factory,
+                secrets,
+                tenant,
+                &[],
+            )
+            .await,
+        );
+    }
+
+    if !force && tenant_outcomes.iter().any(TenantTeardown::failed) {
+        tracing::error!("tenant resources failed to delete, keeping root database");
+
+        let root = [
+            (RootResource::Database, ROOT_DATABASE_NAME.to_string()),
+            (
+                RootResource::DatabaseRole,
+                format!("role from secret {root_secret_name}"),
+            ),
+            (RootResource::DatabaseSecret, root_secret_name.to_string()),
+        ]
+        .into_iter()
+        .map(|(resource, name)| ResourceOutcome {
+            resource,
+            name,
+            status: ResourceStatus::Kept,
+        })
+        .collect();
+
+        return Ok(RootTeardownReport {
+            tenants: tenant_outcomes,
+            root,
+            metadata: ResourceStatus::Kept,
+        });
+    }
+
+    // Root role must be read before the secret holding it is removed
+    let root_secret = get_database_secret(secrets, root_secret_name).await;
+
+    db_cache.close_all().await;
+    db_provider.close_pool(ROOT_DATABASE_NAME).await;
+
+    let db = db_provider
+        .connect(MAINTENANCE_DATABASE_NAME)
+        .await
+        .context("failed to connect to maintenance database")?;
+
+    let mut root = Vec::with_capacity(3);
+
+    let result = drop_database(&db, ROOT_DATABASE_NAME)
+        .await
+        .context("failed to drop root database");
+    root.push(ResourceOutcome {
+        resource: RootResource::Database,
+        name: ROOT_DATABASE_NAME.to_string(),
+        status: result.into(),
+    });
+
+    let (name, result) = match &root_secret {
+        Ok(secret) => (
+            secret.username.clone(),
+            drop_role(&db, &secret.username)
+                .await
+                .context("failed to drop root role"),
+        ),
+        Err(error) => (
+            format!("role from secret {root_secret_name}"),
+            Err(anyhow::anyhow!("failed to get root role: {error:#}")),
+        ),
+    };
+    root.push(ResourceOutcome {
+        resource: RootResource::DatabaseRole,
+        name,
+        status: result.into(),
+    });
+
+    let result = secrets
+        .delete_secret(root_secret_name, true)
+        .await
+        .context("failed to delete root secret");
+    root.push(ResourceOutcome {
+        resource: RootResource::DatabaseSecret,
+        name: root_secret_name.to_string(),
+        status: result.into(),
+    });
+
+    // Tenants can no longer be found once the root database is dropped
+    let result = async {
+        for tenant in &tenants {
+            delete_tenant_metadata(metadata, tenant).await?;
+        }
+
+        ScheduledMigration::cancel_all(metadata)
+            .await
+            .context("failed to cancel scheduled migrations")?;
+
+        anyhow::Ok(())
+    }
+    .await;
+
+    if let Err(error) = &result {
+        tracing::error!(?error, "failed to remove tenant metadata");
+    }
+
+    Ok(RootTeardownReport {
+        tenants: tenant_outcomes,
+        root,
+        metadata: result.into(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(
+        resources: &[ResourceOutcome<TenantResource>],
+        resource: TenantResource,
+    ) -> &ResourceStatus {
+        &resources
+            .iter()
+            .find(|outcome| outcome.resource == resource)
+            .unwrap()
+            .status
+    }
+
+    #[tokio::test]
+    async fn test_teardown_keeps_resources() {
+        let mut deleted = Vec::new();
+        let resources = teardown_resources(
+            &[TenantResource::StorageBucket],
+            |resource| format!("{resource:?}"),
+            |resource| {
+                deleted.push(resource);
+                async { Ok(()) }
+            },
+        )
+        .await;
+
+        assert!(!deleted.contains(&TenantResource::StorageBucket));
+        assert_eq!(deleted.len(), TenantResource::ALL.len() - 1);
+        assert!(matches!(
+            status(&resources, TenantResource::StorageBucket),
+            ResourceStatus::Kept
+        ));
+        assert!(matches!(
+            status(&resources, TenantResource::Database),
+            ResourceStatus::Deleted
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_teardown_keeps_secret_when_role_fails() {
+        let mut deleted = Vec::new();
+        let resources = teardown_resources(
+            &[],
+            |resource| format!("{resource:?}"),
+            |resource| {
+                deleted.push(resource);
+                async move {
+                    match resource {
+                        TenantResource::DatabaseRole => Err(anyhow::anyhow!("role in use")),
+                        _ => Ok(()),
+                    }
+                }
+            },
+        )
+        .await;
+
+        assert!(!deleted.contains(&TenantResource::DatabaseSecret));
+        assert!(matches!(
+            status(&resources, TenantResource::DatabaseRole),
+            ResourceStatus::Failed { .. }
+        ));
+        assert!(matches!(
+            status(&resources, TenantResource::DatabaseSecret),
+            ResourceStatus::Failed { .. }
+        ));
+    }
+
+    /// Purging an archived tenant that previously failed part way through
+    /// must be able to complete once the failing resource can be removed
+    #[tokio::test]
+    async fn test_teardown_retry_after_partial_failure() {
+        async fn attempt(
+            existing: &mut Vec<TenantResource>,
+            role_in_use: bool,
+        ) -> Vec<ResourceOutcome<TenantResource>> {
+            teardown_resources(
+                &[],
+                |resource| format!("{resource:?}"),
+                |resource| {
+                    // Resources removed by an earlier attempt are deleted
+                    let result = if resource == TenantResource::DatabaseRole && role_in_use {
+                        Err(anyhow::anyhow!("role in use"))
+                    } else {
+                        existing.retain(|existing| *existing != resource);
+                        Ok(())
+                    };
+                    async move { result }
+                },
+            )
+            .await
+        }
+
+        let mut existing: Vec<TenantResource> = TenantResource::ALL.to_vec();
+
+        let first = attempt(&mut existing, true).await;
+        assert!(has_failed(&first));
+        assert!(matches!(
+            status(&first, TenantResource::Database),
+            ResourceStatus::Deleted
+        ));
+        assert_eq!(
+            existing,
+            [TenantResource::DatabaseRole, TenantResource::DatabaseSecret]
+        );
+
+        let second = attempt(&mut existing, false).await;
+        assert!(!has_failed(&second));
+        assert!(existing.is_empty());
+    }
+}